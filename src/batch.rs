@@ -0,0 +1,37 @@
+use crate::{ByteStr, ByteString};
+
+/// A group of puts and deletes that `ActionKv::write_batch` appends to the
+/// log as one checksummed frame, so after a crash either all of them are
+/// applied or none are.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    pub(crate) ops: Vec<(ByteString, Option<ByteString>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> &mut Self {
+        self.ops.push((key.to_vec(), Some(value.to_vec())));
+        self
+    }
+
+    pub fn delete(&mut self, key: &ByteStr) -> &mut Self {
+        self.ops.push((key.to_vec(), None));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+}
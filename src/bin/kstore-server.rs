@@ -0,0 +1,123 @@
+use std::{env, net::TcpListener, path::Path, process};
+#[cfg(unix)]
+use std::{fs, io, os::unix::net::UnixListener};
+
+use kstore::{server::{Protocol, Server}, ActionKvOptions, Durability, Error, IndexKind, SharedKv};
+
+const USAGE: &str = "
+Usage:
+    kstore-server [OPTIONS] FILE
+
+Serves the store at FILE, which is created if missing, to kstore clients.
+FILE may also be the directory of a segmented store.
+
+Options:
+    --listen ADDR    TCP address to listen on (default 127.0.0.1:7070,
+                     127.0.0.1:6379 with --resp or 127.0.0.1:8080 with --http)
+    --unix PATH      listen on a Unix socket at PATH instead
+    --resp           speak the Redis protocol, for redis-cli and Redis clients
+    --http           serve an HTTP/JSON API
+    --sync           sync the log after every write
+";
+
+const DEFAULT_ADDR: &str = "127.0.0.1:7070";
+const DEFAULT_RESP_ADDR: &str = "127.0.0.1:6379";
+const DEFAULT_HTTP_ADDR: &str = "127.0.0.1:8080";
+
+enum Listen {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        match err {
+            Failure::Usage(msg) => {
+                eprintln!("error: {}\n{}", msg, USAGE);
+                process::exit(2);
+            },
+            Failure::Store(err) => {
+                eprintln!("error: {}", err);
+                process::exit(4);
+            },
+        }
+    }
+}
+
+enum Failure {
+    Usage(String),
+    Store(Error),
+}
+
+impl<E: Into<Error>> From<E> for Failure {
+    fn from(err: E) -> Self {
+        Failure::Store(err.into())
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Failure> {
+    let mut listen = None;
+    let mut protocol = Protocol::Native;
+    let mut durability = Durability::default();
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => {
+                let addr = args.next().ok_or_else(|| Failure::Usage("--listen expects an address".into()))?;
+                listen = Some(Listen::Tcp(addr.clone()));
+            },
+            #[cfg(unix)]
+            "--unix" => {
+                let socket = args.next().ok_or_else(|| Failure::Usage("--unix expects a path".into()))?;
+                listen = Some(Listen::Unix(socket.clone()));
+            },
+            "--resp" => protocol = Protocol::Resp,
+            "--http" => protocol = Protocol::Http,
+            "--sync" => durability = Durability::SyncEveryWrite,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            },
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.as_str()),
+            _ => return Err(Failure::Usage(format!("unexpected argument {:?}", arg))),
+        }
+    }
+    let path = path.ok_or_else(|| Failure::Usage("expected FILE".into()))?;
+
+    let store = ActionKvOptions::new()
+        .create_if_missing(true)
+        .durability(durability)
+        // every scan page is a range query
+        .index_kind(IndexKind::Ordered)
+        .open(Path::new(path))?;
+    let server = Server::new(SharedKv::new(store)).protocol(protocol);
+
+    let default_addr = match protocol {
+        Protocol::Native => DEFAULT_ADDR,
+        Protocol::Resp => DEFAULT_RESP_ADDR,
+        Protocol::Http => DEFAULT_HTTP_ADDR,
+    };
+    match listen.unwrap_or_else(|| Listen::Tcp(default_addr.to_string())) {
+        Listen::Tcp(addr) => {
+            let listener = TcpListener::bind(&addr)?;
+            eprintln!("serving {} on {}", path, listener.local_addr()?);
+            server.serve(listener)?;
+        },
+        #[cfg(unix)]
+        Listen::Unix(socket) => {
+            // a socket file left behind by an earlier run would make bind fail
+            match fs::remove_file(&socket) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {},
+            }
+            let listener = UnixListener::bind(&socket)?;
+            eprintln!("serving {} on {}", path, socket);
+            server.serve_unix(listener)?;
+        },
+    }
+    Ok(())
+}
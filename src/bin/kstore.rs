@@ -0,0 +1,272 @@
+use std::{env, io::{self, Read, Write}, path::Path, process, time::UNIX_EPOCH};
+
+use kstore::{ActionKv, ActionKvOptions, Error};
+
+const USAGE: &str = "
+Usage:
+    kstore [OPTIONS] FILE get KEY
+    kstore [OPTIONS] FILE insert KEY [VALUE]
+    kstore [OPTIONS] FILE update KEY [VALUE]
+    kstore [OPTIONS] FILE delete KEY
+    kstore [OPTIONS] FILE list [PREFIX]
+    kstore [OPTIONS] FILE find KEY
+    kstore [OPTIONS] FILE stats
+    kstore [OPTIONS] FILE migrate [DEST]
+
+FILE may also be the directory of a segmented store. VALUE is read from
+stdin when omitted. migrate upgrades FILE to the current
+format in place, or writes the upgraded copy to DEST.
+
+Options:
+    --format raw|hex|utf8    how keys and values are printed (default utf8)
+    --hex-input              KEY, VALUE and PREFIX arguments are hex encoded
+
+Exit status: 0 on success, 1 when the key does not exist, 2 on bad usage,
+3 when the store is corrupt or not a kstore file and 4 on any other error.
+";
+
+const EXIT_NOT_FOUND: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_CORRUPT: i32 = 3;
+const EXIT_ERROR: i32 = 4;
+
+#[derive(Clone, Copy)]
+enum Format {
+    Raw,
+    Hex,
+    Utf8,
+}
+
+enum Failure {
+    Usage(String),
+    NotFound,
+    Store(Error),
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        Failure::Store(err)
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        Failure::Store(err.into())
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let code = match run(&args) {
+        Ok(()) => 0,
+        Err(Failure::Usage(msg)) => {
+            eprintln!("error: {}\n{}", msg, USAGE);
+            EXIT_USAGE
+        },
+        Err(Failure::NotFound) => {
+            eprintln!("key not found");
+            EXIT_NOT_FOUND
+        },
+        Err(Failure::Store(err @ (Error::Corruption { .. } | Error::InvalidHeader(_)))) => {
+            eprintln!("error: {}", err);
+            EXIT_CORRUPT
+        },
+        Err(Failure::Store(err)) => {
+            eprintln!("error: {}", err);
+            EXIT_ERROR
+        },
+    };
+    process::exit(code);
+}
+
+fn run(args: &[String]) -> Result<(), Failure> {
+    let mut format = Format::Utf8;
+    let mut hex_input = false;
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().map(String::as_str) {
+                    Some("raw") => Format::Raw,
+                    Some("hex") => Format::Hex,
+                    Some("utf8") => Format::Utf8,
+                    _ => return Err(Failure::Usage("--format expects raw, hex or utf8".into())),
+                };
+            },
+            "--hex-input" => hex_input = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            },
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let (fname, action, rest) = match positional.as_slice() {
+        [fname, action, rest @ ..] => (*fname, *action, rest),
+        _ => return Err(Failure::Usage("expected FILE and a command".into())),
+    };
+    let input = |arg: &str| -> Result<Vec<u8>, Failure> {
+        if hex_input {
+            decode_hex(arg).ok_or_else(|| Failure::Usage(format!("{:?} is not valid hex", arg)))
+        } else {
+            Ok(arg.as_bytes().to_vec())
+        }
+    };
+
+    if !matches!(action, "get" | "insert" | "update" | "delete" | "list" | "find" | "stats" | "migrate") {
+        return Err(Failure::Usage(format!("unknown command {:?}", action)));
+    }
+
+    if action == "migrate" {
+        let report = match rest {
+            [] => kstore::migrate_in_place(Path::new(fname))?,
+            [dest] => kstore::migrate(Path::new(fname), Path::new(dest))?,
+            _ => return Err(Failure::Usage("bad arguments for \"migrate\"".into())),
+        };
+        println!(
+            "format v{} -> v{}: {} records, {} torn bytes dropped",
+            report.from_version, kstore::FORMAT_VERSION, report.records, report.discarded_bytes
+        );
+        return Ok(());
+    }
+
+    let writes = matches!(action, "insert" | "update" | "delete");
+    let mut store = ActionKvOptions::new()
+        .create_if_missing(matches!(action, "insert" | "update"))
+        .read_only(!writes)
+        .open(Path::new(fname))?;
+
+    match (action, rest) {
+        ("get", [key]) => {
+            let value = store.get(&input(key)?)?.ok_or(Failure::NotFound)?;
+            print_line(&[&value], format)?;
+        },
+        ("insert", [key, value @ ..]) | ("update", [key, value @ ..]) if value.len() <= 1 => {
+            let key = input(key)?;
+            if action == "update" && !store.contains_key(&key) {
+                return Err(Failure::NotFound);
+            }
+            let value = match value {
+                [value] => input(value)?,
+                _ => {
+                    let mut value = Vec::new();
+                    io::stdin().read_to_end(&mut value)?;
+                    value
+                },
+            };
+            store.insert(&key, &value)?;
+        },
+        ("delete", [key]) => {
+            let key = input(key)?;
+            if !store.contains_key(&key) {
+                return Err(Failure::NotFound);
+            }
+            store.delete(&key)?;
+        },
+        ("list", prefix) if prefix.len() <= 1 => {
+            let prefix = match prefix {
+                [prefix] => input(prefix)?,
+                _ => Vec::new(),
+            };
+            for pair in store.prefix(&prefix)? {
+                let (key, value) = pair?;
+                print_line(&[&key, &value], format)?;
+            }
+        },
+        ("find", [key]) => {
+            let ((segment, offset), value) = store.find(&input(key)?)?.ok_or(Failure::NotFound)?;
+            let position = if store.is_segmented() { format!("{}:{}", segment, offset) } else { offset.to_string() };
+            print_line(&[position.as_bytes(), &value], format)?;
+        },
+        ("stats", []) => print_stats(&mut store)?,
+        _ => return Err(Failure::Usage(format!("bad arguments for {:?}", action))),
+    }
+
+    store.close()?;
+    Ok(())
+}
+
+fn print_stats(store: &mut ActionKv) -> Result<(), Failure> {
+    let report = store.load_report();
+    let segments = store.segment_stats()?;
+    let file_len: u64 = segments.iter().map(|stats| stats.file_bytes).sum();
+    let live_bytes: u64 = segments.iter().map(|stats| stats.live_bytes).sum();
+    let retained_bytes: u64 = segments.iter().map(|stats| stats.retained_bytes).sum();
+    let dead_bytes: u64 = segments.iter().map(|stats| stats.dead_bytes).sum();
+
+    match store.header() {
+        Some(header) => {
+            let created = header.created_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            println!("format          v{} ({:?})", header.version, header.checksum);
+            println!("created         {} (unix seconds)", created);
+        },
+        None => println!("format          v{} (legacy, run migrate to upgrade)", store.format_version()),
+    }
+    if store.is_segmented() {
+        println!("segments        {}", segments.len());
+    }
+    println!("keys            {}", store.index.live_len());
+    let namespaces = store.namespaces();
+    if !namespaces.is_empty() {
+        println!("namespaces      {}", namespaces.join(", "));
+    }
+    println!("file bytes      {}", file_len);
+    println!("live bytes      {}", live_bytes);
+    if retained_bytes > 0 {
+        println!("retained bytes  {}", retained_bytes);
+    }
+    println!("dead bytes      {}", dead_bytes);
+    if store.is_segmented() {
+        for stats in &segments {
+            println!("  segment {:<8} {} bytes, {} dead", stats.id, stats.file_bytes, stats.dead_bytes);
+        }
+    }
+    println!("loaded from     {}", if report.used_hint { "hint file + log tail" } else { "full log scan" });
+    println!("replayed        {}", report.records);
+    println!("truncated bytes {}", report.discarded_bytes);
+    Ok(())
+}
+
+// fields are tab separated; raw output writes the bytes untouched with no separators
+fn print_line(fields: &[&[u8]], format: Format) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (i, field) in fields.iter().enumerate() {
+        match format {
+            Format::Raw => out.write_all(field)?,
+            Format::Hex => {
+                if i > 0 {
+                    out.write_all(b"\t")?;
+                }
+                for byte in field.iter() {
+                    write!(out, "{:02x}", byte)?;
+                }
+            },
+            Format::Utf8 => {
+                if i > 0 {
+                    out.write_all(b"\t")?;
+                }
+                out.write_all(String::from_utf8_lossy(field).as_bytes())?;
+            },
+        }
+    }
+    if !matches!(format, Format::Raw) {
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    // from_str_radix would take a sign as well
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
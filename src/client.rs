@@ -0,0 +1,158 @@
+//! A client for `kstore-server`.
+//!
+//! ```no_run
+//! # use kstore::client::{Client, Request};
+//! let mut client = Client::connect("127.0.0.1:7070")?;
+//! client.insert(b"greeting", b"hello")?;
+//! assert_eq!(client.get(b"greeting")?, Some(b"hello".to_vec()));
+//!
+//! // several requests in one round trip
+//! let replies = client.pipeline(&[Request::Get(b"a".to_vec()), Request::Get(b"b".to_vec())])?;
+//! # Ok::<(), kstore::Error>(())
+//! ```
+
+use std::{io::{self, BufReader, BufWriter, Read, Write}, net::{TcpStream, ToSocketAddrs}};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
+
+pub use crate::protocol::{Page, Reply, Request, MAX_FRAME_LEN};
+use crate::{protocol, ByteStr, ByteString, Error, Result};
+
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// One connection to a server. Requests on a connection are answered in
+/// the order they were sent.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<Stream>,
+    writer: BufWriter<Stream>,
+    next_id: u32,
+}
+
+impl Client {
+    /// Connects over TCP.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Client::new(Stream::Tcp(stream))
+    }
+
+    /// Connects to a server listening on a Unix socket.
+    #[cfg(unix)]
+    pub fn connect_unix(path: &Path) -> Result<Self> {
+        Client::new(Stream::Unix(UnixStream::connect(path)?))
+    }
+
+    fn new(stream: Stream) -> Result<Self> {
+        Ok(Client { reader: BufReader::new(stream.try_clone()?), writer: BufWriter::new(stream), next_id: 0 })
+    }
+
+    pub fn get(&mut self, key: &ByteStr) -> Result<Option<ByteString>> {
+        match self.call(Request::Get(key.to_vec())) {
+            Ok(Reply::Value(value)) => Ok(Some(value)),
+            Ok(_) => Err(Error::Protocol("unexpected reply")),
+            Err(Error::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.call(Request::Insert(key.to_vec(), value.to_vec())).map(drop)
+    }
+
+    /// Fails with `Error::NotFound` unless the key exists.
+    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.call(Request::Update(key.to_vec(), value.to_vec())).map(drop)
+    }
+
+    pub fn delete(&mut self, key: &ByteStr) -> Result<()> {
+        self.call(Request::Delete(key.to_vec())).map(drop)
+    }
+
+    /// One page of the pairs whose keys start with `prefix`, at most 1000
+    /// however large `limit` is; a `limit` of 0 is an error. Pass the page's `next` as `after` to fetch
+    /// the following one.
+    pub fn scan(&mut self, prefix: &ByteStr, after: Option<&ByteStr>, limit: u32) -> Result<Page> {
+        let request = Request::Scan { prefix: prefix.to_vec(), after: after.map(<[u8]>::to_vec), limit };
+        match self.call(request)? {
+            Reply::Page(page) => Ok(page),
+            _ => Err(Error::Protocol("unexpected reply")),
+        }
+    }
+
+    pub fn ping(&mut self) -> Result<()> {
+        self.call(Request::Ping).map(drop)
+    }
+
+    fn call(&mut self, request: Request) -> Result<Reply> {
+        let mut replies = self.pipeline(std::slice::from_ref(&request))?;
+        replies.pop().expect("one reply per request")
+    }
+
+    /// Sends all of `requests` before reading any reply, so they share one
+    /// round trip. The outer error is a failure of the connection, which
+    /// leaves it unusable; the inner ones are the results of each request.
+    pub fn pipeline(&mut self, requests: &[Request]) -> Result<Vec<Result<Reply>>> {
+        let first_id = self.next_id;
+        for request in requests {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1);
+            protocol::write_frame(&mut self.writer, &request.encode(id)?)?;
+        }
+        self.writer.flush()?;
+
+        let mut replies = Vec::with_capacity(requests.len());
+        for (i, request) in requests.iter().enumerate() {
+            let frame = protocol::read_frame(&mut self.reader)?.ok_or(Error::Protocol("connection closed by the server"))?;
+            let (id, reply) = protocol::decode_response(&frame, request)?;
+            if id != first_id.wrapping_add(i as u32) {
+                return Err(Error::Protocol("response out of order"));
+            }
+            replies.push(reply);
+        }
+        Ok(replies)
+    }
+}
@@ -0,0 +1,99 @@
+//! Serialization formats for `TypedStore`. The codec of a store is recorded
+//! in its file header, so a store is always read back with the format it
+//! was written in.
+
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{ByteStr, ByteString, Error, Result};
+
+mod bincode;
+mod cbor;
+mod json;
+mod msgpack;
+mod value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// The compact, non-self-describing layout of bincode 1.x.
+    Bincode,
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+impl Codec {
+    /// The id stored in the file header; 0 means the store holds raw bytes.
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Bincode => 1,
+            Codec::Json => 2,
+            Codec::Cbor => 3,
+            Codec::MessagePack => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Codec::Bincode),
+            2 => Some(Codec::Json),
+            3 => Some(Codec::Cbor),
+            4 => Some(Codec::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<ByteString> {
+        match self {
+            Codec::Bincode => bincode::to_vec(value),
+            Codec::Json => {
+                let mut out = String::new();
+                json::write(&value::to_value(value)?, &mut out)?;
+                Ok(out.into_bytes())
+            },
+            Codec::Cbor => {
+                let mut out = Vec::new();
+                cbor::write(&value::to_value(value)?, &mut out)?;
+                Ok(out)
+            },
+            Codec::MessagePack => {
+                let mut out = Vec::new();
+                msgpack::write(&value::to_value(value)?, &mut out)?;
+                Ok(out)
+            },
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, data: &ByteStr) -> Result<T> {
+        match self {
+            Codec::Bincode => bincode::from_slice(data),
+            Codec::Json => value::from_value(json::read(data)?),
+            Codec::Cbor => value::from_value(cbor::read(data)?),
+            Codec::MessagePack => value::from_value(msgpack::read(data)?),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Codec::Bincode => "bincode",
+            Codec::Json => "JSON",
+            Codec::Cbor => "CBOR",
+            Codec::MessagePack => "MessagePack",
+        })
+    }
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Codec(msg.to_string())
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Codec(msg.to_string())
+    }
+}
@@ -0,0 +1,161 @@
+//! CBOR (RFC 8949) with definite lengths, the smallest encoding of every
+//! integer and length, and floats as 64-bit values. Tags are skipped when
+//! decoding.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::value::{Value, MAX_DEPTH};
+use crate::{Error, Result};
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+fn write_head(major: u8, n: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= u8::MAX.into() {
+        out.extend_from_slice(&[major | 24, n as u8]);
+    } else if n <= u16::MAX.into() {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u32::MAX.into() {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+pub(crate) fn write(value: &Value, out: &mut Vec<u8>) -> Result<()> {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::U64(v) => write_head(UNSIGNED, *v, out),
+        Value::I64(v) if *v >= 0 => write_head(UNSIGNED, *v as u64, out),
+        Value::I64(v) => write_head(NEGATIVE, !(*v) as u64, out),
+        Value::F64(v) => {
+            out.push(0xfb);
+            out.write_f64::<BigEndian>(*v)?;
+        },
+        Value::Str(v) => {
+            write_head(TEXT, v.len() as u64, out);
+            out.extend_from_slice(v.as_bytes());
+        },
+        Value::Bytes(v) => {
+            write_head(BYTES, v.len() as u64, out);
+            out.extend_from_slice(v);
+        },
+        Value::Seq(items) => {
+            write_head(ARRAY, items.len() as u64, out);
+            for item in items {
+                write(item, out)?;
+            }
+        },
+        Value::Map(entries) => {
+            write_head(MAP, entries.len() as u64, out);
+            for (key, value) in entries {
+                write(key, out)?;
+                write(value, out)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+pub(crate) fn read(data: &[u8]) -> Result<Value> {
+    let mut r = data;
+    let value = read_value(&mut r, 0).map_err(|err| match err {
+        err if err.is_eof() => Error::Codec("unexpected end of CBOR".into()),
+        err => err,
+    })?;
+    if !r.is_empty() {
+        return Err(Error::Codec(format!("{} trailing bytes after CBOR", r.len())));
+    }
+    Ok(value)
+}
+
+fn read_value(r: &mut &[u8], depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(Error::Codec("CBOR nested too deeply".into()));
+    }
+    let initial = r.read_u8()?;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+
+    if major == SIMPLE {
+        return Ok(match info {
+            20 => Value::Bool(false),
+            21 => Value::Bool(true),
+            22 | 23 => Value::Null,
+            25 => Value::F64(f16_to_f64(r.read_u16::<BigEndian>()?)),
+            26 => Value::F64(r.read_f32::<BigEndian>()?.into()),
+            27 => Value::F64(r.read_f64::<BigEndian>()?),
+            _ => return Err(Error::Codec(format!("unsupported CBOR simple value {}", info))),
+        });
+    }
+
+    let n = match info {
+        0..=23 => info.into(),
+        24 => r.read_u8()?.into(),
+        25 => r.read_u16::<BigEndian>()?.into(),
+        26 => r.read_u32::<BigEndian>()?.into(),
+        27 => r.read_u64::<BigEndian>()?,
+        31 => return Err(Error::Codec("indefinite-length CBOR items are not supported".into())),
+        _ => return Err(Error::Codec(format!("invalid CBOR additional information {}", info))),
+    };
+
+    Ok(match major {
+        UNSIGNED => Value::U64(n),
+        NEGATIVE => {
+            let v = i64::try_from(n).map_err(|_| Error::Codec("CBOR integer out of range".into()))?;
+            Value::I64(!v)
+        },
+        BYTES => Value::Bytes(take(r, n)?.to_vec()),
+        TEXT => Value::Str(String::from_utf8(take(r, n)?.to_vec()).map_err(|err| Error::Codec(err.to_string()))?),
+        ARRAY => {
+            let mut items = Vec::new();
+            for _ in 0..n {
+                items.push(read_value(r, depth + 1)?);
+            }
+            Value::Seq(items)
+        },
+        MAP => {
+            let mut entries = Vec::new();
+            for _ in 0..n {
+                entries.push((read_value(r, depth + 1)?, read_value(r, depth + 1)?));
+            }
+            Value::Map(entries)
+        },
+        TAG => read_value(r, depth + 1)?,
+        _ => unreachable!("the major type has three bits"),
+    })
+}
+
+fn take<'a>(r: &mut &'a [u8], n: u64) -> Result<&'a [u8]> {
+    if (r.len() as u64) < n {
+        return Err(Error::Codec("unexpected end of CBOR".into()));
+    }
+    let (bytes, rest) = r.split_at(n as usize);
+    *r = rest;
+    Ok(bytes)
+}
+
+fn f16_to_f64(half: u16) -> f64 {
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent as i32 - 15),
+    };
+    if half & 0x8000 != 0 { -magnitude } else { magnitude }
+}
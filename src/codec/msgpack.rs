@@ -0,0 +1,194 @@
+//! MessagePack with the smallest encoding of every integer, string, array
+//! and map. Extension types are rejected.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::value::{Value, MAX_DEPTH};
+use crate::{Error, Result};
+
+pub(crate) fn write(value: &Value, out: &mut Vec<u8>) -> Result<()> {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::U64(v) => write_uint(*v, out)?,
+        Value::I64(v) if *v >= 0 => write_uint(*v as u64, out)?,
+        Value::I64(v) => {
+            let v = *v;
+            if v >= -32 {
+                out.push(v as u8);
+            } else if v >= i8::MIN.into() {
+                out.push(0xd0);
+                out.write_i8(v as i8)?;
+            } else if v >= i16::MIN.into() {
+                out.push(0xd1);
+                out.write_i16::<BigEndian>(v as i16)?;
+            } else if v >= i32::MIN.into() {
+                out.push(0xd2);
+                out.write_i32::<BigEndian>(v as i32)?;
+            } else {
+                out.push(0xd3);
+                out.write_i64::<BigEndian>(v)?;
+            }
+        },
+        Value::F64(v) => {
+            out.push(0xcb);
+            out.write_f64::<BigEndian>(*v)?;
+        },
+        Value::Str(v) => {
+            write_len(v.len(), Some(0xa0), [0xd9, 0xda, 0xdb], out)?;
+            out.extend_from_slice(v.as_bytes());
+        },
+        Value::Bytes(v) => {
+            write_len(v.len(), None, [0xc4, 0xc5, 0xc6], out)?;
+            out.extend_from_slice(v);
+        },
+        Value::Seq(items) => {
+            write_container_len(items.len(), 0x90, [0xdc, 0xdd], out)?;
+            for item in items {
+                write(item, out)?;
+            }
+        },
+        Value::Map(entries) => {
+            write_container_len(entries.len(), 0x80, [0xde, 0xdf], out)?;
+            for (key, value) in entries {
+                write(key, out)?;
+                write(value, out)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+fn write_uint(v: u64, out: &mut Vec<u8>) -> Result<()> {
+    if v < 0x80 {
+        out.push(v as u8);
+    } else if v <= u8::MAX.into() {
+        out.extend_from_slice(&[0xcc, v as u8]);
+    } else if v <= u16::MAX.into() {
+        out.push(0xcd);
+        out.write_u16::<BigEndian>(v as u16)?;
+    } else if v <= u32::MAX.into() {
+        out.push(0xce);
+        out.write_u32::<BigEndian>(v as u32)?;
+    } else {
+        out.push(0xcf);
+        out.write_u64::<BigEndian>(v)?;
+    }
+    Ok(())
+}
+
+/// Writes the length of a string or binary, using the fix form when there
+/// is one and otherwise the 8, 16 or 32 bit marker.
+fn write_len(len: usize, fix: Option<u8>, markers: [u8; 3], out: &mut Vec<u8>) -> Result<()> {
+    match fix {
+        Some(fix) if len < 32 => out.push(fix | len as u8),
+        _ if len <= u8::MAX as usize => out.extend_from_slice(&[markers[0], len as u8]),
+        _ if len <= u16::MAX as usize => {
+            out.push(markers[1]);
+            out.write_u16::<BigEndian>(len as u16)?;
+        },
+        _ => {
+            out.push(markers[2]);
+            out.write_u32::<BigEndian>(u32::try_from(len).map_err(|_| Error::Codec("MessagePack item too long".into()))?)?;
+        },
+    }
+    Ok(())
+}
+
+fn write_container_len(len: usize, fix: u8, markers: [u8; 2], out: &mut Vec<u8>) -> Result<()> {
+    if len < 16 {
+        out.push(fix | len as u8);
+    } else if len <= u16::MAX as usize {
+        out.push(markers[0]);
+        out.write_u16::<BigEndian>(len as u16)?;
+    } else {
+        out.push(markers[1]);
+        out.write_u32::<BigEndian>(u32::try_from(len).map_err(|_| Error::Codec("MessagePack item too long".into()))?)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read(data: &[u8]) -> Result<Value> {
+    let mut r = data;
+    let value = read_value(&mut r, 0).map_err(|err| match err {
+        err if err.is_eof() => Error::Codec("unexpected end of MessagePack".into()),
+        err => err,
+    })?;
+    if !r.is_empty() {
+        return Err(Error::Codec(format!("{} trailing bytes after MessagePack", r.len())));
+    }
+    Ok(value)
+}
+
+fn read_value(r: &mut &[u8], depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(Error::Codec("MessagePack nested too deeply".into()));
+    }
+    let marker = r.read_u8()?;
+    Ok(match marker {
+        0x00..=0x7f => Value::U64(marker.into()),
+        0x80..=0x8f => read_map(r, (marker & 0x0f).into(), depth)?,
+        0x90..=0x9f => read_array(r, (marker & 0x0f).into(), depth)?,
+        0xa0..=0xbf => read_str(r, (marker & 0x1f).into())?,
+        0xc0 => Value::Null,
+        0xc2 => Value::Bool(false),
+        0xc3 => Value::Bool(true),
+        0xc4 => { let len = r.read_u8()?.into(); Value::Bytes(take(r, len)?.to_vec()) },
+        0xc5 => { let len = r.read_u16::<BigEndian>()?.into(); Value::Bytes(take(r, len)?.to_vec()) },
+        0xc6 => { let len = r.read_u32::<BigEndian>()? as usize; Value::Bytes(take(r, len)?.to_vec()) },
+        0xca => Value::F64(r.read_f32::<BigEndian>()?.into()),
+        0xcb => Value::F64(r.read_f64::<BigEndian>()?),
+        0xcc => Value::U64(r.read_u8()?.into()),
+        0xcd => Value::U64(r.read_u16::<BigEndian>()?.into()),
+        0xce => Value::U64(r.read_u32::<BigEndian>()?.into()),
+        0xcf => Value::U64(r.read_u64::<BigEndian>()?),
+        0xd0 => int(r.read_i8()?.into()),
+        0xd1 => int(r.read_i16::<BigEndian>()?.into()),
+        0xd2 => int(r.read_i32::<BigEndian>()?.into()),
+        0xd3 => int(r.read_i64::<BigEndian>()?),
+        0xd9 => { let len = r.read_u8()?.into(); read_str(r, len)? },
+        0xda => { let len = r.read_u16::<BigEndian>()?.into(); read_str(r, len)? },
+        0xdb => { let len = r.read_u32::<BigEndian>()? as usize; read_str(r, len)? },
+        0xdc => { let len = r.read_u16::<BigEndian>()?.into(); read_array(r, len, depth)? },
+        0xdd => { let len = r.read_u32::<BigEndian>()? as usize; read_array(r, len, depth)? },
+        0xde => { let len = r.read_u16::<BigEndian>()?.into(); read_map(r, len, depth)? },
+        0xdf => { let len = r.read_u32::<BigEndian>()? as usize; read_map(r, len, depth)? },
+        0xe0..=0xff => Value::I64((marker as i8).into()),
+        _ => return Err(Error::Codec(format!("unsupported MessagePack marker {:#04x}", marker))),
+    })
+}
+
+fn int(v: i64) -> Value {
+    if v < 0 { Value::I64(v) } else { Value::U64(v as u64) }
+}
+
+fn take<'a>(r: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if r.len() < len {
+        return Err(Error::Codec("unexpected end of MessagePack".into()));
+    }
+    let (bytes, rest) = r.split_at(len);
+    *r = rest;
+    Ok(bytes)
+}
+
+fn read_str(r: &mut &[u8], len: usize) -> Result<Value> {
+    let bytes = take(r, len)?.to_vec();
+    Ok(Value::Str(String::from_utf8(bytes).map_err(|err| Error::Codec(err.to_string()))?))
+}
+
+fn read_array(r: &mut &[u8], len: usize, depth: usize) -> Result<Value> {
+    let mut items = Vec::new();
+    for _ in 0..len {
+        items.push(read_value(r, depth + 1)?);
+    }
+    Ok(Value::Seq(items))
+}
+
+fn read_map(r: &mut &[u8], len: usize, depth: usize) -> Result<Value> {
+    let mut entries = Vec::new();
+    for _ in 0..len {
+        entries.push((read_value(r, depth + 1)?, read_value(r, depth + 1)?));
+    }
+    Ok(Value::Map(entries))
+}
@@ -0,0 +1,414 @@
+//! Dead-byte accounting and the merge that background compaction runs.
+//!
+//! A merge rewrites every sealed segment without holding the store for the
+//! whole copy. It starts by rotating the active segment out, leaving a gap
+//! of free ids below the new active segment. The live records of the sealed
+//! segments are then copied into new segments that take those ids, so on
+//! replay they still come before anything written in the meantime. Finally
+//! the index is pointed at the copies and the old segments are deleted;
+//! keys rewritten while the merge ran keep their newer entry.
+//!
+//! With a history retention window both merges and `compact` also copy the
+//! superseded records and tombstones written within it, in their original
+//! order, so replaying the copies still ends in the same index. Likewise
+//! the record each key had at the seq of a live snapshot is copied for as
+//! long as that snapshot exists.
+
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, fs::{self, File, OpenOptions}, io::{self, BufWriter, Write}, path::PathBuf, time::Duration};
+
+use crate::{header, now_micros, segment::{self, Segment}, ActionKv, ByteString, ChecksumAlgorithm, Codec, Error, FileHeader, IndexEntry, LogRecord, Record, RecordMeta, Result};
+
+/// When background compaction merges the segments of a store. A merge
+/// starts once either threshold is reached; `None` disables a threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactionPolicy {
+    /// Fraction of the log, between 0 and 1, that has to be dead.
+    pub garbage_ratio: Option<f64>,
+    /// Number of dead bytes in the log.
+    pub dead_bytes: Option<u64>,
+    /// How often the background thread checks the thresholds.
+    pub check_interval: Duration,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        CompactionPolicy { garbage_ratio: Some(0.5), dead_bytes: None, check_interval: Duration::from_secs(10) }
+    }
+}
+
+/// Space used by one segment. Dead bytes belong to records that were
+/// overwritten or deleted, tombstones and batch frame headers. Retained
+/// bytes are the older records the last rewrite of the segment kept for
+/// the history retention window or a snapshot; they count as neither, so
+/// keeping them does not make the compaction policy merge again. Until the
+/// next rewrite after a reopen they count as dead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentStats {
+    pub id: u32,
+    pub file_bytes: u64,
+    pub live_bytes: u64,
+    pub retained_bytes: u64,
+    pub dead_bytes: u64,
+}
+
+/// The records a rewrite of the log keeps.
+pub(crate) struct Survivors {
+    /// Entries for the records to copy, in log order. The live ones are
+    /// the entries of `index`.
+    pub kept: Vec<(ByteString, IndexEntry)>,
+    /// Entries of `index` that expired and are left behind.
+    pub expired: Vec<(ByteString, IndexEntry)>,
+}
+
+/// A merge that has been planned and is waiting to be written and applied.
+pub(crate) struct MergePlan {
+    inputs: BTreeMap<u32, (File, ChecksumAlgorithm)>,
+    checksum: ChecksumAlgorithm,
+    codec: Option<Codec>,
+    /// `(key, entry before the merge, entry after it)` in output order.
+    moves: Vec<(ByteString, IndexEntry, IndexEntry)>,
+    /// Keys whose entry had expired, which the merge leaves behind.
+    expired: Vec<(ByteString, IndexEntry)>,
+    /// `(id, final path, temporary path)` of every new segment.
+    outputs: Vec<(u32, PathBuf, PathBuf)>,
+}
+
+/// The walk of the log that finds the older records a rewrite keeps. It
+/// can be split: `walk` covers what is in the log when the walk is set up,
+/// using handles of its own so the store need not be locked, and
+/// `survivors` then covers only what was appended in the meantime.
+pub(crate) struct Retention {
+    files: Vec<(u32, File, ChecksumAlgorithm, u64)>,
+    /// Where the walk has got to and the seq of the record there.
+    position: Option<(u32, u64)>,
+    next_seq: u64,
+    now: u64,
+    /// When the history retention window opened.
+    opens_at: Option<u64>,
+    pins: BTreeSet<u64>,
+    keys: HashMap<(u32, ByteString), KeyHistory>,
+    kept: Vec<(ByteString, IndexEntry)>,
+}
+
+/// What `Retention` knows of one key so far.
+#[derive(Default)]
+struct KeyHistory {
+    /// The record the key had when the retention window opened.
+    before: Option<IndexEntry>,
+    /// Its newest record and whether that still holds a value.
+    last: Option<(IndexEntry, bool)>,
+    keeps_older: bool,
+}
+
+impl ActionKv {
+    pub fn segment_stats(&self) -> Result<Vec<SegmentStats>> {
+        self.check_open()?;
+        self.segments.values()
+            .map(|segment| {
+                let file_bytes = segment.file_len()?;
+                let live_bytes = self.live_bytes.get(&segment.id).copied().unwrap_or(0);
+                let retained_bytes = self.retained_bytes.get(&segment.id).copied().unwrap_or(0);
+                let dead_bytes = file_bytes.saturating_sub(segment.data_start).saturating_sub(live_bytes).saturating_sub(retained_bytes);
+                Ok(SegmentStats { id: segment.id, file_bytes, live_bytes, retained_bytes, dead_bytes })
+            })
+            .collect()
+    }
+
+    /// Dead bytes across all segments.
+    pub fn dead_bytes(&self) -> Result<u64> {
+        Ok(self.segment_stats()?.iter().map(|stats| stats.dead_bytes).sum())
+    }
+
+    /// Whether the background compaction policy calls for a merge.
+    pub fn compaction_due(&self) -> Result<bool> {
+        let policy = match self.compaction {
+            Some(policy) => policy,
+            None => return Ok(false),
+        };
+        let stats = self.segment_stats()?;
+        let dead: u64 = stats.iter().map(|stats| stats.dead_bytes).sum();
+        let total: u64 = stats.iter().map(|stats| stats.file_bytes).sum();
+        if dead == 0 {
+            return Ok(false);
+        }
+
+        let over_ratio = policy.garbage_ratio.is_some_and(|ratio| dead as f64 >= ratio * total as f64);
+        let over_bytes = policy.dead_bytes.is_some_and(|bytes| dead >= bytes);
+        Ok(over_ratio || over_bytes)
+    }
+
+    pub(crate) fn compaction_policy(&self) -> Option<CompactionPolicy> {
+        self.compaction
+    }
+
+    /// Sets up the walk for the history a rewrite has to keep, `None` when
+    /// there is neither a retention window nor a live snapshot.
+    pub(crate) fn retention(&self) -> Result<Option<Retention>> {
+        let pins = self.pinned_seqs();
+        if self.history_retention.is_none() && pins.is_empty() {
+            return Ok(None);
+        }
+        let files = self.segments.values()
+            .map(|segment| Ok((segment.id, segment.f.try_clone()?, segment.checksum, segment.data_start)))
+            .collect::<Result<_>>()?;
+        let now = now_micros();
+        let opens_at = self.history_retention.map(|window| now.saturating_sub(window.as_micros().min(u64::MAX as u128) as u64));
+        Ok(Some(Retention { files, position: None, next_seq: 0, now, opens_at, pins, keys: HashMap::new(), kept: Vec::new() }))
+    }
+
+    /// The live record of every key that has not expired and the history
+    /// that has to outlast the rewrite, for a retention window or for the
+    /// live snapshots. `retention` is a walk set up by `retention`, which
+    /// is finished here.
+    pub(crate) fn survivors(&self, retention: Option<Retention>) -> Result<Survivors> {
+        let (mut kept, mut expired): (Vec<(ByteString, IndexEntry)>, Vec<_>) = self.indexes().flatten()
+            .map(|(key, entry)| (key.clone(), *entry))
+            .partition(|(_, entry)| !entry.is_expired());
+        // a snapshot may have been taken since the walk was set up
+        let retention = match retention {
+            Some(retention) => Some(retention),
+            None => self.retention()?,
+        };
+        if let Some(retention) = retention {
+            let mut kept_at: HashSet<(u32, u64)> = kept.iter().map(|(_, entry)| (entry.segment, entry.offset)).collect();
+            for (key, entry) in retention.finish(self)? {
+                if kept_at.insert((entry.segment, entry.offset)) {
+                    kept.push((key, entry));
+                }
+            }
+            expired.retain(|(_, entry)| !kept_at.contains(&(entry.segment, entry.offset)));
+        }
+        kept.sort_by_key(|(_, entry)| (entry.segment, entry.offset));
+        Ok(Survivors { kept, expired })
+    }
+
+    /// Copies the record `entry` points at in `f` to `out`, keeping its
+    /// timestamp, and returns the length written and whether it holds a
+    /// value.
+    pub(crate) fn copy_record<W: Write>(f: &File, checksum: ChecksumAlgorithm, entry: &IndexEntry, out: &mut W, out_checksum: ChecksumAlgorithm) -> Result<(u64, bool)> {
+        let (key, value, stored) = match ActionKv::read_any(f, entry.offset, checksum)?.0 {
+            Record::Value(kv, meta) => (kv.key, Some(kv.value), meta),
+            Record::Tombstone(key, meta) => (key, None, meta),
+            Record::Batch(_) => return Err(Error::NotFound),
+        };
+        let mut meta = RecordMeta { written_at: stored.written_at, ..RecordMeta::of(entry) };
+        if value.is_none() {
+            meta.version = None;
+        }
+        let len = ActionKv::write_record(out, &key, value.as_deref(), &meta, out_checksum)?;
+        Ok((len, value.is_some()))
+    }
+
+    /// Seals the active segment and plans a merge of every segment that
+    /// copies `survivors`. They have to be taken under the writer lock, or
+    /// a record appended in between would be left out.
+    pub(crate) fn begin_merge(&mut self, survivors: Survivors) -> Result<MergePlan> {
+        let active = self.writable_segment()?;
+        let (last_input, checksum) = (active.id, active.checksum);
+        let Survivors { kept: live, expired } = survivors;
+
+        // lay the records out the way `compact` would, so the ids the new
+        // segments need are known before the active segment is rotated; a
+        // record that gains fields when rewritten can end up a little longer,
+        // so `write` fills in the final offsets
+        let mut moves = Vec::with_capacity(live.len());
+        let mut id = last_input + 1;
+        let mut next_position = header::HEADER_LEN;
+        for (key, entry) in live {
+            if next_position >= self.max_segment_size && next_position > header::HEADER_LEN {
+                id += 1;
+                next_position = header::HEADER_LEN;
+            }
+            moves.push((key, entry, IndexEntry { segment: id, offset: next_position, ..entry }));
+            next_position += entry.len;
+        }
+        let last_output = if moves.is_empty() { last_input } else { id };
+
+        let mut inputs = BTreeMap::new();
+        for segment in self.segments.values() {
+            inputs.insert(segment.id, (segment.f.try_clone()?, segment.checksum));
+        }
+        let outputs = (last_input + 1..=last_output)
+            .map(|id| {
+                let path = segment::segment_path(&self.path, id);
+                let tmp_path = ActionKv::sibling_path(&path, "compact");
+                (id, path, tmp_path)
+            })
+            .collect();
+
+        self.rotate_to(last_output + 1)?;
+
+        Ok(MergePlan { inputs, checksum, codec: self.codec, moves, expired, outputs })
+    }
+
+    /// Swaps the segments written by `plan` in for the ones it merged. A
+    /// plan whose inputs were compacted away in the meantime is dropped.
+    pub(crate) fn finish_merge(&mut self, plan: MergePlan) -> Result<()> {
+        if self.closed || plan.inputs.keys().any(|id| !self.segments.contains_key(id)) {
+            plan.discard();
+            return if self.closed { Err(Error::Closed) } else { Ok(()) };
+        }
+
+        for (_, path, tmp_path) in &plan.outputs {
+            fs::rename(tmp_path, path)?;
+        }
+        let dir_entry = segment::segment_path(&self.path, 0);
+        ActionKv::sync_parent_dir(&dir_entry)?;
+
+        let mut open = OpenOptions::new();
+        open.read(true).append(true);
+        for (id, path, _) in &plan.outputs {
+            self.segments.insert(*id, Segment::open(*id, path.clone(), &open, false, true, plan.checksum, self.codec)?);
+        }
+
+        let last_move = plan.moves.last().map(|(_, _, entry)| (entry.segment, entry.offset));
+        let mut written: BTreeMap<u32, u64> = BTreeMap::new();
+        for (_, _, new) in &plan.moves {
+            *written.entry(new.segment).or_default() += new.len;
+        }
+        for (key, old, new) in plan.moves {
+            if self.index_of(old.namespace).and_then(|index| index.get(&key)) == Some(&old) {
+                self.index_insert(key, new);
+            }
+        }
+        for (key, old) in plan.expired {
+            if self.index_of(old.namespace).and_then(|index| index.get(&key)) == Some(&old) {
+                self.index_remove(old.namespace, &key);
+            }
+        }
+        // whatever was copied and is not live now was kept as history, or
+        // was rewritten while the merge ran and counts as retained until
+        // the next one
+        for (id, bytes) in written {
+            let live = self.live_bytes.get(&id).copied().unwrap_or(0);
+            self.retained_bytes.insert(id, bytes.saturating_sub(live));
+        }
+        if self.last_record.is_some_and(|(segment, _)| plan.inputs.contains_key(&segment)) {
+            self.last_record = last_move;
+        }
+        self.generation += 1;
+
+        for id in plan.inputs.keys() {
+            if let Some(segment) = self.segments.remove(id) {
+                self.live_bytes.remove(id);
+                self.retained_bytes.remove(id);
+                drop(segment.f);
+                fs::remove_file(&segment.path)?;
+            }
+        }
+        ActionKv::sync_parent_dir(&dir_entry)?;
+
+        if self.hint_every.is_some() {
+            self.write_hint()?;
+        } else {
+            match fs::remove_file(self.hint_path()) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MergePlan {
+    /// Copies the live records into the new segments under temporary names.
+    /// Needs no access to the store: the merged segments are sealed and
+    /// read through handles of their own.
+    pub fn write(&mut self) -> Result<()> {
+        let mut moves = self.moves.iter_mut().peekable();
+        for (id, _, tmp_path) in &self.outputs {
+            let tmp = OpenOptions::new().write(true).create(true).truncate(true).open(tmp_path)?;
+            let mut out = BufWriter::new(tmp);
+            FileHeader { codec: self.codec, ..FileHeader::new(self.checksum) }.write(&mut out)?;
+
+            let mut next_position = header::HEADER_LEN;
+            while let Some((_, old, new)) = moves.next_if(|(_, _, new)| new.segment == *id) {
+                let (f, checksum) = &self.inputs[&old.segment];
+                let (len, _) = ActionKv::copy_record(f, *checksum, old, &mut out, self.checksum)?;
+                *new = IndexEntry { offset: next_position, len, ..*new };
+                next_position += len;
+            }
+
+            let tmp = out.into_inner().map_err(|err| err.into_error())?;
+            tmp.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Removes whatever `write` left behind.
+    pub fn discard(&self) {
+        for (_, _, tmp_path) in &self.outputs {
+            let _ = fs::remove_file(tmp_path);
+        }
+    }
+}
+
+impl Retention {
+    /// Walks the log as far as it went when the walk was set up.
+    pub fn walk(&mut self) -> Result<()> {
+        let files = std::mem::take(&mut self.files);
+        let segments = files.iter().map(|(id, f, checksum, data_start)| (*id, f, *checksum, *data_start));
+        let (position, next_seq) = crate::walk_segments(segments, self.position, self.next_seq, |record| self.visit(record))?;
+        self.position = position;
+        self.next_seq = next_seq;
+        Ok(())
+    }
+
+    // With a window, every record written within it and the one each key
+    // had when it opened if it still held a value then; records without a
+    // timestamp count as older than any window. For each pinned seq, the
+    // record each key had then if it still holds a value. A key that keeps
+    // an older record also keeps its newest, so the copies replay to the
+    // same index. Records of dropped namespaces are left out, and records
+    // can come up more than once.
+    fn finish(mut self, store: &ActionKv) -> Result<Vec<(ByteString, IndexEntry)>> {
+        // snapshots taken since the walk was set up are pinned past every
+        // record it has seen, so they only matter from here on
+        self.pins = store.pinned_seqs();
+        let (position, next_seq) = (self.position, self.next_seq);
+        store.walk_log_from(position, next_seq, |record| self.visit(record))?;
+
+        let mut kept = self.kept;
+        for ((_, key), history) in self.keys {
+            if let Some(before) = history.before {
+                kept.push((key.clone(), before));
+            }
+            match history.last {
+                Some((last, _)) if history.keeps_older || history.before.is_some() => kept.push((key, last)),
+                _ => {},
+            }
+        }
+        kept.retain(|(_, entry)| store.index_of(entry.namespace).is_some());
+        Ok(kept)
+    }
+
+    fn visit(&mut self, record: LogRecord) {
+        let history = self.keys.entry((record.namespace, record.key.clone())).or_default();
+        if let Some((last, holds)) = history.last {
+            if holds && self.pins.range(last.seq..record.seq).next().is_some() {
+                self.kept.push((record.key.clone(), last));
+                history.keeps_older = true;
+            }
+        }
+
+        let entry = IndexEntry {
+            segment: record.segment,
+            offset: record.offset,
+            len: record.len,
+            seq: record.seq,
+            version: if record.value.is_some() { record.meta.version_at(record.seq) } else { 0 },
+            expires_at: record.meta.expires_at,
+            namespace: record.namespace,
+        };
+        let held_at = |at: u64| record.value.is_some() && record.meta.expires_at.is_none_or(|expires_at| expires_at > at);
+        if let Some(opens_at) = self.opens_at {
+            if record.meta.written_at.is_some_and(|at| at >= opens_at) {
+                self.kept.push((record.key.clone(), entry));
+                history.keeps_older = true;
+            } else {
+                history.before = Some(entry).filter(|_| held_at(opens_at));
+            }
+        }
+        history.last = Some((entry, held_at(self.now)));
+    }
+}
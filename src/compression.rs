@@ -0,0 +1,175 @@
+//! Value compression for namespaces. A compressed value is stored as
+//! `uncompressed_len u32 | block`, where the block is in the LZ4 block
+//! format.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{ByteStr, ByteString, Error, Result};
+
+const MIN_MATCH: usize = 4;
+// the format wants the last five bytes to be literals and no match to
+// start within the last twelve
+const LAST_LITERALS: usize = 5;
+const MATCH_START_LIMIT: usize = 12;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_LOG: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+}
+
+impl Compression {
+    /// The id stored in a namespace's catalog record.
+    pub fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn compress(self, data: &ByteStr) -> Result<ByteString> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => {
+                let len = u32::try_from(data.len()).map_err(|_| Error::ValueTooLarge { len: data.len(), max: u32::MAX as usize })?;
+                let mut out = Vec::with_capacity(4 + data.len() / 2);
+                out.extend_from_slice(&len.to_le_bytes());
+                compress_block(data, &mut out);
+                Ok(out)
+            },
+        }
+    }
+
+    pub fn decompress(self, data: ByteString) -> Result<ByteString> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Lz4 => {
+                if data.len() < 4 {
+                    return Err(malformed());
+                }
+                decompress_block(&data[4..], LittleEndian::read_u32(&data) as usize)
+            },
+        }
+    }
+}
+
+fn malformed() -> Error {
+    Error::Codec("malformed LZ4 block".into())
+}
+
+// greedy matching against the last position each four byte sequence was seen at
+fn compress_block(input: &ByteStr, out: &mut ByteString) {
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut i = 0;
+    let match_end_limit = input.len().saturating_sub(LAST_LITERALS);
+    while i + MATCH_START_LIMIT < input.len() {
+        let sequence = LittleEndian::read_u32(&input[i..]);
+        let slot = (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize;
+        // positions are stored plus one so that 0 means empty
+        let candidate = table[slot].checked_sub(1);
+        table[slot] = i + 1;
+
+        match candidate {
+            Some(start) if i - start <= MAX_OFFSET && input[start..start + MIN_MATCH] == input[i..i + MIN_MATCH] => {
+                let mut len = MIN_MATCH;
+                while i + len < match_end_limit && input[start + len] == input[i + len] {
+                    len += 1;
+                }
+                write_sequence(out, &input[anchor..i], Some((i - start, len)));
+                i += len;
+                anchor = i;
+            },
+            _ => i += 1,
+        }
+    }
+    write_sequence(out, &input[anchor..], None);
+}
+
+fn write_sequence(out: &mut ByteString, literals: &ByteStr, matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_length(out, match_len - 15);
+        }
+    }
+}
+
+fn write_length(out: &mut ByteString, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn decompress_block(mut input: &ByteStr, expected_len: usize) -> Result<ByteString> {
+    // the stored length is only trusted as far as the block could expand
+    let mut out = Vec::with_capacity(expected_len.min(input.len().saturating_mul(255)));
+    loop {
+        let (&token, rest) = input.split_first().ok_or_else(malformed)?;
+        input = rest;
+
+        let literals = read_length(&mut input, (token >> 4) as usize)?;
+        if input.len() < literals {
+            return Err(malformed());
+        }
+        let (literal_bytes, rest) = input.split_at(literals);
+        out.extend_from_slice(literal_bytes);
+        input = rest;
+        if input.is_empty() {
+            break;
+        }
+
+        if input.len() < 2 {
+            return Err(malformed());
+        }
+        let offset = LittleEndian::read_u16(input) as usize;
+        input = &input[2..];
+        let len = read_length(&mut input, (token & 15) as usize)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + len > expected_len {
+            return Err(malformed());
+        }
+        // a match may overlap the bytes it produces, so copy one at a time
+        let start = out.len() - offset;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+    if out.len() != expected_len {
+        return Err(malformed());
+    }
+    Ok(out)
+}
+
+fn read_length(input: &mut &ByteStr, nibble: usize) -> Result<usize> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let (&byte, rest) = input.split_first().ok_or_else(malformed)?;
+            *input = rest;
+            len = len.checked_add(byte as usize).ok_or_else(malformed)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
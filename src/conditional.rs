@@ -0,0 +1,79 @@
+//! Writes that only go ahead when the key is in the state the caller last
+//! saw, for read-modify-write cycles that must not lose a concurrent update.
+
+use crate::{ActionKv, ByteStr, ByteString, Error, Result};
+
+/// What a conditional write expects to find.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Condition<'a> {
+    /// The current value, `None` meaning absent.
+    Value(Option<&'a ByteStr>),
+    /// The current version.
+    Version(u64),
+    /// Still absent since `ActionKv::absence` returned this. An absent key
+    /// has no version to compare, so this holds only while the key's index
+    /// entry, if an expired one is left, is the same and no key at all has
+    /// left the index, as a deleted one would.
+    Absent { seq: Option<u64>, removals: u64 },
+}
+
+impl ActionKv {
+    /// The version of `key`, which goes up with every write. Versions are
+    /// taken from the seq of the record that set the value, so one is never
+    /// handed out twice, even to a key that was deleted and written again.
+    pub fn version(&self, key: &ByteStr) -> Option<u64> {
+        self.live_entry(key).map(|entry| entry.version)
+    }
+
+    /// The value of `key` together with its version.
+    pub fn get_versioned(&self, key: &ByteStr) -> Result<Option<(ByteString, u64)>> {
+        let entry = match self.live_entry(key) {
+            None => return Ok(None),
+            Some(entry) => entry,
+        };
+        let kv = self.get_at(entry.segment, entry.offset)?;
+        Ok(Some((kv.value, entry.version)))
+    }
+
+    /// The condition that `key`, which has no live value, stays absent.
+    pub(crate) fn absence(&self, key: &ByteStr) -> Condition<'static> {
+        Condition::Absent { seq: self.index.get(key).map(|entry| entry.seq), removals: self.removals }
+    }
+
+    /// Fails with `Error::Conflict` unless `key` meets `condition`.
+    pub(crate) fn check(&self, key: &ByteStr, condition: Condition) -> Result<()> {
+        let version = self.version(key);
+        let holds = match condition {
+            Condition::Value(expected) => self.get(key)?.as_deref() == expected,
+            Condition::Version(expected) => version == Some(expected),
+            Condition::Absent { seq, removals } => self.index.get(key).map(|entry| entry.seq) == seq && self.removals == removals,
+        };
+        if holds {
+            Ok(())
+        } else {
+            Err(Error::Conflict { key: key.to_vec(), version })
+        }
+    }
+
+    /// Replaces the value of `key` with `new` if it currently is `expected`.
+    /// `None` stands for an absent key on either side, so this can also
+    /// create or delete the key.
+    pub fn compare_and_swap(&mut self, key: &ByteStr, expected: Option<&ByteStr>, new: Option<&ByteStr>) -> Result<()> {
+        self.check(key, Condition::Value(expected))?;
+        match new {
+            Some(value) => self.insert(key, value),
+            None if expected.is_some() => self.delete(key),
+            None => Ok(()),
+        }
+    }
+
+    pub fn insert_if_absent(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.compare_and_swap(key, None, Some(value))
+    }
+
+    /// Sets `key` to `value` if it is still at `version`.
+    pub fn update_if_version(&mut self, key: &ByteStr, version: u64, value: &ByteStr) -> Result<()> {
+        self.check(key, Condition::Version(version))?;
+        self.insert(key, value)
+    }
+}
@@ -0,0 +1,109 @@
+use std::{error, fmt, io};
+
+use crate::{ByteString, Codec};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The record at `offset` failed its checksum.
+    Corruption { offset: u64, expected: u32, actual: u32 },
+    KeyTooLarge { len: usize, max: usize },
+    ValueTooLarge { len: usize, max: usize },
+    /// There is no live record for the requested key or position.
+    NotFound,
+    /// The store was closed, either explicitly or after a failed file swap.
+    Closed,
+    /// The file was written in a format this version cannot read.
+    FormatVersion { found: u16, supported: u16 },
+    /// The file does not start with a valid kstore header or record.
+    InvalidHeader(&'static str),
+    /// The file header names a checksum algorithm this version does not know.
+    UnknownChecksum(u8),
+    /// A write was attempted on a store opened read-only.
+    ReadOnly,
+    /// The combination of `ActionKvOptions` cannot be honoured.
+    InvalidOptions(&'static str),
+    /// A conditional write found `key` in another state than it expected;
+    /// `version` is the key's current version, `None` when it is absent.
+    Conflict { key: ByteString, version: Option<u64> },
+    /// A key or value could not be encoded or decoded.
+    Codec(String),
+    /// The store was written with another codec than the one it was opened
+    /// with; `found` is `None` for a store of raw bytes.
+    CodecMismatch { expected: Codec, found: Option<Codec> },
+    /// The peer sent something that is not valid in the client/server protocol.
+    Protocol(&'static str),
+    /// The server failed to carry out a request and sent back this message.
+    Server(String),
+    /// The data needs a feature this version or this file does not support.
+    Unsupported(&'static str),
+    /// A namespace by this name already exists.
+    NamespaceExists(String),
+    /// There is no namespace by this name.
+    UnknownNamespace(String),
+}
+
+impl Error {
+    /// True when a read ran off the end of the log, which is how scans
+    /// detect that there are no more records.
+    pub fn is_eof(&self) -> bool {
+        matches!(self, Error::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "i/o error: {}", err),
+            Error::Corruption { offset, expected, actual } => write!(
+                f,
+                "data corruption at offset {}: checksum {:08x} != saved checksum {:08x}",
+                offset, actual, expected
+            ),
+            Error::KeyTooLarge { len, max } => write!(f, "key of {} bytes exceeds the {} byte limit", len, max),
+            Error::ValueTooLarge { len, max } => write!(f, "value of {} bytes exceeds the {} byte limit", len, max),
+            Error::NotFound => write!(f, "key not found"),
+            Error::Closed => write!(f, "store is closed"),
+            Error::FormatVersion { found, supported } => write!(
+                f,
+                "unsupported format version {} (this build reads up to {})",
+                found, supported
+            ),
+            Error::InvalidHeader(msg) => write!(f, "invalid file header: {}", msg),
+            Error::UnknownChecksum(id) => write!(f, "unknown checksum algorithm id {}", id),
+            Error::ReadOnly => write!(f, "store is read-only"),
+            Error::InvalidOptions(msg) => write!(f, "invalid options: {}", msg),
+            Error::Conflict { key, version: Some(version) } => write!(
+                f,
+                "conflicting write to key {:?}, which is at version {}",
+                String::from_utf8_lossy(key), version
+            ),
+            Error::Conflict { key, version: None } => write!(f, "conflicting write to key {:?}, which is absent", String::from_utf8_lossy(key)),
+            Error::Codec(msg) => write!(f, "codec error: {}", msg),
+            Error::CodecMismatch { expected, found: Some(found) } => write!(f, "store is encoded with {}, not {}", found, expected),
+            Error::CodecMismatch { expected, found: None } => write!(f, "store holds raw bytes, not {}", expected),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Error::Server(msg) => write!(f, "server error: {}", msg),
+            Error::Unsupported(msg) => write!(f, "unsupported: {}", msg),
+            Error::NamespaceExists(name) => write!(f, "namespace {:?} already exists", name),
+            Error::UnknownNamespace(name) => write!(f, "no namespace named {:?}", name),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
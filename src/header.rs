@@ -0,0 +1,208 @@
+//! The header at the start of every data file created by kstore.
+//!
+//! ```text
+//!  0 magic "KSTR"
+//!  4 version        u16
+//!  6 header_len     u16  records start at this offset
+//!  8 checksum id    u8
+//!  9 codec id       u8   0 for raw bytes, see `Codec::id`
+//! 10 flags          u32
+//! 14 created_at     u64  microseconds since the Unix epoch
+//! 22 next_seq       u64  only in a 34 byte header, see `FileHeader::next_seq`
+//! 22 or 30 header checksum u32 CRC-32/CKSUM over the bytes before it
+//! ```
+//!
+//! Older layouts are still recognised so they can be migrated: version 0 is
+//! a headerless log that starts directly with a record, version 1 is the
+//! eight byte `magic | version | checksum id | reserved` header.
+
+use std::{fs::File, io::{self, BufReader, Read, Seek, SeekFrom, Write}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc::Crc;
+
+use crate::{ActionKv, Codec, Error, Result};
+
+pub const MAGIC: &[u8; 4] = b"KSTR";
+pub const FORMAT_VERSION: u16 = 2;
+pub const HEADER_LEN: u64 = 26;
+/// The length of a header that carries `next_seq`.
+pub const SEQ_HEADER_LEN: u64 = HEADER_LEN + 8;
+pub(crate) const V1_HEADER_LEN: u64 = 8;
+
+/// Flags in the high half change how records must be read, so a file
+/// carrying one this version does not know cannot be opened. Unknown flags
+/// in the low half are ignored.
+pub const INCOMPATIBLE_FLAGS_MASK: u32 = 0xffff_0000;
+/// Records may carry extra fields such as an expiry time.
+pub const FLAG_RECORD_FIELDS: u32 = 1 << 16;
+pub const KNOWN_FLAGS: u32 = FLAG_RECORD_FIELDS;
+
+/// The checksum used for every record in a data file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumAlgorithm {
+    /// CRC-32/CKSUM, the algorithm of headerless logs.
+    #[default]
+    Crc32Cksum,
+    /// CRC-32C (Castagnoli), as used by iSCSI and ext4.
+    Crc32Iscsi,
+    /// The zlib/PNG CRC-32.
+    Crc32IsoHdlc,
+}
+
+impl ChecksumAlgorithm {
+    pub fn id(self) -> u8 {
+        match self {
+            ChecksumAlgorithm::Crc32Cksum => 0,
+            ChecksumAlgorithm::Crc32Iscsi => 1,
+            ChecksumAlgorithm::Crc32IsoHdlc => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(ChecksumAlgorithm::Crc32Cksum),
+            1 => Some(ChecksumAlgorithm::Crc32Iscsi),
+            2 => Some(ChecksumAlgorithm::Crc32IsoHdlc),
+            _ => None,
+        }
+    }
+
+    pub fn checksum(self, data: &[u8]) -> u32 {
+        let algorithm = match self {
+            ChecksumAlgorithm::Crc32Cksum => &crc::CRC_32_CKSUM,
+            ChecksumAlgorithm::Crc32Iscsi => &crc::CRC_32_ISCSI,
+            ChecksumAlgorithm::Crc32IsoHdlc => &crc::CRC_32_ISO_HDLC,
+        };
+        Crc::<u32>::new(algorithm).checksum(data)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHeader {
+    pub version: u16,
+    pub checksum: ChecksumAlgorithm,
+    /// The codec of a `TypedStore`, `None` for raw bytes.
+    pub codec: Option<Codec>,
+    pub flags: u32,
+    pub created_at: SystemTime,
+    /// Every seq below this had been handed out when the file was created.
+    /// Loading never numbers records from less, so a seq stays unused even
+    /// after compaction drops every record that had it.
+    pub next_seq: Option<u64>,
+}
+
+/// What sits at the start of a data file.
+pub(crate) enum Detected {
+    Empty,
+    Current(FileHeader),
+    /// An older layout that `migrate` can upgrade; carries its version and
+    /// the offset of its first record.
+    Legacy { version: u16, checksum: ChecksumAlgorithm, data_start: u64 },
+}
+
+impl FileHeader {
+    pub fn new(checksum: ChecksumAlgorithm) -> Self {
+        FileHeader { version: FORMAT_VERSION, checksum, codec: None, flags: FLAG_RECORD_FIELDS, created_at: SystemTime::now(), next_seq: None }
+    }
+
+    /// The length of the header, where the first record starts.
+    pub fn data_start(&self) -> u64 {
+        if self.next_seq.is_some() { SEQ_HEADER_LEN } else { HEADER_LEN }
+    }
+
+    pub fn write<W: Write>(&self, f: &mut W) -> Result<()> {
+        let created_at = self.created_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+
+        let mut header = Vec::with_capacity(self.data_start() as usize);
+        header.extend_from_slice(MAGIC);
+        header.write_u16::<LittleEndian>(self.version)?;
+        header.write_u16::<LittleEndian>(self.data_start() as u16)?;
+        header.write_u8(self.checksum.id())?;
+        header.write_u8(self.codec.map_or(0, Codec::id))?;
+        header.write_u32::<LittleEndian>(self.flags)?;
+        header.write_u64::<LittleEndian>(created_at)?;
+        if let Some(next_seq) = self.next_seq {
+            header.write_u64::<LittleEndian>(next_seq)?;
+        }
+        let checksum = Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(&header);
+        header.write_u32::<LittleEndian>(checksum)?;
+
+        f.write_all(&header)?;
+        Ok(())
+    }
+
+    /// Parses a current-version header whose magic and version have already been read.
+    fn read_rest<R: Read>(f: &mut R, version: u16) -> Result<Self> {
+        let header_len = f.read_u16::<LittleEndian>()?;
+        if header_len as u64 != HEADER_LEN && header_len as u64 != SEQ_HEADER_LEN {
+            return Err(Error::InvalidHeader("unexpected header length"));
+        }
+        let mut rest = vec![0; header_len as usize - 8];
+        f.read_exact(&mut rest)?;
+
+        let mut header = Vec::with_capacity(header_len as usize);
+        header.extend_from_slice(MAGIC);
+        header.write_u16::<LittleEndian>(version)?;
+        header.write_u16::<LittleEndian>(header_len)?;
+        header.extend_from_slice(&rest);
+        let (body, saved) = header.split_at(header.len() - 4);
+        let saved = u32::from_le_bytes(saved.try_into().unwrap());
+        if Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(body) != saved {
+            return Err(Error::InvalidHeader("header checksum mismatch"));
+        }
+
+        let mut r = &rest[..];
+        let checksum_id = r.read_u8()?;
+        let checksum = ChecksumAlgorithm::from_id(checksum_id).ok_or(Error::UnknownChecksum(checksum_id))?;
+        let codec = match r.read_u8()? {
+            0 => None,
+            id => Some(Codec::from_id(id).ok_or(Error::InvalidHeader("unknown codec id"))?),
+        };
+        let flags = r.read_u32::<LittleEndian>()?;
+        if flags & INCOMPATIBLE_FLAGS_MASK & !KNOWN_FLAGS != 0 {
+            return Err(Error::InvalidHeader("unsupported incompatible flags"));
+        }
+        let created_at = UNIX_EPOCH + Duration::from_micros(r.read_u64::<LittleEndian>()?);
+        let next_seq = if header_len as u64 == SEQ_HEADER_LEN { Some(r.read_u64::<LittleEndian>()?) } else { None };
+
+        Ok(FileHeader { version, checksum, codec, flags, created_at, next_seq })
+    }
+
+    /// Works out the format of `f` from its first bytes. Anything that is
+    /// neither a kstore header nor a headerless log starting with a valid
+    /// record is rejected, so random files are never mistaken for a store.
+    pub(crate) fn detect(f: &mut File) -> Result<Detected> {
+        if f.metadata()?.len() == 0 {
+            return Ok(Detected::Empty);
+        }
+        f.seek(SeekFrom::Start(0))?;
+        let mut r = BufReader::new(f);
+
+        let mut magic = [0; 4];
+        let has_magic = match r.read_exact(&mut magic) {
+            Ok(()) => &magic == MAGIC,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(err) => return Err(err.into()),
+        };
+
+        if has_magic {
+            let version = r.read_u16::<LittleEndian>()?;
+            return match version {
+                1 => {
+                    let checksum_id = r.read_u8()?;
+                    let checksum = ChecksumAlgorithm::from_id(checksum_id).ok_or(Error::UnknownChecksum(checksum_id))?;
+                    Ok(Detected::Legacy { version, checksum, data_start: V1_HEADER_LEN })
+                },
+                FORMAT_VERSION => Ok(Detected::Current(FileHeader::read_rest(&mut r, version)?)),
+                _ => Err(Error::FormatVersion { found: version, supported: FORMAT_VERSION }),
+            };
+        }
+
+        r.seek(SeekFrom::Start(0))?;
+        match ActionKv::process_record(&mut r, 0, ChecksumAlgorithm::Crc32Cksum) {
+            Ok(_) => Ok(Detected::Legacy { version: 0, checksum: ChecksumAlgorithm::Crc32Cksum, data_start: 0 }),
+            Err(Error::Io(err)) if err.kind() != io::ErrorKind::UnexpectedEof => Err(Error::Io(err)),
+            Err(_) => Err(Error::InvalidHeader("not a kstore file")),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! The hint file is a checksummed snapshot of the index written next to the
+//! data file, so `load` only has to replay records appended after it.
+//!
+//! Layout, all integers little endian:
+//!
+//! ```text
+//! magic "KSH5" | log_end_segment u32 | log_end_offset u64 | next_seq u64
+//! anchor_segment u32 | anchor_offset u64 | anchor_checksum u32
+//! segment_count u32 | segment_count * segment_id u32 | count u64
+//! count * (key_len u32 | key | namespace u32 | segment u32 | offset u64 | len u64 | seq u64 | version u64 | expires_at u64)
+//! checksum u32 over everything above
+//! ```
+//!
+//! `log_end` is the end of the active segment when the hint was written and
+//! `segments` lists every segment it covers. `anchor_*` identify the last
+//! record covered by the hint so a data file that was rewritten or truncated
+//! underneath it is detected. An `expires_at` of `u64::MAX` means no expiry.
+//! The entries of every namespace are saved together.
+
+use std::{fs::{self, OpenOptions}, io::{self, BufWriter, Read, Write}, path::Path};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc::Crc;
+
+use crate::{ByteString, Index, IndexEntry, Result};
+
+const MAGIC: &[u8; 4] = b"KSH5";
+
+/// `I` is the indexes to save when writing, which are borrowed rather than
+/// moved, and the saved entries when reading.
+pub(crate) struct Hint<I = Vec<(ByteString, IndexEntry)>> {
+    pub segments: Vec<u32>,
+    pub log_end: (u32, u64),
+    pub next_seq: u64,
+    /// `((segment, offset), checksum)` of the last record.
+    pub anchor: Option<((u32, u64), u32)>,
+    pub index: I,
+}
+
+pub(crate) fn write(path: &Path, hint: &Hint<Vec<&Index>>) -> Result<()> {
+    let mut body = Vec::new();
+    body.extend_from_slice(MAGIC);
+    body.write_u32::<LittleEndian>(hint.log_end.0)?;
+    body.write_u64::<LittleEndian>(hint.log_end.1)?;
+    body.write_u64::<LittleEndian>(hint.next_seq)?;
+    let ((anchor_segment, anchor_offset), anchor_checksum) = hint.anchor.unwrap_or(((u32::MAX, u64::MAX), 0));
+    body.write_u32::<LittleEndian>(anchor_segment)?;
+    body.write_u64::<LittleEndian>(anchor_offset)?;
+    body.write_u32::<LittleEndian>(anchor_checksum)?;
+    body.write_u32::<LittleEndian>(hint.segments.len() as u32)?;
+    for id in &hint.segments {
+        body.write_u32::<LittleEndian>(*id)?;
+    }
+    body.write_u64::<LittleEndian>(hint.index.iter().map(|index| index.len() as u64).sum())?;
+    for (key, entry) in hint.index.iter().copied().flatten() {
+        body.write_u32::<LittleEndian>(key.len() as u32)?;
+        body.extend_from_slice(key);
+        body.write_u32::<LittleEndian>(entry.namespace)?;
+        body.write_u32::<LittleEndian>(entry.segment)?;
+        body.write_u64::<LittleEndian>(entry.offset)?;
+        body.write_u64::<LittleEndian>(entry.len)?;
+        body.write_u64::<LittleEndian>(entry.seq)?;
+        body.write_u64::<LittleEndian>(entry.version)?;
+        body.write_u64::<LittleEndian>(entry.expires_at.unwrap_or(u64::MAX))?;
+    }
+
+    let crc32 = Crc::<u32>::new(&crc::CRC_32_CKSUM);
+    let checksum = crc32.checksum(&body);
+
+    let tmp_path = crate::ActionKv::sibling_path(path, "tmp");
+    {
+        let tmp = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        let mut out = BufWriter::new(tmp);
+        out.write_all(&body)?;
+        out.write_u32::<LittleEndian>(checksum)?;
+        let tmp = out.into_inner().map_err(|err| err.into_error())?;
+        tmp.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// Reads the hint at `path`. A missing, truncated or checksum-failing hint
+/// yields `None`: it is only an accelerator and the log is always authoritative.
+pub(crate) fn read(path: &Path) -> Result<Option<Hint>> {
+    let mut data = Vec::new();
+    match fs::File::open(path) {
+        Ok(mut f) => f.read_to_end(&mut data)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    if data.len() < MAGIC.len() + 4 || &data[..MAGIC.len()] != MAGIC {
+        return Ok(None);
+    }
+    let (body, saved_checksum) = data.split_at(data.len() - 4);
+    let saved_checksum = u32::from_le_bytes(saved_checksum.try_into().unwrap());
+    let crc32 = Crc::<u32>::new(&crc::CRC_32_CKSUM);
+    if crc32.checksum(body) != saved_checksum {
+        return Ok(None);
+    }
+
+    // the checksum matched, so a short read here means a bug rather than a torn file
+    let mut r = &body[MAGIC.len()..];
+    let log_end = (r.read_u32::<LittleEndian>()?, r.read_u64::<LittleEndian>()?);
+    let next_seq = r.read_u64::<LittleEndian>()?;
+    let anchor_segment = r.read_u32::<LittleEndian>()?;
+    let anchor_offset = r.read_u64::<LittleEndian>()?;
+    let anchor_checksum = r.read_u32::<LittleEndian>()?;
+    let segment_count = r.read_u32::<LittleEndian>()?;
+    let mut segments = Vec::new();
+    for _ in 0..segment_count {
+        segments.push(r.read_u32::<LittleEndian>()?);
+    }
+    let count = r.read_u64::<LittleEndian>()?;
+
+    let mut index = Vec::new();
+    for _ in 0..count {
+        let key_len = r.read_u32::<LittleEndian>()? as usize;
+        if r.len() < key_len {
+            return Ok(None);
+        }
+        let (key, rest) = r.split_at(key_len);
+        r = rest;
+        let namespace = r.read_u32::<LittleEndian>()?;
+        let segment = r.read_u32::<LittleEndian>()?;
+        let offset = r.read_u64::<LittleEndian>()?;
+        let len = r.read_u64::<LittleEndian>()?;
+        let seq = r.read_u64::<LittleEndian>()?;
+        let version = r.read_u64::<LittleEndian>()?;
+        let expires_at = Some(r.read_u64::<LittleEndian>()?).filter(|at| *at != u64::MAX);
+        index.push((key.to_vec(), IndexEntry { segment, offset, len, seq, version, expires_at, namespace }));
+    }
+
+    let anchor = if anchor_offset == u64::MAX { None } else { Some(((anchor_segment, anchor_offset), anchor_checksum)) };
+
+    Ok(Some(Hint { segments, log_end, next_seq, anchor, index }))
+}
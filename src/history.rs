@@ -0,0 +1,109 @@
+//! Reads of the values a key had before. The log is append-only, so every
+//! record stays on disk until compaction drops it; these walk the whole log
+//! to find them.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{ActionKv, ByteStr, ByteString, Result};
+
+/// One record of a key's history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    /// The position of the record among all records appended to the store.
+    pub seq: u64,
+    /// When the record was appended, unless it predates timestamps.
+    pub written_at: Option<SystemTime>,
+    /// The value the record set, `None` for a delete.
+    pub value: Option<ByteString>,
+    /// The version the record set, `None` for a delete.
+    pub version: Option<u64>,
+    pub expires_at: Option<SystemTime>,
+}
+
+/// The point in the past `ActionKv::get_as_of` reads at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Just after the record with this sequence number was appended.
+    Seq(u64),
+    Time(SystemTime),
+}
+
+impl From<u64> for AsOf {
+    fn from(seq: u64) -> Self {
+        AsOf::Seq(seq)
+    }
+}
+
+impl From<SystemTime> for AsOf {
+    fn from(time: SystemTime) -> Self {
+        AsOf::Time(time)
+    }
+}
+
+fn to_time(micros: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_micros(micros)
+}
+
+fn to_micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_micros().min(u64::MAX as u128) as u64)
+}
+
+impl ActionKv {
+    /// The sequence number of the newest record, `None` while the log is
+    /// empty. Passed to `get_as_of` later, it reads the store as it is now.
+    pub fn last_seq(&self) -> Option<u64> {
+        self.next_seq.checked_sub(1)
+    }
+
+    /// Every record of `key` in the default namespace still in the log,
+    /// oldest first, deletes and expired values included. Compaction leaves
+    /// only the live value unless the store has a history retention window.
+    ///
+    /// Nothing indexes old records, so this reads the whole log, however
+    /// few records `key` has.
+    pub fn history(&self, key: &ByteStr) -> Result<Vec<Revision>> {
+        let mut revisions = Vec::new();
+        self.walk_log(|record| {
+            if record.namespace != 0 || record.key != key {
+                return;
+            }
+            revisions.push(Revision {
+                seq: record.seq,
+                written_at: record.meta.written_at.map(to_time),
+                version: record.value.as_ref().map(|_| record.meta.version_at(record.seq)),
+                value: record.value,
+                expires_at: record.meta.expires_at.map(to_time),
+            });
+        })?;
+        Ok(revisions)
+    }
+
+    /// The value `key` in the default namespace had at `at`, a sequence
+    /// number or a time, going by the records still in the log. A value is gone from its expiry on;
+    /// for a sequence number that is judged by when the record was
+    /// appended, and not at all if it has no timestamp. Records without a
+    /// timestamp count as older than any time.
+    ///
+    /// Like `history`, this reads the whole log.
+    pub fn get_as_of(&self, key: &ByteStr, at: impl Into<AsOf>) -> Result<Option<ByteString>> {
+        let at = at.into();
+        let mut now = match at {
+            AsOf::Seq(_) => None,
+            AsOf::Time(time) => Some(to_micros(time)),
+        };
+        let mut found = None;
+        self.walk_log(|record| {
+            match at {
+                AsOf::Seq(seq) if record.seq <= seq => now = record.meta.written_at.or(now),
+                AsOf::Time(_) if record.meta.written_at.is_none_or(|written_at| Some(written_at) <= now) => {},
+                _ => return,
+            }
+            if record.namespace == 0 && record.key == key {
+                found = Some((record.value, record.meta.expires_at));
+            }
+        })?;
+
+        let expired = |expires_at: Option<u64>| expires_at.is_some_and(|expires_at| now.is_some_and(|now| expires_at <= now));
+        Ok(found.and_then(|(value, expires_at)| value.filter(|_| !expired(expires_at))))
+    }
+}
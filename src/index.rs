@@ -0,0 +1,208 @@
+use std::{collections::{btree_map, hash_map, BTreeMap, HashMap}, ops::Bound};
+
+use crate::{ByteStr, ByteString};
+
+/// Where the live record of a key sits in the log: `(segment, offset)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// The segment holding the record; always 0 for a store kept in one file.
+    pub segment: u32,
+    pub offset: u64,
+    /// Length of the whole record, header included.
+    pub len: u64,
+    /// Position of the record in the sequence of all records ever appended.
+    pub seq: u64,
+    /// Changes with every write to the key and is never reused; one past
+    /// `seq` unless the record was written with an older version.
+    pub version: u64,
+    /// When the value expires, in microseconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+    /// The namespace of the key; 0 for the default one.
+    pub namespace: u32,
+}
+
+impl IndexEntry {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= crate::now_micros())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexKind {
+    /// Fastest point lookups; ordered scans have to sort the matching keys first.
+    #[default]
+    Hash,
+    /// Keeps keys sorted so range and prefix scans walk the index directly.
+    Ordered,
+}
+
+/// Maps every live key to the position of its latest record.
+#[derive(Debug, Clone)]
+pub enum Index {
+    Hash(HashMap<ByteString, IndexEntry>),
+    Ordered(BTreeMap<ByteString, IndexEntry>),
+}
+
+impl Index {
+    pub fn new(kind: IndexKind) -> Self {
+        match kind {
+            IndexKind::Hash => Index::Hash(HashMap::new()),
+            IndexKind::Ordered => Index::Ordered(BTreeMap::new()),
+        }
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        match self {
+            Index::Hash(_) => IndexKind::Hash,
+            Index::Ordered(_) => IndexKind::Ordered,
+        }
+    }
+
+    pub fn get(&self, key: &ByteStr) -> Option<&IndexEntry> {
+        match self {
+            Index::Hash(map) => map.get(key),
+            Index::Ordered(map) => map.get(key),
+        }
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: ByteString, entry: IndexEntry) -> Option<IndexEntry> {
+        match self {
+            Index::Hash(map) => map.insert(key, entry),
+            Index::Ordered(map) => map.insert(key, entry),
+        }
+    }
+
+    pub fn remove(&mut self, key: &ByteStr) -> Option<IndexEntry> {
+        match self {
+            Index::Hash(map) => map.remove(key),
+            Index::Ordered(map) => map.remove(key),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Index::Hash(map) => map.len(),
+            Index::Ordered(map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of keys that have not expired. Expired keys stay in the
+    /// index until they are next written or compacted away, so unlike `len`
+    /// this looks at every entry.
+    pub fn live_len(&self) -> usize {
+        let now = crate::now_micros();
+        self.iter().filter(|(_, entry)| entry.expires_at.is_none_or(|at| at > now)).count()
+    }
+
+    /// Iterates entries in no particular order (sorted for an ordered index).
+    pub fn iter(&self) -> IndexIter<'_> {
+        match self {
+            Index::Hash(map) => IndexIter::Hash(map.iter()),
+            Index::Ordered(map) => IndexIter::Ordered(map.iter()),
+        }
+    }
+
+    /// Entries whose keys fall within the bounds, in key order. A hash index
+    /// has to collect and sort the matching keys up front.
+    pub fn range<'a>(&'a self, start: Bound<&ByteStr>, end: Bound<&ByteStr>) -> Box<dyn DoubleEndedIterator<Item = (&'a ByteString, &'a IndexEntry)> + 'a> {
+        match self {
+            Index::Hash(map) => {
+                let mut matching: Vec<_> = map.iter()
+                    .filter(|(key, _)| in_bounds(key, start, end))
+                    .collect();
+                matching.sort_unstable_by(|a, b| a.0.cmp(b.0));
+                Box::new(matching.into_iter())
+            },
+            Index::Ordered(map) => {
+                // BTreeMap::range panics on inverted bounds, an empty scan is friendlier
+                if is_empty_range(start, end) {
+                    return Box::new(std::iter::empty());
+                }
+                Box::new(map.range::<ByteStr, _>((start, end)))
+            },
+        }
+    }
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index::new(IndexKind::default())
+    }
+}
+
+pub enum IndexIter<'a> {
+    Hash(hash_map::Iter<'a, ByteString, IndexEntry>),
+    Ordered(btree_map::Iter<'a, ByteString, IndexEntry>),
+}
+
+impl<'a> Iterator for IndexIter<'a> {
+    type Item = (&'a ByteString, &'a IndexEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            IndexIter::Hash(iter) => iter.next(),
+            IndexIter::Ordered(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            IndexIter::Hash(iter) => iter.size_hint(),
+            IndexIter::Ordered(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Index {
+    type Item = (&'a ByteString, &'a IndexEntry);
+    type IntoIter = IndexIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// The smallest key greater than every key starting with `prefix`, or
+/// `None` when no such key exists (empty or all-0xff prefix).
+pub(crate) fn prefix_end(prefix: &ByteStr) -> Option<ByteString> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+fn in_bounds(key: &ByteStr, start: Bound<&ByteStr>, end: Bound<&ByteStr>) -> bool {
+    let after_start = match start {
+        Bound::Included(start) => key >= start,
+        Bound::Excluded(start) => key > start,
+        Bound::Unbounded => true,
+    };
+    let before_end = match end {
+        Bound::Included(end) => key <= end,
+        Bound::Excluded(end) => key < end,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+fn is_empty_range(start: Bound<&ByteStr>, end: Bound<&ByteStr>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{ActionKv, ByteString, IndexEntry, Result};
+
+/// Lazily reads `(key, value)` pairs for a set of index entries, fetching
+/// each value from the log only when the iterator reaches it. Iterates in
+/// key order and supports `.rev()`. Expired entries are skipped.
+pub struct Iter<'a> {
+    entries: Box<dyn DoubleEndedIterator<Item = (&'a ByteString, &'a IndexEntry)> + 'a>,
+    kv: &'a ActionKv,
+}
+
+impl<'a> Iter<'a> {
+    pub(crate) fn new(entries: Box<dyn DoubleEndedIterator<Item = (&'a ByteString, &'a IndexEntry)> + 'a>, kv: &'a ActionKv) -> Self {
+        Iter { entries: Box::new(entries.filter(|(_, entry)| !entry.is_expired())), kv }
+    }
+
+    fn read(&mut self, entry: &IndexEntry) -> Result<(ByteString, ByteString)> {
+        let kv = self.kv.get_at(entry.segment, entry.offset)?;
+        Ok((kv.key, kv.value))
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(ByteString, ByteString)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, entry) = self.entries.next()?;
+        Some(self.read(entry))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (_, entry) = self.entries.next_back()?;
+        Some(self.read(entry))
+    }
+}
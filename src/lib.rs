@@ -1,7 +1,54 @@
-use std::{fs::{File, OpenOptions}, collections::HashMap, io::{self, BufReader, SeekFrom, Seek, Read, BufWriter, Write}, path::Path };
+use std::{collections::BTreeMap, fs::{self, File, OpenOptions}, ffi::OsString, io::{self, BufReader, Read, BufWriter, Write, Seek, SeekFrom}, ops::{Bound, RangeBounds}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant, SystemTime, UNIX_EPOCH} };
 use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
 use serde_derive::{Serialize, Deserialize};
-use crc::Crc;
+
+use mmap::Mmap;
+use namespace::{Namespaces, CATALOG};
+use pread::ReadAt;
+use segment::Segment;
+
+mod batch;
+pub mod client;
+mod codec;
+mod compaction;
+mod compression;
+mod conditional;
+mod error;
+mod header;
+mod hint;
+mod history;
+mod index;
+mod iter;
+mod migrate;
+mod mmap;
+mod namespace;
+mod options;
+mod pread;
+mod protocol;
+mod segment;
+pub mod server;
+mod shared;
+mod snapshot;
+mod transaction;
+mod typed;
+
+pub use batch::WriteBatch;
+pub use codec::Codec;
+pub use compaction::{CompactionPolicy, SegmentStats};
+pub use compression::Compression;
+pub use error::{Error, Result};
+pub use header::{ChecksumAlgorithm, FileHeader, FORMAT_VERSION};
+pub use history::{AsOf, Revision};
+pub use index::{Index, IndexEntry, IndexIter, IndexKind};
+pub use iter::Iter;
+pub use migrate::{migrate, migrate_in_place, MigrationReport};
+pub use mmap::Bytes;
+pub use namespace::{Namespace, NamespaceOptions};
+pub use options::{ActionKvOptions, Durability};
+pub use shared::SharedKv;
+pub use snapshot::{Snapshot, SnapshotIter};
+pub use transaction::Transaction;
+pub use typed::TypedStore;
 
 type ByteString = Vec<u8>;
 type ByteStr = [u8];
@@ -12,165 +59,1413 @@ pub struct KeyValuePair {
     value: ByteString
 }
 
+// a record whose value length is this sentinel deletes its key
+const TOMBSTONE: u32 = u32::MAX;
+// a record whose key length is this sentinel is a batch frame: its value
+// is a run of ordinary records covered by the frame's checksum
+const BATCH: u32 = u32::MAX;
+// a record whose key length is this sentinel carries extra fields: its value
+// is `fields u8 | field values | key_len u32 | value_len u32 | key | value`
+// and files holding one are marked with `header::FLAG_RECORD_FIELDS`
+const EXTENDED: u32 = u32::MAX - 1;
+
+// bits of the `fields` byte of an extended record, in the order the values follow it
+const FIELD_EXPIRES_AT: u8 = 1;
+const FIELD_VERSION: u8 = 2;
+const FIELD_SEQ: u8 = 4;
+const FIELD_WRITTEN_AT: u8 = 8;
+const FIELD_NAMESPACE: u8 = 16;
+const KNOWN_FIELDS: u8 = FIELD_EXPIRES_AT | FIELD_VERSION | FIELD_SEQ | FIELD_WRITTEN_AT | FIELD_NAMESPACE;
+
+pub const MAX_KEY_LEN: usize = EXTENDED as usize - 1;
+pub const MAX_VALUE_LEN: usize = TOMBSTONE as usize - 1;
+
+/// What `ActionKv::load` found while replaying the log.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LoadReport {
+    /// Whether the index was seeded from the hint file.
+    pub used_hint: bool,
+    /// Records replayed from the log, i.e. those not covered by the hint.
+    pub records: u64,
+    /// Bytes of a torn final record that were truncated from the end of the
+    /// active segment.
+    pub discarded_bytes: u64,
+}
+
+/// The optional fields of a record. A record without any is written in the
+/// plain layout that every format version reads.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RecordMeta {
+    /// Microseconds since the Unix epoch after which the value is gone.
+    pub expires_at: Option<u64>,
+    /// The version of the key this record sets; records without one are at
+    /// one past their seq.
+    pub version: Option<u64>,
+    /// The position of the record in the log; records without one follow
+    /// the record before them.
+    pub seq: Option<u64>,
+    /// Microseconds since the Unix epoch when the record was appended.
+    pub written_at: Option<u64>,
+    /// The namespace of the key; records without one are in the default
+    /// namespace.
+    pub namespace: Option<u32>,
+}
+
+impl RecordMeta {
+    pub(crate) fn expiring_in(ttl: Duration) -> Self {
+        let expires_at = now_micros().saturating_add(ttl.as_micros().min(u64::MAX as u128) as u64);
+        RecordMeta { expires_at: Some(expires_at), ..RecordMeta::default() }
+    }
+
+    /// The fields an index entry needs its rewritten record to carry.
+    pub(crate) fn of(entry: &IndexEntry) -> Self {
+        RecordMeta {
+            expires_at: entry.expires_at,
+            version: Some(entry.version),
+            seq: Some(entry.seq),
+            written_at: None,
+            namespace: Some(entry.namespace).filter(|namespace| *namespace != 0),
+        }
+    }
+
+    /// The version a record at `seq` with these fields sets.
+    pub(crate) fn version_at(&self, seq: u64) -> u64 {
+        self.version.unwrap_or(seq + 1)
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == RecordMeta::default()
+    }
+}
+
+pub(crate) fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
+
+enum Record {
+    Value(KeyValuePair, RecordMeta),
+    Tombstone(ByteString, RecordMeta),
+    /// The records of a batch frame with their absolute offsets and lengths.
+    Batch(Vec<(u64, u64, Record)>),
+}
+
+/// A record met by `ActionKv::walk_log`.
+pub(crate) struct LogRecord {
+    pub segment: u32,
+    pub offset: u64,
+    pub len: u64,
+    pub seq: u64,
+    pub namespace: u32,
+    pub key: ByteString,
+    /// `None` for a tombstone.
+    pub value: Option<ByteString>,
+    pub meta: RecordMeta,
+}
+
+/// A log-structured store kept either in one file or, when opened on a
+/// directory, in numbered segment files of which only the newest is
+/// appended to.
 #[derive(Debug)]
 pub struct ActionKv {
-    f: File,
-    pub index: HashMap<ByteString, u64>
+    path: PathBuf,
+    segmented: bool,
+    segments: BTreeMap<u32, Segment>,
+    closed: bool,
+    max_segment_size: u64,
+    compaction: Option<CompactionPolicy>,
+    codec: Option<Codec>,
+    history_retention: Option<Duration>,
+    /// Bytes of each segment still referenced by `index`.
+    live_bytes: BTreeMap<u32, u64>,
+    /// Bytes of each segment that its last rewrite kept besides the live
+    /// records; see `SegmentStats`.
+    retained_bytes: BTreeMap<u32, u64>,
+    header: Option<FileHeader>,
+    format_version: u16,
+    read_only: bool,
+    durability: Durability,
+    unsynced_writes: u32,
+    last_sync: Instant,
+    hint_every: Option<u64>,
+    records_since_hint: u64,
+    next_seq: u64,
+    last_record: Option<(u32, u64)>,
+    /// Bumped whenever compaction moves records.
+    generation: u64,
+    /// Bumped whenever a key leaves the index, which a delete does without
+    /// leaving a version behind.
+    removals: u64,
+    /// How many live snapshots are pinned at each seq.
+    snapshots: Arc<Mutex<BTreeMap<u64, usize>>>,
+    namespaces: Namespaces,
+    loaded: bool,
+    load_report: LoadReport,
+    mmap: bool,
+    pub index: Index
 }
 
 impl ActionKv {
 
-    pub fn open(path: &Path) -> io::Result<Self>  {
-        let f = OpenOptions::new().read(true).write(true).append(true).open(path)?;
+    pub fn open(path: &Path) -> Result<Self>  {
+        ActionKv::open_with(path, &ActionKvOptions::default())
+    }
+
+    pub(crate) fn open_with(path: &Path, options: &ActionKvOptions) -> Result<Self> {
+        if options.read_only && (options.create_if_missing || options.error_if_exists) {
+            return Err(Error::InvalidOptions("a read-only store cannot create its file"));
+        }
+        if options.read_only && options.migrate {
+            return Err(Error::InvalidOptions("a read-only store cannot be migrated"));
+        }
+        if options.mmap && !cfg!(unix) {
+            return Err(Error::InvalidOptions("memory maps are only supported on unix"));
+        }
+        if options.migrate && !options.error_if_exists && path.exists() {
+            migrate::migrate_in_place(path)?;
+        }
+
+        let segmented = options.segmented || path.is_dir();
+        if options.compaction.is_some() && !segmented {
+            return Err(Error::InvalidOptions("background compaction needs a segmented store"));
+        }
+        let mut open = OpenOptions::new();
+        open.read(true);
+        if !options.read_only {
+            open.append(true);
+        }
+
+        let mut segments = BTreeMap::new();
+        if segmented {
+            if options.error_if_exists {
+                fs::create_dir(path)?;
+            } else if options.create_if_missing {
+                fs::create_dir_all(path)?;
+            }
+            for (id, segment_path) in segment::list(path)? {
+                segments.insert(id, Segment::open(id, segment_path, &open, options.read_only, true, options.checksum, options.codec)?);
+            }
+            if segments.is_empty() && !options.read_only {
+                segments.insert(0, Segment::create(0, segment::segment_path(path, 0), options.checksum, options.codec, 0)?);
+                Self::sync_parent_dir(&segment::segment_path(path, 0))?;
+            }
+        } else {
+            if options.error_if_exists {
+                open.create_new(true);
+            } else if options.create_if_missing {
+                open.create(true);
+            }
+            segments.insert(0, Segment::open(0, path.to_path_buf(), &open, options.read_only, options.write_header, options.checksum, options.codec)?);
+        }
+
+        // every segment is created with the codec of the store, so the
+        // oldest one speaks for all of them
+        let codec = segments.values().next().and_then(|segment| segment.header).and_then(|header| header.codec);
+        if let Some(expected) = options.codec {
+            if codec != Some(expected) {
+                return Err(Error::CodecMismatch { expected, found: codec });
+            }
+        }
 
-        Ok(ActionKv { f: f, index: HashMap::new() })
+        let mut store = ActionKv {
+            path: path.to_path_buf(),
+            segmented,
+            segments,
+            closed: false,
+            max_segment_size: options.max_segment_size,
+            compaction: options.compaction,
+            codec,
+            history_retention: options.history_retention,
+            live_bytes: BTreeMap::new(),
+            retained_bytes: BTreeMap::new(),
+            header: None,
+            format_version: FORMAT_VERSION,
+            read_only: options.read_only,
+            durability: options.durability,
+            unsynced_writes: 0,
+            last_sync: Instant::now(),
+            hint_every: options.hint_every,
+            records_since_hint: 0,
+            next_seq: 0,
+            last_record: None,
+            generation: 0,
+            removals: 0,
+            snapshots: Arc::default(),
+            namespaces: Namespaces::new(options.index_kind),
+            loaded: false,
+            load_report: LoadReport::default(),
+            mmap: options.mmap,
+            index: Index::new(options.index_kind),
+        };
+        store.active_changed();
+        Ok(store)
     }
 
-    pub fn load(&mut self) ->io::Result<()> {
-        let mut f = BufReader::new(&mut self.f);
-         
-        loop {
-            let current_position = f.seek(SeekFrom::Current(0))?;
-
-            let maybe_kv = ActionKv::process_record(&mut f);
-
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => {
-                    match err.kind() {
-                        io::ErrorKind::UnexpectedEof => {
-                            break;
-                        }
-                        _ => return Err(err),
-                    }
-                },
+    /// Syncs and releases the data files. Every later call returns `Error::Closed`.
+    pub fn close(&mut self) -> Result<()> {
+        if self.read_only || self.closed {
+            self.release();
+            return Ok(());
+        }
+        if self.hint_every.is_some() && self.records_since_hint > 0 {
+            self.write_hint()?;
+        }
+        let result = self.active().and_then(|active| Ok(active.f.sync_all()?));
+        self.release();
+        self.unsynced_writes = 0;
+        result
+    }
+
+    fn release(&mut self) {
+        self.segments.clear();
+        self.closed = true;
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the store is a directory of segment files rather than a single file.
+    pub fn is_segmented(&self) -> bool {
+        self.segmented
+    }
+
+    /// The ids of the segment files, oldest first; the last one is appended to.
+    pub fn segment_ids(&self) -> Vec<u32> {
+        self.segments.keys().copied().collect()
+    }
+
+    /// The combined size of all data files.
+    pub fn disk_size(&self) -> Result<u64> {
+        self.check_open()?;
+        self.segments.values().map(Segment::file_len).sum()
+    }
+
+    /// The format header of the active segment, or `None` for a file in an older layout.
+    pub fn header(&self) -> Option<FileHeader> {
+        self.header
+    }
+
+    /// The codec recorded for the store, `None` if it holds raw bytes.
+    pub fn codec(&self) -> Option<Codec> {
+        self.codec
+    }
+
+    /// The on-disk format version: `FORMAT_VERSION` for current files, 0 for
+    /// headerless logs and 1 for the first header layout. Older files are
+    /// readable as they are; `migrate_in_place` or `compact` upgrades them.
+    pub fn format_version(&self) -> u16 {
+        self.format_version
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The report of the most recent `load`.
+    pub fn load_report(&self) -> LoadReport {
+        self.load_report
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Hands any buffered writes to the operating system without waiting for the disk.
+    pub fn flush(&self) -> Result<()> {
+        Ok((&self.active()?.f).flush()?)
+    }
+
+    /// Forces every write made so far to stable storage, whatever the durability mode.
+    pub fn sync(&mut self) -> Result<()> {
+        self.sync_data()?;
+        self.mark_synced();
+        Ok(())
+    }
+
+    // older segments were synced when they were rotated out
+    pub(crate) fn sync_data(&self) -> Result<()> {
+        self.flush()?;
+        Ok(self.active()?.f.sync_data()?)
+    }
+
+    pub(crate) fn mark_synced(&mut self) {
+        self.unsynced_writes = 0;
+        self.last_sync = Instant::now();
+    }
+
+    /// Syncs if the durability mode calls for it once `pending` more writes
+    /// are counted, and reports whether it did.
+    fn sync_if_due(&self, pending: u32) -> Result<bool> {
+        let due = match self.durability {
+            Durability::SyncEveryWrite => true,
+            Durability::SyncEveryN(n) => self.unsynced_writes.saturating_add(pending) >= n,
+            Durability::SyncInterval(interval) => self.last_sync.elapsed() >= interval,
+            Durability::OsManaged => false,
+        };
+        if due {
+            self.sync_data()?;
+        }
+        Ok(due)
+    }
+
+    /// Accounts for `records` records appended at `offset` of `segment` by a detached write.
+    pub(crate) fn note_appended(&mut self, segment: u32, offset: u64, records: u64, synced: bool) {
+        self.next_seq += records;
+        self.last_record = Some((segment, offset));
+        self.records_since_hint += records;
+        if synced {
+            self.mark_synced();
+        } else {
+            self.unsynced_writes = self.unsynced_writes.saturating_add(1);
+        }
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed);
+        }
+        Ok(())
+    }
+
+    fn active(&self) -> Result<&Segment> {
+        self.check_open()?;
+        self.segments.values().next_back().ok_or(Error::Closed)
+    }
+
+    fn writable_segment(&self) -> Result<&Segment> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        self.active()
+    }
+
+    fn segment(&self, id: u32) -> Result<&Segment> {
+        self.check_open()?;
+        self.segments.get(&id).ok_or(Error::NotFound)
+    }
+
+    // refreshes what the accessors report about the active segment
+    fn active_changed(&mut self) {
+        if let Some(active) = self.segments.values().next_back() {
+            self.header = active.header;
+            self.format_version = active.format_version;
+        }
+    }
+
+    /// Whether the active segment of a segmented store has reached the
+    /// maximum segment size.
+    pub(crate) fn rotation_due(&self) -> Result<bool> {
+        if !self.segmented {
+            return Ok(false);
+        }
+        Ok(self.active()?.file_len()? >= self.max_segment_size)
+    }
+
+    /// Syncs the active segment and starts appending to a new, empty one.
+    pub fn rotate(&mut self) -> Result<()> {
+        let id = self.active()?.id + 1;
+        self.rotate_to(id)
+    }
+
+    // ids between the old and the new active segment stay free for a merge
+    pub(crate) fn rotate_to(&mut self, id: u32) -> Result<()> {
+        if !self.segmented {
+            return Err(Error::InvalidOptions("only a segmented store can rotate"));
+        }
+        let active = self.writable_segment()?;
+        active.f.sync_data()?;
+        let checksum = active.checksum;
+
+        let path = segment::segment_path(&self.path, id);
+        let segment = Segment::create(id, path.clone(), checksum, self.codec, self.next_seq)?;
+        Self::sync_parent_dir(&path)?;
+        self.segments.insert(id, segment);
+        self.active_changed();
+        self.mark_synced();
+        Ok(())
+    }
+
+    pub(crate) fn maybe_rotate(&mut self) -> Result<()> {
+        if self.rotation_due()? {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds `index` from the log. A crash part-way through an append
+    /// leaves a short or checksum-failing record at the very end of the
+    /// active segment; that tail is truncated back to the last good record
+    /// so the store keeps working, and its size is reported in the
+    /// `LoadReport`. A record like that in a sealed segment cannot come from
+    /// a crash and fails the load with `Error::Corruption`.
+    ///
+    /// When a valid hint file is present the index starts from it and only
+    /// the records appended after the hint was written are replayed.
+    /// A read-only store skips a torn tail instead of truncating it.
+    pub fn load(&mut self) -> Result<LoadReport> {
+        self.check_open()?;
+        for segment in self.segments.values_mut() {
+            segment.unmap();
+        }
+        let mut report = LoadReport::default();
+
+        self.index = Index::new(self.index.kind());
+        self.namespaces = Namespaces::new(self.index.kind());
+        self.next_seq = 0;
+        self.last_record = None;
+        self.records_since_hint = 0;
+
+        let mut resume_at = None;
+        if let Some(hint) = hint::read(&self.hint_path())? {
+            if self.hint_matches(&hint)? {
+                resume_at = Some(hint.log_end);
+                self.next_seq = hint.next_seq;
+                self.last_record = hint.anchor.map(|(position, _)| position);
+                self.restore_indexes(hint.index)?;
+                report.used_hint = true;
+            }
+        }
+
+        let last_segment = self.segments.keys().next_back().copied();
+        for segment in self.segments.values() {
+            let start = match resume_at {
+                Some((id, _)) if segment.id < id => continue,
+                Some((id, offset)) if segment.id == id => offset,
+                _ => segment.data_start,
             };
-            self.index.insert(kv.key, current_position);
+            let file_len = segment.file_len()?;
+            let mut f = BufReader::new(ReadAt::new(&segment.f, start));
+
+            let mut torn_at = None;
+            loop {
+                let current_position = f.stream_position()?;
+                if current_position >= file_len {
+                    break;
+                }
+
+                let maybe_record = ActionKv::process_record(&mut f, current_position, segment.checksum);
+
+                let record = match maybe_record {
+                    Ok(record) => record,
+                    Err(err) if err.is_eof() => {
+                        torn_at = Some(current_position);
+                        break;
+                    },
+                    // a bad checksum only means a torn write when nothing follows it
+                    Err(Error::Corruption { .. }) if f.stream_position()? == file_len => {
+                        torn_at = Some(current_position);
+                        break;
+                    },
+                    Err(err) => return Err(err),
+                };
+                let len = f.stream_position()? - current_position;
+                report.records += ActionKv::apply_record(&mut self.index, &mut self.namespaces, &mut self.next_seq, segment.id, current_position, len, record)?;
+                self.last_record = Some((segment.id, current_position));
+            }
+
+            if let Some(position) = torn_at {
+                // only appends to the active segment can be cut short; anything
+                // that looks torn in a sealed one was damaged afterwards, and
+                // truncating it would drop every record after the damage
+                if Some(segment.id) != last_segment {
+                    let saved = ReadAt::new(&segment.f, position).read_u32::<LittleEndian>().unwrap_or(0);
+                    return Err(Error::Corruption { offset: position, expected: saved, actual: saved });
+                }
+                if !self.read_only {
+                    segment.f.set_len(position)?;
+                    segment.f.sync_data()?;
+                }
+                report.discarded_bytes += file_len - position;
+            }
+            // records copied by a merge come after newer ones in the log, so
+            // this is only settled once the segment has been replayed
+            if let Some(next_seq) = segment.header.and_then(|header| header.next_seq) {
+                self.next_seq = self.next_seq.max(next_seq);
+            }
+        }
+
+        self.recount_live_bytes();
+        self.retained_bytes.clear();
+        self.records_since_hint += report.records;
+        self.loaded = true;
+        self.load_report = report;
+
+        Ok(report)
+    }
+
+    // returns the number of records applied, counting each record of a batch;
+    // records of a dropped namespace are skipped
+    fn apply_record(index: &mut Index, namespaces: &mut Namespaces, next_seq: &mut u64, segment: u32, offset: u64, len: u64, record: Record) -> Result<u64> {
+        match record {
+            Record::Value(kv, meta) => {
+                let seq = ActionKv::take_seq(next_seq, &meta);
+                let namespace = meta.namespace.unwrap_or(0);
+                if namespace == CATALOG {
+                    namespaces.apply(&kv.key, Some(&kv.value))?;
+                }
+                if let Some(index) = namespaces.index_mut(index, namespace) {
+                    let version = meta.version_at(seq);
+                    index.insert(kv.key, IndexEntry { segment, offset, len, seq, version, expires_at: meta.expires_at, namespace });
+                }
+                Ok(1)
+            },
+            Record::Tombstone(key, meta) => {
+                ActionKv::take_seq(next_seq, &meta);
+                let namespace = meta.namespace.unwrap_or(0);
+                if namespace == CATALOG {
+                    namespaces.apply(&key, None)?;
+                }
+                if let Some(index) = namespaces.index_mut(index, namespace) {
+                    index.remove(&key);
+                }
+                Ok(1)
+            },
+            Record::Batch(records) => {
+                records.into_iter()
+                    .map(|(offset, len, record)| ActionKv::apply_record(index, namespaces, next_seq, segment, offset, len, record))
+                    .sum()
+            },
+        }
+    }
+
+    // seeds the indexes from the entries of a hint, reading back the catalog
+    // records to learn the namespaces
+    fn restore_indexes(&mut self, entries: Vec<(ByteString, IndexEntry)>) -> Result<()> {
+        let (catalog, entries): (Vec<_>, Vec<_>) = entries.into_iter().partition(|(_, entry)| entry.namespace == CATALOG);
+        for (name, entry) in catalog {
+            let value = self.get_at(entry.segment, entry.offset)?.value;
+            self.namespaces.apply(&name, Some(&value))?;
+            self.namespaces.catalog.insert(name, entry);
+        }
+        for (key, entry) in entries {
+            if let Some(index) = self.namespaces.index_mut(&mut self.index, entry.namespace) {
+                index.insert(key, entry);
+            }
         }
         Ok(())
-    }   
+    }
+
+    // the seq of a record met while replaying the log
+    fn take_seq(next_seq: &mut u64, meta: &RecordMeta) -> u64 {
+        let seq = meta.seq.unwrap_or(*next_seq);
+        *next_seq = seq + 1;
+        seq
+    }
+
+    fn hint_path(&self) -> PathBuf {
+        ActionKv::hint_path_for(&self.path, self.segmented)
+    }
+
+    pub(crate) fn hint_path_for(path: &Path, segmented: bool) -> PathBuf {
+        if segmented {
+            path.join("hint")
+        } else {
+            ActionKv::sibling_path(path, "hint")
+        }
+    }
+
+    fn hint_matches(&self, hint: &hint::Hint) -> Result<bool> {
+        let (end_segment, log_len) = hint.log_end;
+        // segments are never reused, so the hint still describes every one up to its end
+        let covered: Vec<u32> = self.segments.range(..=end_segment).map(|(id, _)| *id).collect();
+        if covered != hint.segments {
+            return Ok(false);
+        }
+        let segment = match self.segments.get(&end_segment) {
+            Some(segment) => segment,
+            None => return Ok(false),
+        };
+        if log_len > segment.file_len()? {
+            return Ok(false);
+        }
+        let ((anchor_segment, offset), checksum) = match hint.anchor {
+            Some(anchor) => anchor,
+            None => return Ok(log_len == segment.data_start),
+        };
+        if !covered.contains(&anchor_segment) || (anchor_segment == end_segment && offset >= log_len) {
+            return Ok(false);
+        }
+        Ok(self.checksum_at(anchor_segment, offset)? == checksum)
+    }
+
+    fn checksum_at(&self, segment: u32, offset: u64) -> Result<u32> {
+        Ok(ReadAt::new(&self.segment(segment)?.f, offset).read_u32::<LittleEndian>()?)
+    }
+
+    /// Persists the current index as the hint file so the next `load` can
+    /// skip replaying the log up to this point. Does nothing until `load`
+    /// has run, since the index would not describe the whole log.
+    pub fn write_hint(&mut self) -> Result<()> {
+        if !self.loaded {
+            return Ok(());
+        }
+        self.store_hint()?;
+        self.records_since_hint = 0;
+        Ok(())
+    }
+
+    pub(crate) fn store_hint(&self) -> Result<()> {
+        let active = self.writable_segment()?;
+        let log_end = (active.id, active.file_len()?);
+        let anchor = match self.last_record {
+            Some((segment, offset)) => Some(((segment, offset), self.checksum_at(segment, offset)?)),
+            None => None,
+        };
+        let hint = hint::Hint {
+            segments: self.segment_ids(),
+            log_end,
+            next_seq: self.next_seq,
+            anchor,
+            index: self.indexes().collect(),
+        };
+        hint::write(&self.hint_path(), &hint)
+    }
+
+    /// Points `key` at `entry` in the index of its namespace, keeping the
+    /// live byte counts in step.
+    pub(crate) fn index_insert(&mut self, key: ByteString, entry: IndexEntry) {
+        let Some(index) = self.namespaces.index_mut(&mut self.index, entry.namespace) else {
+            return;
+        };
+        let old = index.insert(key, entry);
+        *self.live_bytes.entry(entry.segment).or_default() += entry.len;
+        if let Some(old) = old {
+            self.forget_live(old);
+        }
+    }
+
+    pub(crate) fn index_remove(&mut self, namespace: u32, key: &ByteStr) {
+        let old = self.namespaces.index_mut(&mut self.index, namespace).and_then(|index| index.remove(key));
+        if let Some(old) = old {
+            self.forget_live(old);
+            self.removals += 1;
+        }
+    }
+
+    pub(crate) fn forget_live(&mut self, entry: IndexEntry) {
+        if let Some(live) = self.live_bytes.get_mut(&entry.segment) {
+            *live = live.saturating_sub(entry.len);
+        }
+    }
+
+    fn recount_live_bytes(&mut self) {
+        let mut live_bytes = BTreeMap::new();
+        for (_, entry) in self.indexes().flatten() {
+            *live_bytes.entry(entry.segment).or_default() += entry.len;
+        }
+        self.live_bytes = live_bytes;
+    }
+
+    pub(crate) fn hint_due(&self) -> bool {
+        matches!(self.hint_every, Some(every) if self.loaded && self.records_since_hint >= every)
+    }
+
+    pub(crate) fn hint_written(&mut self) {
+        self.records_since_hint = 0;
+    }
 
-    fn process_record<R: Read>(f: &mut R) -> io::Result<KeyValuePair>{
+    pub(crate) fn process_record<R: Read>(f: &mut R, position: u64, algorithm: ChecksumAlgorithm) -> Result<Record>{
         let saved_checksum = f.read_u32::<LittleEndian>()?;
         let key_len = f.read_u32::<LittleEndian>()?;
         let value_len = f.read_u32::<LittleEndian>()?;
-        let data_len = key_len + value_len;
-        let mut data = ByteString::with_capacity(value_len as usize);
+        let is_batch = key_len == BATCH;
+        let is_extended = key_len == EXTENDED;
+        let is_tombstone = !is_batch && !is_extended && value_len == TOMBSTONE;
+        let data_len = if is_batch || is_extended {
+            value_len as u64
+        } else if is_tombstone {
+            key_len as u64
+        } else {
+            key_len as u64 + value_len as u64
+        };
+        // no pre-allocation: a torn header can claim an arbitrary length
+        let mut data = ByteString::new();
         {
-            f.by_ref().take(data_len as u64).read_to_end(&mut data);
+            f.by_ref().take(data_len).read_to_end(&mut data)?;
+        }
+        if data.len() as u64 != data_len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
         }
-        debug_assert_eq!(data.len(), data_len as usize);
 
-        let crc32 = Crc::<u32>::new(&crc::CRC_32_CKSUM);
-        let checksum = crc32.checksum(&data);
-        
+        let checksum = algorithm.checksum(&data);
+
         if checksum != saved_checksum {
-            panic!("data corruption current checksum {:08x} != {:08x} saved_checksum", checksum, saved_checksum)
+            return Err(Error::Corruption { offset: position, expected: saved_checksum, actual: checksum });
+        }
+
+        if is_batch {
+            return ActionKv::process_batch(&data, position + 12, saved_checksum, algorithm);
+        }
+        if is_tombstone {
+            return Ok(Record::Tombstone(data, RecordMeta::default()));
+        }
+        if is_extended {
+            let (meta, key_start, key_len, value_len) = ActionKv::parse_extended(&data, position, saved_checksum)?;
+            let mut key = data.split_off(key_start);
+            return Ok(match value_len {
+                Some(_) => {
+                    let value = key.split_off(key_len);
+                    Record::Value(KeyValuePair { key, value }, meta)
+                },
+                None => Record::Tombstone(key, meta),
+            });
         }
 
         let value = data.split_off(key_len as usize);
         let key = data;
 
-        Ok(KeyValuePair { key, value })
+        Ok(Record::Value(KeyValuePair { key, value }, RecordMeta::default()))
+    }
+
+    /// Reads the fields of an extended record's payload and returns them with
+    /// the offset of the key, the key length and the value length, which is
+    /// `None` for a tombstone.
+    fn parse_extended(payload: &ByteStr, position: u64, checksum: u32) -> Result<(RecordMeta, usize, usize, Option<usize>)> {
+        // the checksum matched, so a payload that does not parse was written that way
+        let malformed = || Error::Corruption { offset: position, expected: checksum, actual: checksum };
+        let mut r = payload;
+
+        let fields = r.read_u8().map_err(|_| malformed())?;
+        if fields & !KNOWN_FIELDS != 0 {
+            return Err(Error::Unsupported("record has fields this version does not know"));
+        }
+        let mut meta = RecordMeta::default();
+        if fields & FIELD_EXPIRES_AT != 0 {
+            meta.expires_at = Some(r.read_u64::<LittleEndian>().map_err(|_| malformed())?);
+        }
+        if fields & FIELD_VERSION != 0 {
+            meta.version = Some(r.read_u64::<LittleEndian>().map_err(|_| malformed())?);
+        }
+        if fields & FIELD_SEQ != 0 {
+            meta.seq = Some(r.read_u64::<LittleEndian>().map_err(|_| malformed())?);
+        }
+        if fields & FIELD_WRITTEN_AT != 0 {
+            meta.written_at = Some(r.read_u64::<LittleEndian>().map_err(|_| malformed())?);
+        }
+        if fields & FIELD_NAMESPACE != 0 {
+            let namespace = r.read_u64::<LittleEndian>().map_err(|_| malformed())?;
+            meta.namespace = Some(u32::try_from(namespace).map_err(|_| malformed())?);
+        }
+        let key_len = r.read_u32::<LittleEndian>().map_err(|_| malformed())? as usize;
+        let value_len = match r.read_u32::<LittleEndian>().map_err(|_| malformed())? {
+            TOMBSTONE => None,
+            len => Some(len as usize),
+        };
+        if r.len() != key_len + value_len.unwrap_or(0) {
+            return Err(malformed());
+        }
+
+        Ok((meta, payload.len() - r.len(), key_len, value_len))
     }
 
-    pub fn seek_to_end(&mut self) -> io::Result<u64> {
-        self.f.seek(SeekFrom::End(0))
+    fn process_batch(data: &ByteStr, start: u64, frame_checksum: u32, algorithm: ChecksumAlgorithm) -> Result<Record> {
+        let mut cursor = io::Cursor::new(data);
+        let mut records = Vec::new();
+
+        while (cursor.position() as usize) < data.len() {
+            let relative = cursor.position();
+            let position = start + relative;
+            // the frame checksum already matched, so a record that does not
+            // parse was written that way rather than torn
+            let record = match ActionKv::process_record(&mut cursor, position, algorithm) {
+                Ok(Record::Batch(_)) => return Err(Error::Corruption { offset: position, expected: frame_checksum, actual: frame_checksum }),
+                Ok(record) => record,
+                Err(err) if err.is_eof() => return Err(Error::Corruption { offset: position, expected: frame_checksum, actual: frame_checksum }),
+                Err(err) => return Err(err),
+            };
+            records.push((position, cursor.position() - relative, record));
+        }
+
+        Ok(Record::Batch(records))
     }
 
-    pub fn get(&mut self, key : &ByteStr) -> io::Result<Option<ByteString>> {
-        let position = match self.index.get(key) {
+    /// Appends a record to `f`; a `None` value writes a tombstone for `key`.
+    /// A record with fields set in `meta` is written as an extended record.
+    fn write_record<W: Write>(f: &mut W, key: &ByteStr, value: Option<&ByteStr>, meta: &RecordMeta, algorithm: ChecksumAlgorithm) -> Result<u64> {
+        let key_len = key.len();
+        let value_len = value.map_or(0, |value| value.len());
+
+        if key_len > MAX_KEY_LEN {
+            return Err(Error::KeyTooLarge { len: key_len, max: MAX_KEY_LEN });
+        }
+        if value_len > MAX_VALUE_LEN {
+            return Err(Error::ValueTooLarge { len: value_len, max: MAX_VALUE_LEN });
+        }
+        let stored_value_len = if value.is_some() { value_len as u32 } else { TOMBSTONE };
+
+        let mut temp = ByteString::with_capacity(key_len + value_len);
+        let (key_field, value_field) = if meta.is_empty() {
+            (key_len as u32, stored_value_len)
+        } else {
+            let present = [
+                (FIELD_EXPIRES_AT, meta.expires_at),
+                (FIELD_VERSION, meta.version),
+                (FIELD_SEQ, meta.seq),
+                (FIELD_WRITTEN_AT, meta.written_at),
+                (FIELD_NAMESPACE, meta.namespace.map(u64::from)),
+            ];
+            temp.write_u8(present.iter().filter(|(_, field)| field.is_some()).fold(0, |fields, (bit, _)| fields | bit))?;
+            for field in present.iter().filter_map(|(_, field)| *field) {
+                temp.write_u64::<LittleEndian>(field)?;
+            }
+            temp.write_u32::<LittleEndian>(key_len as u32)?;
+            temp.write_u32::<LittleEndian>(stored_value_len)?;
+            let payload_len = temp.len() + key_len + value_len;
+            if payload_len > u32::MAX as usize {
+                return Err(Error::ValueTooLarge { len: value_len, max: u32::MAX as usize - (payload_len - value_len) });
+            }
+            (EXTENDED, payload_len as u32)
+        };
+        temp.extend_from_slice(key);
+        if let Some(value) = value {
+            temp.extend_from_slice(value);
+        }
+
+        let checksum = algorithm.checksum(&temp);
+
+        f.write_u32::<LittleEndian>(checksum)?;
+        f.write_u32::<LittleEndian>(key_field)?;
+        f.write_u32::<LittleEndian>(value_field)?;
+        f.write_all(&temp)?;
+
+        Ok(12 + temp.len() as u64)
+    }
+
+    /// The end of the active segment.
+    pub fn seek_to_end(&mut self) -> Result<u64> {
+        Ok((&self.active()?.f).seek(SeekFrom::End(0))?)
+    }
+
+    /// The index entry of `key` unless it is missing or expired.
+    fn live_entry(&self, key: &ByteStr) -> Option<IndexEntry> {
+        self.index.get(key).filter(|entry| !entry.is_expired()).copied()
+    }
+
+    /// Whether `key` has a value that has not expired.
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.live_entry(key).is_some()
+    }
+
+    /// The time `key` has left before it expires, `None` when it never
+    /// does, or `Error::NotFound` if it has no live value.
+    pub fn ttl(&self, key: &ByteStr) -> Result<Option<Duration>> {
+        let entry = self.live_entry(key).ok_or(Error::NotFound)?;
+        Ok(entry.expires_at.map(|at| Duration::from_micros(at.saturating_sub(now_micros()))))
+    }
+
+    pub fn get(&self, key : &ByteStr) -> Result<Option<ByteString>> {
+        let entry = match self.live_entry(key) {
             None => return Ok(None),
-            Some(position) => *position,
+            Some(entry) => entry,
         };
 
-        let kv = self.get_at(position)?;
+        let kv = self.get_at(entry.segment, entry.offset)?;
 
-        Ok(Some(ByteString::from(kv.value)))
+        Ok(Some(kv.value))
     }
 
-    pub fn get_at(&mut self, position: u64) -> io::Result<KeyValuePair> {
-        let mut f = BufReader::new(&mut self.f);
-        f.seek(SeekFrom::Start(position))?;
+    /// Like `get`, but with `ActionKvOptions::mmap` the value is borrowed
+    /// from the mapped log instead of copied out of it.
+    pub fn get_bytes(&self, key: &ByteStr) -> Result<Option<Bytes>> {
+        let entry = match self.live_entry(key) {
+            None => return Ok(None),
+            Some(entry) => entry,
+        };
 
-        let kv = ActionKv::process_record(&mut f)?;
+        if !self.mmap {
+            return Ok(Some(self.get_at(entry.segment, entry.offset)?.value.into()));
+        }
+        let (map, key_start, key_len, value_len) = self.read_mapped(self.segment(entry.segment)?, entry.offset)?;
+        Ok(Some(Bytes::mapped(map, key_start + key_len, value_len)))
+    }
 
-        Ok(kv)
+    pub fn get_at(&self, segment: u32, position: u64) -> Result<KeyValuePair> {
+        let segment = self.segment(segment)?;
+        if !self.mmap {
+            return ActionKv::read_record(&segment.f, position, segment.checksum);
+        }
+        let (map, key_start, key_len, value_len) = self.read_mapped(segment, position)?;
+        let data = &map.as_slice()[key_start..key_start + key_len + value_len];
+        let (key, value) = data.split_at(key_len);
+        Ok(KeyValuePair { key: key.to_vec(), value: value.to_vec() })
     }
 
-    pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
-        let mut f = BufReader::new(&mut self.f);
+    /// Checks the record at `position` in the mapped segment and returns the
+    /// mapping with the offset of its key and the key and value lengths.
+    fn read_mapped(&self, segment: &Segment, position: u64) -> Result<(Arc<Mmap>, usize, usize, usize)> {
+        let data_start = position + 12;
+        let map = segment.mapping(data_start)?;
+        let mut header = &map.as_slice()[position as usize..data_start as usize];
+        let saved_checksum = header.read_u32::<LittleEndian>()?;
+        let key_len = header.read_u32::<LittleEndian>()?;
+        let value_len = header.read_u32::<LittleEndian>()?;
+        let is_extended = key_len == EXTENDED;
+        if key_len == BATCH || (!is_extended && value_len == TOMBSTONE) {
+            return Err(Error::NotFound);
+        }
 
-        let mut found: Option<(u64, ByteString)> = None;
-        
-        loop {
-            let position = f.seek(SeekFrom::Current(0))?;
-
-            let maybe_kv = ActionKv::process_record(&mut f);
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => {
-                    match err.kind() {
-                        io::ErrorKind::UnexpectedEof => {
-                            break;
-                        },
-                        _ => return Err(err),
-                    }
-                },
+        let data_end = data_start + if is_extended { value_len as u64 } else { key_len as u64 + value_len as u64 };
+        let map = segment.mapping(data_end)?;
+        let data = &map.as_slice()[data_start as usize..data_end as usize];
+        let checksum = segment.checksum.checksum(data);
+        if checksum != saved_checksum {
+            return Err(Error::Corruption { offset: position, expected: saved_checksum, actual: checksum });
+        }
+
+        if is_extended {
+            return match ActionKv::parse_extended(data, position, saved_checksum)? {
+                (_, key_start, key_len, Some(value_len)) => Ok((map, data_start as usize + key_start, key_len, value_len)),
+                (_, _, _, None) => Err(Error::NotFound),
             };
-            if kv.key == target {
-                found = Some((position, kv.value));
-            }
         }
+        Ok((map, data_start as usize, key_len as usize, value_len as usize))
+    }
 
+    pub(crate) fn read_record(f: &File, position: u64, algorithm: ChecksumAlgorithm) -> Result<KeyValuePair> {
+        match ActionKv::read_any(f, position, algorithm)?.0 {
+            Record::Value(kv, _) => Ok(kv),
+            Record::Tombstone(..) | Record::Batch(_) => Err(Error::NotFound),
+        }
+    }
+
+    /// The record at `position`, whatever its kind, and its length.
+    pub(crate) fn read_any(f: &File, position: u64, algorithm: ChecksumAlgorithm) -> Result<(Record, u64)> {
+        let mut f = BufReader::new(ReadAt::new(f, position));
+        let record = ActionKv::process_record(&mut f, position, algorithm)?;
+        Ok((record, f.stream_position()? - position))
+    }
+
+    /// All live pairs in key order.
+    pub fn iter(&self) -> Result<Iter<'_>> {
+        self.range::<&ByteStr, _>(..)
+    }
+
+    /// Live pairs whose keys fall within `range`, in key order, e.g.
+    /// `kv.range("user:100".."user:200")`.
+    pub fn range<K: AsRef<ByteStr>, R: RangeBounds<K>>(&self, range: R) -> Result<Iter<'_>> {
+        self.check_open()?;
+        let start = range.start_bound().map(|key| key.as_ref());
+        let end = range.end_bound().map(|key| key.as_ref());
+
+        Ok(Iter::new(self.index.range(start, end), self))
+    }
+
+    /// Live pairs whose keys start with `prefix`, in key order.
+    pub fn prefix(&self, prefix: &ByteStr) -> Result<Iter<'_>> {
+        match index::prefix_end(prefix) {
+            Some(end) => self.range::<&ByteStr, _>((Bound::Included(prefix), Bound::Excluded(end.as_slice()))),
+            None => self.range::<&ByteStr, _>((Bound::Included(prefix), Bound::Unbounded)),
+        }
+    }
+
+    /// Scans the whole log for the latest record of `target` and returns its
+    /// `(segment, offset)` with the value.
+    pub fn find(&self, target: &ByteStr) -> Result<Option<((u32, u64), ByteString)>> {
+        let mut found = None;
+        self.walk_log(|record| {
+            if record.namespace == 0 && record.key == target {
+                let expired = record.meta.expires_at.is_some_and(|at| at <= now_micros());
+                found = record.value.filter(|_| !expired).map(|value| ((record.segment, record.offset), value));
+            }
+        })?;
         Ok(found)
     }
 
-    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
-        let position = self.insert_but_ignore_index(key, value)?;
-        self.index.insert(key.to_vec(), position);
+    /// Calls `visit` with every record in the log, in the order they were
+    /// appended, with the records of a batch one by one.
+    pub(crate) fn walk_log(&self, visit: impl FnMut(LogRecord)) -> Result<()> {
+        self.walk_log_from(None, 0, visit).map(|_| ())
+    }
 
-        Ok(())
+    /// Like `walk_log`, but starting at `from`, the position `walk_segments`
+    /// stopped at, with the seq of the record there.
+    pub(crate) fn walk_log_from(&self, from: Option<(u32, u64)>, next_seq: u64, visit: impl FnMut(LogRecord)) -> Result<(Option<(u32, u64)>, u64)> {
+        self.check_open()?;
+        let segments = self.segments.values().map(|segment| (segment.id, &segment.f, segment.checksum, segment.data_start));
+        walk_segments(segments, from, next_seq, visit)
     }
 
-    pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64>{
-        let mut f = BufWriter::new(&mut self.f);
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.insert_with_meta(key, value, &RecordMeta::default())
+    }
 
-        let key_len = key.len();
-        let value_len = value.len();
+    /// Inserts `key` so that it reads as absent once `ttl` has passed. The
+    /// expiry is stored in the record, so it survives restarts, and
+    /// compaction drops the record after it. A later plain `insert` makes
+    /// the key permanent again.
+    pub fn insert_with_ttl(&mut self, key: &ByteStr, value: &ByteStr, ttl: Duration) -> Result<()> {
+        self.insert_with_meta(key, value, &RecordMeta::expiring_in(ttl))
+    }
 
-        let mut temp = ByteString::with_capacity(key_len + value_len);
+    /// Gives the current value of `key` a new `ttl` by rewriting it.
+    /// Returns whether the key had a live value.
+    pub fn expire(&mut self, key: &ByteStr, ttl: Duration) -> Result<bool> {
+        match self.get(key)? {
+            Some(value) => self.insert_with_ttl(key, &value, ttl).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    fn insert_with_meta(&mut self, key: &ByteStr, value: &ByteStr, meta: &RecordMeta) -> Result<()> {
+        let entry = self.append(key, Some(value), meta)?;
+        self.index_insert(key.to_vec(), entry);
+        self.after_append()
+    }
+
+    /// Appends `key` and `value` and returns their `(segment, offset)`.
+    pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> Result<(u32, u64)> {
+        let entry = self.append(key, Some(value), &RecordMeta::default())?;
+        self.after_append()?;
+        Ok((entry.segment, entry.offset))
+    }
+
+    fn append(&mut self, key: &ByteStr, value: Option<&ByteStr>, meta: &RecordMeta) -> Result<IndexEntry> {
+        let (entry, synced) = self.append_detached(key, value, meta)?;
+        self.note_appended(entry.segment, entry.offset, 1, synced);
+
+        Ok(entry)
+    }
+
+    /// Writes a record to the log without touching any in-memory state, so
+    /// it can run while readers hold the store. The caller publishes it with
+    /// `note_appended` and an index update; the returned flag says whether
+    /// the durability mode synced it.
+    pub(crate) fn append_detached(&self, key: &ByteStr, value: Option<&ByteStr>, meta: &RecordMeta) -> Result<(IndexEntry, bool)> {
+        let segment = self.writable_segment()?;
+        if !meta.is_empty() && !segment.record_fields {
+            return Err(Error::Unsupported("this file predates record fields; compact it or open it with ActionKvOptions::migrate"));
+        }
+        let version = self.next_seq + 1;
+        let mut meta = *meta;
+        if segment.record_fields {
+            meta.version = Some(version).filter(|_| value.is_some());
+            meta.seq = Some(self.next_seq);
+            meta.written_at = Some(now_micros());
+        }
+        let mut record = ByteString::new();
+        let len = ActionKv::write_record(&mut record, key, value, &meta, segment.checksum)?;
+        let offset = ActionKv::append_bytes(segment, &record)?;
+        let synced = self.sync_if_due(1)?;
+
+        let entry = IndexEntry {
+            segment: segment.id,
+            offset,
+            len,
+            seq: self.next_seq,
+            version,
+            expires_at: meta.expires_at,
+            namespace: meta.namespace.unwrap_or(0),
+        };
+        Ok((entry, synced))
+    }
+
+    fn append_bytes(segment: &Segment, data: &ByteStr) -> Result<u64> {
+        let mut f = &segment.f;
+        let position = f.seek(SeekFrom::End(0))?;
+        f.write_all(data)?;
+        Ok(position)
+    }
+
+    // rotation comes first so a hint written now already names the new segment
+    fn after_append(&mut self) -> Result<()> {
+        self.maybe_rotate()?;
+        self.maybe_write_hint()
+    }
 
-        for byte in key {
-            temp.push(*byte);
+    /// Appends every operation in `batch` as a single frame and syncs it
+    /// before any of its keys become visible in `index`, whatever the
+    /// durability mode. A crash mid-write loses the whole batch on `load`.
+    pub fn write_batch(&mut self, batch: &WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
         }
+        let (segment, frame_position, entries) = self.write_batch_detached(batch)?;
+        self.apply_batch(batch, segment, frame_position, entries);
+        self.after_append()
+    }
 
-        for byte in value {
-            temp.push(*byte);
+    /// Writes and syncs the frame for a non-empty `batch`, returning its
+    /// segment and offset and the index entry of each of its records.
+    pub(crate) fn write_batch_detached(&self, batch: &WriteBatch) -> Result<(u32, u64, Vec<IndexEntry>)> {
+        let segment = self.writable_segment()?;
+        let mut payload = ByteString::new();
+        let mut entries = Vec::with_capacity(batch.len());
+        let written_at = now_micros();
+        for (seq, (key, value)) in (self.next_seq..).zip(&batch.ops) {
+            let version = if value.is_some() { seq + 1 } else { 0 };
+            let meta = if segment.record_fields {
+                RecordMeta { version: Some(version).filter(|_| value.is_some()), seq: Some(seq), written_at: Some(written_at), ..RecordMeta::default() }
+            } else {
+                RecordMeta::default()
+            };
+            let offset = 12 + payload.len() as u64;
+            let len = ActionKv::write_record(&mut payload, key, value.as_deref(), &meta, segment.checksum)?;
+            entries.push(IndexEntry { segment: segment.id, offset, len, seq, version, expires_at: None, namespace: 0 });
+        }
+        if payload.len() > u32::MAX as usize {
+            return Err(Error::ValueTooLarge { len: payload.len(), max: u32::MAX as usize });
         }
 
-        let crc32 = Crc::<u32>::new(&crc::CRC_32_CKSUM);
-        let checksum = crc32.checksum(&temp);
+        let checksum = segment.checksum.checksum(&payload);
 
-        let next_byte = SeekFrom::End(0);
+        let mut frame = ByteString::with_capacity(12 + payload.len());
+        frame.write_u32::<LittleEndian>(checksum)?;
+        frame.write_u32::<LittleEndian>(BATCH)?;
+        frame.write_u32::<LittleEndian>(payload.len() as u32)?;
+        frame.extend_from_slice(&payload);
 
-        let current_position = f.seek(SeekFrom::Current(0))?;
+        let frame_position = ActionKv::append_bytes(segment, &frame)?;
+        self.sync_data()?;
 
-        f.seek(next_byte);
-        f.write_u32::<LittleEndian>(checksum)?;
-        f.write_u32::<LittleEndian>(key_len as u32);
-        f.write_u32::<LittleEndian>(value_len as u32);
-        f.write_all(&mut temp)?;
+        Ok((segment.id, frame_position, entries))
+    }
+
+    /// Publishes a batch frame written by `write_batch_detached` to `index`.
+    /// `entries` hold offsets relative to the frame.
+    pub(crate) fn apply_batch(&mut self, batch: &WriteBatch, segment: u32, frame_position: u64, entries: Vec<IndexEntry>) {
+        for ((key, value), entry) in batch.ops.iter().zip(entries) {
+            match value {
+                Some(_) => {
+                    self.index_insert(key.clone(), IndexEntry { offset: frame_position + entry.offset, ..entry });
+                },
+                None => {
+                    self.index_remove(0, key);
+                },
+            }
+        }
+        self.note_appended(segment, frame_position, batch.len() as u64, true);
+    }
 
-        Ok(current_position)
+    fn maybe_write_hint(&mut self) -> Result<()> {
+        if self.hint_due() {
+            self.write_hint()?;
+        }
+        Ok(())
     }
 
-    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
         self.insert(key,value)
     }
 
-    pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
-        self.insert(key, b"")
+    pub fn delete(&mut self, key: &ByteStr) -> Result<()> {
+        self.append(key, None, &RecordMeta::default())?;
+        self.index_remove(0, key);
+        self.after_append()
     }
 
+    /// Rewrites the log so it only holds the live record of every key in
+    /// `index`, then swaps the new files in place of the old ones. Deleted
+    /// keys are not in `index`, so their values and tombstones are dropped,
+    /// unless they fall within the history retention window or a live
+    /// snapshot still reads them.
+    /// Files in an older format come out in the current one.
+    ///
+    /// A single-file store is rewritten next to the original and renamed over
+    /// it. A segmented store writes the live records to new segments,
+    /// numbered after the existing ones, before deleting the old segments; a
+    /// crash in between leaves both, which replay to the same contents.
+    pub fn compact(&mut self) -> Result<()> {
+        let checksum = self.writable_segment()?.checksum;
+        let first_id = if self.segmented { self.active()?.id + 1 } else { 0 };
+
+        // copy records in log order so the new files keep the original write order
+        let live = self.survivors(None)?.kept;
+
+        // (id, final path, temporary path) of every new file
+        let mut outputs: Vec<(u32, PathBuf, PathBuf)> = Vec::new();
+        let mut new_index = Index::new(self.index.kind());
+        let mut new_namespaces = self.namespaces.emptied();
+        let mut last_record = None;
+        let mut written: BTreeMap<u32, u64> = BTreeMap::new();
+        {
+            let mut out: Option<(BufWriter<File>, u64)> = None;
+            let mut live = live.into_iter().peekable();
+            loop {
+                let full = match &out {
+                    Some((_, next_position)) => self.segmented && *next_position >= self.max_segment_size && live.peek().is_some(),
+                    None => true,
+                };
+                if full {
+                    if let Some((out, _)) = out.take() {
+                        out.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+                    }
+                    let id = first_id + outputs.len() as u32;
+                    let path = if self.segmented { segment::segment_path(&self.path, id) } else { self.path.clone() };
+                    let tmp_path = Self::sibling_path(&path, "compact");
+                    let tmp = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+                    // the records of the newest seqs may all be dropped, so
+                    // the header keeps them from being handed out again
+                    let header = match self.header {
+                        Some(header) if !self.segmented => FileHeader { flags: header.flags | header::FLAG_RECORD_FIELDS, next_seq: Some(self.next_seq), ..header },
+                        _ => FileHeader { codec: self.codec, next_seq: Some(self.next_seq), ..FileHeader::new(checksum) },
+                    };
+                    let mut writer = BufWriter::new(tmp);
+                    header.write(&mut writer)?;
+                    out = Some((writer, header.data_start()));
+                    outputs.push((id, path, tmp_path));
+                }
+
+                let (key, entry) = match live.next() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let (writer, next_position) = out.as_mut().expect("an output file is always open");
+                let id = first_id + outputs.len() as u32 - 1;
+                let segment = self.segment(entry.segment)?;
+                let (len, is_value) = ActionKv::copy_record(&segment.f, segment.checksum, &entry, writer, checksum)?;
+                if let Some(index) = new_namespaces.index_mut(&mut new_index, entry.namespace) {
+                    if is_value {
+                        index.insert(key, IndexEntry { segment: id, offset: *next_position, len, ..entry });
+                    } else {
+                        index.remove(&key);
+                    }
+                }
+                last_record = Some((id, *next_position));
+                *next_position += len;
+                *written.entry(id).or_default() += len;
+            }
+            if let Some((out, _)) = out {
+                out.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+            }
+        }
+
+        for (_, path, tmp_path) in &outputs {
+            fs::rename(tmp_path, path)?;
+        }
+
+        // the old handles now point at replaced or soon deleted files, so if
+        // the new ones cannot be opened the store is left closed rather than
+        // half-swapped
+        let old: Vec<PathBuf> = self.segments.values().map(|segment| segment.path.clone()).collect();
+        self.release();
+        self.index = new_index;
+        self.namespaces = new_namespaces;
+        // expired values were left out of the new index
+        self.removals += 1;
+        self.recount_live_bytes();
+        self.retained_bytes = written.into_iter()
+            .map(|(id, bytes)| (id, bytes.saturating_sub(self.live_bytes.get(&id).copied().unwrap_or(0))))
+            .collect();
+        Self::sync_parent_dir(&outputs[0].1)?;
+        if self.segmented {
+            for path in old {
+                fs::remove_file(path)?;
+            }
+            Self::sync_parent_dir(&outputs[0].1)?;
+        }
+
+        let mut open = OpenOptions::new();
+        open.read(true).append(true);
+        for (id, path, _) in outputs {
+            self.segments.insert(id, Segment::open(id, path, &open, false, true, checksum, self.codec)?);
+        }
+        self.closed = false;
+        self.active_changed();
+        self.mark_synced();
+        self.last_record = last_record;
+        self.generation += 1;
+
+        // offsets in the old hint no longer mean anything
+        if self.hint_every.is_some() {
+            self.write_hint()?;
+        } else {
+            match fs::remove_file(self.hint_path()) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(".");
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn sync_parent_dir(path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+            _ => File::open(".")?.sync_all(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+}
+
+/// Walks the records of `segments`, given as `(id, file, checksum,
+/// data_start)`, from `from` to the end of the last one, numbering them from
+/// `next_seq`. Returns where it stopped and the seq of the next record, so
+/// a walk over a log that is still being appended to can be picked up later.
+pub(crate) fn walk_segments<'a>(
+    segments: impl IntoIterator<Item = (u32, &'a File, ChecksumAlgorithm, u64)>,
+    from: Option<(u32, u64)>,
+    mut next_seq: u64,
+    mut visit: impl FnMut(LogRecord),
+) -> Result<(Option<(u32, u64)>, u64)> {
+    let mut stopped_at = from;
+    let mut segments = segments.into_iter().peekable();
+
+    while let Some((id, file, checksum, data_start)) = segments.next() {
+        let last = segments.peek().is_none();
+        let start = match from {
+            Some((from_id, _)) if id < from_id => continue,
+            Some((from_id, offset)) if id == from_id => offset,
+            _ => data_start,
+        };
+        let mut f = BufReader::new(ReadAt::new(file, start));
+
+        loop {
+            let position = f.stream_position()?;
+            stopped_at = Some((id, position));
+
+            let maybe_record = ActionKv::process_record(&mut f, position, checksum);
+            let record = match maybe_record {
+                Ok(record) => record,
+                Err(err) if err.is_eof() => break,
+                // a torn tail, as `load` sees it, when a read-only store has
+                // left it in place
+                Err(Error::Corruption { .. }) if last && f.stream_position()? == file.metadata()?.len() => break,
+                Err(err) => return Err(err),
+            };
+            let records = match record {
+                Record::Batch(records) => records,
+                record => vec![(position, f.stream_position()? - position, record)],
+            };
+            for (offset, len, record) in records {
+                let (key, value, meta) = match record {
+                    Record::Value(kv, meta) => (kv.key, Some(kv.value), meta),
+                    Record::Tombstone(key, meta) => (key, None, meta),
+                    Record::Batch(_) => unreachable!("batches do not nest"),
+                };
+                let seq = ActionKv::take_seq(&mut next_seq, &meta);
+                let namespace = meta.namespace.unwrap_or(0);
+                visit(LogRecord { segment: id, offset, len, seq, namespace, key, value, meta });
+            }
+        }
+    }
+
+    Ok((stopped_at, next_seq))
+}
+
+impl Drop for ActionKv {
+    fn drop(&mut self) {
+        // writes under an explicit sync policy should not be left behind in the page cache
+        if self.unsynced_writes > 0 && self.durability != Durability::OsManaged {
+            if let Ok(active) = self.active() {
+                let _ = active.f.sync_data();
+            }
+        }
+    }
 }
@@ -0,0 +1,180 @@
+//! Upgrades data files written in an older layout to the current format.
+
+use std::{fs::{self, File, OpenOptions}, io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write}, path::Path};
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::{header::{Detected, FileHeader, FLAG_RECORD_FIELDS, FORMAT_VERSION}, segment, ActionKv, ChecksumAlgorithm, Error, Record, Result};
+
+/// What `migrate` or `migrate_in_place` did to a data file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// The version the file was in; equal to the current version when
+    /// nothing had to be upgraded.
+    pub from_version: u16,
+    /// Records copied into the upgraded file, counting each record of a batch.
+    pub records: u64,
+    /// Bytes of a torn final record that were left behind.
+    pub discarded_bytes: u64,
+}
+
+impl MigrationReport {
+    // the report for a segmented store covers all of its segments
+    fn merge(self, other: MigrationReport) -> MigrationReport {
+        MigrationReport {
+            from_version: self.from_version.min(other.from_version),
+            records: self.records + other.records,
+            discarded_bytes: self.discarded_bytes + other.discarded_bytes,
+        }
+    }
+}
+
+/// Writes an upgraded copy of the store at `src` to the new file `dst`,
+/// leaving `src` untouched. A segmented store is copied into the new
+/// directory `dst` segment by segment.
+pub fn migrate(src: &Path, dst: &Path) -> Result<MigrationReport> {
+    if !src.is_dir() {
+        let out = OpenOptions::new().write(true).create_new(true).open(dst)?;
+        return copy_upgraded(src, out, true);
+    }
+
+    fs::create_dir(dst)?;
+    let mut report = MigrationReport { from_version: FORMAT_VERSION, ..MigrationReport::default() };
+    let segments = segment::list(src)?;
+    let last_id = segments.last().map(|(id, _)| *id);
+    for (id, path) in segments {
+        let out = OpenOptions::new().write(true).create_new(true).open(segment::segment_path(dst, id))?;
+        report = report.merge(copy_upgraded(&path, out, Some(id) == last_id)?);
+    }
+    ActionKv::sync_parent_dir(&segment::segment_path(dst, 0))?;
+    Ok(report)
+}
+
+/// Upgrades the store at `path` to the current format by rewriting it next
+/// to the original and renaming it into place. The hint file is removed
+/// because every record moves. Files already in the current format are
+/// left alone, unless they were written before records could carry fields
+/// such as an expiry; those are rewritten so they can. The same goes for
+/// each segment of a segmented store.
+pub fn migrate_in_place(path: &Path) -> Result<MigrationReport> {
+    if !path.is_dir() {
+        return migrate_file_in_place(path, &ActionKv::hint_path_for(path, false), true);
+    }
+
+    let hint_path = ActionKv::hint_path_for(path, true);
+    let mut report = MigrationReport { from_version: FORMAT_VERSION, ..MigrationReport::default() };
+    let segments = segment::list(path)?;
+    let last_id = segments.last().map(|(id, _)| *id);
+    for (id, segment_path) in segments {
+        report = report.merge(migrate_file_in_place(&segment_path, &hint_path, Some(id) == last_id)?);
+    }
+    Ok(report)
+}
+
+fn migrate_file_in_place(path: &Path, hint_path: &Path, last: bool) -> Result<MigrationReport> {
+    {
+        let mut f = File::open(path)?;
+        match FileHeader::detect(&mut f)? {
+            Detected::Empty => {
+                return Ok(MigrationReport { from_version: FORMAT_VERSION, ..MigrationReport::default() });
+            },
+            Detected::Current(header) if header.flags & FLAG_RECORD_FIELDS != 0 => {
+                return Ok(MigrationReport { from_version: FORMAT_VERSION, ..MigrationReport::default() });
+            },
+            Detected::Current(_) | Detected::Legacy { .. } => {},
+        }
+    }
+
+    let tmp_path = ActionKv::sibling_path(path, "migrate");
+    let out = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+    let report = match copy_upgraded(path, out, last) {
+        Ok(report) => report,
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        },
+    };
+
+    fs::rename(&tmp_path, path)?;
+    match fs::remove_file(hint_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {},
+    }
+    ActionKv::sync_parent_dir(path)?;
+
+    Ok(report)
+}
+
+// `last` says whether `src` is the file appends went to; as in `load`, only
+// that one can end in a torn record
+fn copy_upgraded(src: &Path, out: File, last: bool) -> Result<MigrationReport> {
+    let mut f = File::open(src)?;
+    let file_len = f.metadata()?.len();
+    let (header, from_version, data_start) = match FileHeader::detect(&mut f)? {
+        Detected::Empty => (FileHeader::new(ChecksumAlgorithm::default()), FORMAT_VERSION, 0),
+        // plain records are valid in a file that allows fields, so they
+        // are still copied as they are
+        Detected::Current(header) => (FileHeader { flags: header.flags | FLAG_RECORD_FIELDS, ..header }, header.version, header.data_start()),
+        Detected::Legacy { version, checksum, data_start } => (FileHeader::new(checksum), version, data_start),
+    };
+    let mut report = MigrationReport { from_version, ..MigrationReport::default() };
+
+    let mut out = BufWriter::new(out);
+    header.write(&mut out)?;
+
+    let mut r = BufReader::new(f);
+    r.seek(SeekFrom::Start(data_start))?;
+    let mut raw = Vec::new();
+    let mut torn_at = None;
+    loop {
+        let position = r.stream_position()?;
+        if position >= file_len {
+            break;
+        }
+
+        // records are copied byte for byte; parsing them only validates checksums
+        raw.clear();
+        let record = ActionKv::process_record(&mut Tee { inner: &mut r, copy: &mut raw }, position, header.checksum);
+        match record {
+            Ok(Record::Batch(records)) => report.records += records.len() as u64,
+            Ok(_) => report.records += 1,
+            Err(err) if err.is_eof() => {
+                torn_at = Some(position);
+                break;
+            },
+            Err(Error::Corruption { .. }) if r.stream_position()? == file_len => {
+                torn_at = Some(position);
+                break;
+            },
+            Err(err) => return Err(err),
+        }
+        out.write_all(&raw)?;
+    }
+
+    if let Some(position) = torn_at {
+        if !last {
+            r.seek(SeekFrom::Start(position))?;
+            let saved = r.read_u32::<LittleEndian>().unwrap_or(0);
+            return Err(Error::Corruption { offset: position, expected: saved, actual: saved });
+        }
+        report.discarded_bytes = file_len - position;
+    }
+
+    let out = out.into_inner().map_err(|err| err.into_error())?;
+    out.sync_all()?;
+
+    Ok(report)
+}
+
+/// Keeps a copy of everything read through it.
+struct Tee<'a, R> {
+    inner: R,
+    copy: &'a mut Vec<u8>,
+}
+
+impl<'a, R: Read> Read for Tee<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
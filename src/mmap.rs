@@ -0,0 +1,166 @@
+//! Read-only memory maps of the data file, and `Bytes`, the cheap handle to
+//! a value that `ActionKv::get_bytes` returns.
+//!
+//! The log is append-only, so a mapping of its first `len` bytes stays valid
+//! while the file grows; reads past the end of the current mapping map the
+//! file again at its new length. Handles keep the mapping they borrow from
+//! alive, so a remap never invalidates them. The file must not be truncated
+//! by another process while it is mapped.
+
+use std::{fmt, fs::File, io, ops::Deref, sync::Arc};
+
+use crate::ByteString;
+
+pub(crate) struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// the mapping is read-only and only ever handed out as `&[u8]`
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the first `len` bytes of `f`, which must be non-zero.
+    pub fn map(f: &File, len: u64) -> io::Result<Self> {
+        let len = usize::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"))?;
+        sys::map(f, len).map(|ptr| Mmap { ptr, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        sys::unmap(self.ptr, self.len);
+    }
+}
+
+impl fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mmap").field("len", &self.len).finish()
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{ffi::{c_int, c_long, c_void}, fs::File, io, os::unix::io::AsRawFd};
+
+    const PROT_READ: c_int = 1;
+    const MAP_SHARED: c_int = 1;
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: c_long) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    pub fn map(f: &File, len: usize) -> io::Result<*const u8> {
+        let ptr = unsafe { mmap(std::ptr::null_mut(), len, PROT_READ, MAP_SHARED, f.as_raw_fd(), 0) };
+        // MAP_FAILED
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ptr as *const u8)
+    }
+
+    pub fn unmap(ptr: *const u8, len: usize) {
+        unsafe {
+            munmap(ptr as *mut c_void, len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::{fs::File, io};
+
+    pub fn map(_f: &File, _len: usize) -> io::Result<*const u8> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "memory maps are only supported on unix"))
+    }
+
+    pub fn unmap(_ptr: *const u8, _len: usize) {}
+}
+
+/// A value read from the store. With `ActionKvOptions::mmap` it points
+/// straight into the mapped log; otherwise it owns a copy. Cloning is cheap
+/// either way.
+#[derive(Clone)]
+pub struct Bytes {
+    repr: Repr,
+}
+
+#[derive(Clone)]
+enum Repr {
+    Owned(Arc<ByteString>),
+    Mapped { map: Arc<Mmap>, start: usize, len: usize },
+}
+
+impl Bytes {
+    pub(crate) fn mapped(map: Arc<Mmap>, start: usize, len: usize) -> Self {
+        debug_assert!(start + len <= map.len());
+        Bytes { repr: Repr::Mapped { map, start, len } }
+    }
+
+    /// Whether the value borrows from a memory map rather than owning a copy.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.repr, Repr::Mapped { .. })
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.repr {
+            Repr::Owned(data) => data,
+            Repr::Mapped { map, start, len } => &map.as_slice()[*start..*start + *len],
+        }
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<ByteString> for Bytes {
+    fn from(data: ByteString) -> Self {
+        Bytes { repr: Repr::Owned(Arc::new(data)) }
+    }
+}
+
+impl From<Bytes> for ByteString {
+    fn from(bytes: Bytes) -> Self {
+        match bytes.repr {
+            Repr::Owned(data) => Arc::try_unwrap(data).unwrap_or_else(|data| data.to_vec()),
+            Repr::Mapped { .. } => bytes.to_vec(),
+        }
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Bytes) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Bytes {}
+
+impl PartialEq<[u8]> for Bytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,292 @@
+//! Named keyspaces that share the log of a store.
+//!
+//! Every record of a namespace carries its id as a record field; records
+//! without one belong to the default namespace, which is what the plain
+//! `ActionKv` methods read and write. Namespaces are created and dropped
+//! through records in a catalog namespace, keyed by name, whose values hold
+//! the id and options:
+//!
+//! ```text
+//! id u32 | default_ttl u64 | compression u8 | codec u8
+//! ```
+//!
+//! `default_ttl` is in microseconds, `u64::MAX` meaning none, and a codec
+//! id of 0 means none. Dropping a namespace writes a tombstone to the
+//! catalog, after which replay ignores the namespace's records until
+//! compaction removes them. An id may be handed out again once the
+//! catalog record of its namespace is compacted away, but every record
+//! left of the old namespace comes before the new one is created, so
+//! replay never mixes the two.
+
+use std::{collections::BTreeMap, ops::Bound, time::Duration};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{index, ActionKv, ByteStr, ByteString, Codec, Compression, Error, Index, IndexKind, Iter, Result, RecordMeta};
+
+/// The namespace catalog records are written to.
+pub(crate) const CATALOG: u32 = u32::MAX;
+
+/// How a namespace treats the values written to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NamespaceOptions {
+    /// The time to live of values written without one.
+    pub default_ttl: Option<Duration>,
+    /// Applied to every value before it is appended.
+    pub compression: Compression,
+    /// The codec `Namespace::get_as` and `insert_as` use.
+    pub codec: Option<Codec>,
+}
+
+impl NamespaceOptions {
+    fn encode(&self, id: u32) -> Result<ByteString> {
+        let mut out = ByteString::with_capacity(14);
+        out.write_u32::<LittleEndian>(id)?;
+        out.write_u64::<LittleEndian>(self.default_ttl.map_or(u64::MAX, |ttl| ttl.as_micros().min(u64::MAX as u128 - 1) as u64))?;
+        out.write_u8(self.compression.id())?;
+        out.write_u8(self.codec.map_or(0, Codec::id))?;
+        Ok(out)
+    }
+
+    fn decode(mut data: &ByteStr) -> Result<(u32, Self)> {
+        let malformed = |_| Error::Unsupported("malformed namespace catalog record");
+        let id = data.read_u32::<LittleEndian>().map_err(malformed)?;
+        let default_ttl = Some(data.read_u64::<LittleEndian>().map_err(malformed)?)
+            .filter(|ttl| *ttl != u64::MAX)
+            .map(Duration::from_micros);
+        let compression = Compression::from_id(data.read_u8().map_err(malformed)?)
+            .ok_or(Error::Unsupported("namespace compression this version does not know"))?;
+        let codec = match data.read_u8().map_err(malformed)? {
+            0 => None,
+            id => Some(Codec::from_id(id).ok_or(Error::Unsupported("namespace codec this version does not know"))?),
+        };
+        Ok((id, NamespaceOptions { default_ttl, compression, codec }))
+    }
+}
+
+/// One namespace other than the default.
+#[derive(Debug)]
+pub(crate) struct Keyspace {
+    pub name: String,
+    pub options: NamespaceOptions,
+    pub index: Index,
+}
+
+/// The namespaces of a store besides the default one.
+#[derive(Debug)]
+pub(crate) struct Namespaces {
+    /// The live catalog record of every namespace, by name.
+    pub catalog: Index,
+    pub by_id: BTreeMap<u32, Keyspace>,
+    next_id: u32,
+}
+
+impl Namespaces {
+    pub fn new(kind: IndexKind) -> Self {
+        Namespaces { catalog: Index::new(kind), by_id: BTreeMap::new(), next_id: 1 }
+    }
+
+    /// The same namespaces with nothing in their indexes.
+    pub fn emptied(&self) -> Self {
+        let kind = self.catalog.kind();
+        let by_id = self.by_id.iter()
+            .map(|(id, space)| (*id, Keyspace { name: space.name.clone(), options: space.options, index: Index::new(kind) }))
+            .collect();
+        Namespaces { catalog: Index::new(kind), by_id, next_id: self.next_id }
+    }
+
+    pub fn id_of(&self, name: &str) -> Option<u32> {
+        self.by_id.iter().find(|(_, space)| space.name == name).map(|(id, _)| *id)
+    }
+
+    /// Applies a catalog record for `name`, `None` dropping the namespace.
+    /// Returns the index of a dropped namespace.
+    pub fn apply(&mut self, name: &ByteStr, value: Option<&ByteStr>) -> Result<Option<Index>> {
+        let name = String::from_utf8_lossy(name).into_owned();
+        let dropped = self.id_of(&name).and_then(|id| self.by_id.remove(&id)).map(|space| space.index);
+        if let Some(value) = value {
+            let (id, options) = NamespaceOptions::decode(value)?;
+            self.next_id = self.next_id.max(id + 1);
+            self.by_id.insert(id, Keyspace { name, options, index: Index::new(self.catalog.kind()) });
+        }
+        Ok(dropped)
+    }
+
+    pub fn index_mut<'a>(&'a mut self, default: &'a mut Index, namespace: u32) -> Option<&'a mut Index> {
+        match namespace {
+            0 => Some(default),
+            CATALOG => Some(&mut self.catalog),
+            id => self.by_id.get_mut(&id).map(|space| &mut space.index),
+        }
+    }
+}
+
+impl ActionKv {
+    /// The index of `namespace`, unless it has been dropped.
+    pub(crate) fn index_of(&self, namespace: u32) -> Option<&Index> {
+        match namespace {
+            0 => Some(&self.index),
+            CATALOG => Some(&self.namespaces.catalog),
+            id => self.namespaces.by_id.get(&id).map(|space| &space.index),
+        }
+    }
+
+    /// The indexes of every namespace, the catalog included.
+    pub(crate) fn indexes(&self) -> impl Iterator<Item = &Index> {
+        [&self.index, &self.namespaces.catalog].into_iter().chain(self.namespaces.by_id.values().map(|space| &space.index))
+    }
+
+    /// The names of all namespaces besides the default one, sorted.
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.namespaces.by_id.values().map(|space| space.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn create_namespace(&mut self, name: &str, options: NamespaceOptions) -> Result<()> {
+        if self.namespaces.id_of(name).is_some() {
+            return Err(Error::NamespaceExists(name.to_string()));
+        }
+        let id = self.namespaces.next_id;
+        if id == CATALOG {
+            return Err(Error::Unsupported("every namespace id has been used"));
+        }
+        let value = options.encode(id)?;
+        let meta = RecordMeta { namespace: Some(CATALOG), ..RecordMeta::default() };
+        let entry = self.append(name.as_bytes(), Some(&value), &meta)?;
+        self.index_insert(name.as_bytes().to_vec(), entry);
+        self.namespaces.apply(name.as_bytes(), Some(&value))?;
+        self.after_append()
+    }
+
+    /// Drops the namespace and everything in it with a single record. The
+    /// space its records take is reclaimed by the next compaction, which
+    /// keeps none of their history.
+    pub fn drop_namespace(&mut self, name: &str) -> Result<()> {
+        if self.namespaces.id_of(name).is_none() {
+            return Err(Error::UnknownNamespace(name.to_string()));
+        }
+        let meta = RecordMeta { namespace: Some(CATALOG), ..RecordMeta::default() };
+        self.append(name.as_bytes(), None, &meta)?;
+        self.index_remove(CATALOG, name.as_bytes());
+        if let Some(index) = self.namespaces.apply(name.as_bytes(), None)? {
+            for (_, entry) in &index {
+                self.forget_live(*entry);
+            }
+        }
+        self.after_append()
+    }
+
+    /// A handle for reading and writing the namespace called `name`.
+    pub fn namespace(&mut self, name: &str) -> Result<Namespace<'_>> {
+        let id = self.namespaces.id_of(name).ok_or_else(|| Error::UnknownNamespace(name.to_string()))?;
+        Ok(Namespace { kv: self, id })
+    }
+}
+
+/// A namespace of a store, with an index and options of its own.
+#[derive(Debug)]
+pub struct Namespace<'a> {
+    kv: &'a mut ActionKv,
+    id: u32,
+}
+
+impl Namespace<'_> {
+    fn space(&self) -> &Keyspace {
+        &self.kv.namespaces.by_id[&self.id]
+    }
+
+    pub fn name(&self) -> &str {
+        &self.space().name
+    }
+
+    pub fn options(&self) -> NamespaceOptions {
+        self.space().options
+    }
+
+    /// The number of keys that have not expired; see `Index::live_len`.
+    pub fn len(&self) -> usize {
+        self.space().index.live_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.space().index.get(key).is_some_and(|entry| !entry.is_expired())
+    }
+
+    pub fn get(&self, key: &ByteStr) -> Result<Option<ByteString>> {
+        let entry = match self.space().index.get(key) {
+            Some(entry) if !entry.is_expired() => *entry,
+            _ => return Ok(None),
+        };
+        let value = self.kv.get_at(entry.segment, entry.offset)?.value;
+        Ok(Some(self.options().compression.decompress(value)?))
+    }
+
+    /// Sets `key`, expiring it after the namespace's default time to live
+    /// if it has one.
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        let meta = self.options().default_ttl.map(RecordMeta::expiring_in).unwrap_or_default();
+        self.write(key, Some(value), meta)
+    }
+
+    pub fn insert_with_ttl(&mut self, key: &ByteStr, value: &ByteStr, ttl: Duration) -> Result<()> {
+        self.write(key, Some(value), RecordMeta::expiring_in(ttl))
+    }
+
+    pub fn delete(&mut self, key: &ByteStr) -> Result<()> {
+        self.write(key, None, RecordMeta::default())
+    }
+
+    fn write(&mut self, key: &ByteStr, value: Option<&ByteStr>, meta: RecordMeta) -> Result<()> {
+        let value = value.map(|value| self.options().compression.compress(value)).transpose()?;
+        let meta = RecordMeta { namespace: Some(self.id), ..meta };
+        let entry = self.kv.append(key, value.as_deref(), &meta)?;
+        match value {
+            Some(_) => self.kv.index_insert(key.to_vec(), entry),
+            None => self.kv.index_remove(self.id, key),
+        }
+        self.kv.after_append()
+    }
+
+    /// All live pairs in key order.
+    pub fn iter(&self) -> Result<impl DoubleEndedIterator<Item = Result<(ByteString, ByteString)>> + '_> {
+        self.prefix(b"")
+    }
+
+    /// Live pairs whose keys start with `prefix`, in key order.
+    pub fn prefix(&self, prefix: &ByteStr) -> Result<impl DoubleEndedIterator<Item = Result<(ByteString, ByteString)>> + '_> {
+        self.kv.check_open()?;
+        let compression = self.options().compression;
+        let end = index::prefix_end(prefix);
+        let end = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        let pairs = Iter::new(self.space().index.range(Bound::Included(prefix), end), self.kv);
+        Ok(pairs.map(move |pair| {
+            let (key, value) = pair?;
+            Ok((key, compression.decompress(value)?))
+        }))
+    }
+
+    /// The value of `key` decoded with the namespace's codec, which also
+    /// encodes the key.
+    pub fn get_as<K: Serialize, V: DeserializeOwned>(&self, key: &K) -> Result<Option<V>> {
+        let codec = self.codec()?;
+        match self.get(&codec.encode(key)?)? {
+            Some(value) => Ok(Some(codec.decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert_as<K: Serialize, V: Serialize>(&mut self, key: &K, value: &V) -> Result<()> {
+        let codec = self.codec()?;
+        self.insert(&codec.encode(key)?, &codec.encode(value)?)
+    }
+
+    fn codec(&self) -> Result<Codec> {
+        self.options().codec.ok_or(Error::InvalidOptions("the namespace has no codec"))
+    }
+}
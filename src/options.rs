@@ -0,0 +1,179 @@
+use std::{path::Path, time::Duration};
+
+use crate::{ActionKv, ChecksumAlgorithm, Codec, CompactionPolicy, IndexKind, Result};
+
+/// When appended records are forced to stable storage with `sync_data`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Sync after every insert, update and delete before returning.
+    SyncEveryWrite,
+    /// Sync once every `n` writes.
+    SyncEveryN(u32),
+    /// Sync on the first write after the interval has elapsed since the last
+    /// sync. A `SharedKv` also syncs from a background thread once the
+    /// interval is up; a plain `ActionKv` has no thread, so the writes
+    /// before a pause stay unsynced until the next write or `close`.
+    SyncInterval(Duration),
+    /// Never sync explicitly and leave write-back to the operating system.
+    #[default]
+    OsManaged,
+}
+
+/// Configuration used when opening an `ActionKv`, in the spirit of `std::fs::OpenOptions`.
+///
+/// ```no_run
+/// # use kstore::{ActionKvOptions, Durability};
+/// let store = ActionKvOptions::new()
+///     .create_if_missing(true)
+///     .durability(Durability::SyncEveryWrite)
+///     .open("data.kv".as_ref())?;
+/// # Ok::<(), kstore::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ActionKvOptions {
+    pub(crate) create_if_missing: bool,
+    pub(crate) error_if_exists: bool,
+    pub(crate) read_only: bool,
+    pub(crate) write_header: bool,
+    pub(crate) migrate: bool,
+    pub(crate) checksum: ChecksumAlgorithm,
+    pub(crate) durability: Durability,
+    pub(crate) hint_every: Option<u64>,
+    pub(crate) index_kind: IndexKind,
+    pub(crate) mmap: bool,
+    pub(crate) segmented: bool,
+    pub(crate) max_segment_size: u64,
+    pub(crate) compaction: Option<CompactionPolicy>,
+    pub(crate) codec: Option<Codec>,
+    pub(crate) history_retention: Option<Duration>,
+}
+
+impl Default for ActionKvOptions {
+    fn default() -> Self {
+        ActionKvOptions {
+            create_if_missing: false,
+            error_if_exists: false,
+            read_only: false,
+            write_header: true,
+            migrate: false,
+            checksum: ChecksumAlgorithm::default(),
+            durability: Durability::default(),
+            hint_every: Some(100_000),
+            index_kind: IndexKind::default(),
+            mmap: false,
+            segmented: false,
+            max_segment_size: 64 * 1024 * 1024,
+            compaction: None,
+            codec: None,
+            history_retention: None,
+        }
+    }
+}
+
+impl ActionKvOptions {
+    pub fn new() -> Self {
+        ActionKvOptions::default()
+    }
+
+    pub fn create_if_missing(&mut self, create: bool) -> &mut Self {
+        self.create_if_missing = create;
+        self
+    }
+
+    /// Fails with `io::ErrorKind::AlreadyExists` instead of opening an existing file.
+    pub fn error_if_exists(&mut self, error: bool) -> &mut Self {
+        self.error_if_exists = error;
+        self
+    }
+
+    /// Opens the file without write access; every mutation returns `Error::ReadOnly`
+    /// and a torn tail is skipped rather than truncated.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Whether an empty file gets a format header before its first record.
+    pub fn write_header(&mut self, write: bool) -> &mut Self {
+        self.write_header = write;
+        self
+    }
+
+    /// Upgrades a file in an older format to the current one before opening it.
+    pub fn migrate(&mut self, migrate: bool) -> &mut Self {
+        self.migrate = migrate;
+        self
+    }
+
+    /// The checksum for records of a new file. Existing files keep the
+    /// algorithm recorded in their header.
+    pub fn checksum(&mut self, checksum: ChecksumAlgorithm) -> &mut Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn durability(&mut self, durability: Durability) -> &mut Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Rewrites the hint file after this many appended records, and on
+    /// `close` and `compact`. `None` stops kstore from writing hints at all.
+    pub fn hint_every(&mut self, records: Option<u64>) -> &mut Self {
+        self.hint_every = records;
+        self
+    }
+
+    /// Chooses the in-memory index; `IndexKind::Ordered` makes range and prefix scans cheap.
+    pub fn index_kind(&mut self, kind: IndexKind) -> &mut Self {
+        self.index_kind = kind;
+        self
+    }
+
+    /// Serves `get`, `get_at` and `get_bytes` from a read-only memory map of
+    /// the log, which is remapped as the file grows. Unix only.
+    pub fn mmap(&mut self, mmap: bool) -> &mut Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Keeps the store as a directory of numbered segment files at the
+    /// given path instead of a single file. An existing directory is always
+    /// opened this way.
+    pub fn segmented(&mut self, segmented: bool) -> &mut Self {
+        self.segmented = segmented;
+        self
+    }
+
+    /// The size at which a segmented store starts a new segment. A segment
+    /// can exceed it by the last record written to it.
+    pub fn max_segment_size(&mut self, bytes: u64) -> &mut Self {
+        self.max_segment_size = bytes;
+        self
+    }
+
+    /// Lets a `SharedKv` made from the store merge its segments on a
+    /// background thread whenever `policy` says enough of the log is dead.
+    /// Only segmented stores can be compacted in the background.
+    pub fn background_compaction(&mut self, policy: Option<CompactionPolicy>) -> &mut Self {
+        self.compaction = policy;
+        self
+    }
+
+    /// Makes compaction keep the records written within `window`, along
+    /// with the value each key had when it opened, so `history` and
+    /// `get_as_of` reach back at least that far. `None`, the default, keeps
+    /// only the live value of each key. The history a rewrite keeps counts
+    /// as retained rather than dead bytes, see `SegmentStats`.
+    pub fn history_retention(&mut self, window: Option<Duration>) -> &mut Self {
+        self.history_retention = window;
+        self
+    }
+
+    /// Opens the store and loads its index, so it is ready for reads and writes.
+    pub fn open(&self, path: &Path) -> Result<ActionKv> {
+        let mut store = ActionKv::open_with(path, self)?;
+        store.load()?;
+        Ok(store)
+    }
+}
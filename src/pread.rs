@@ -0,0 +1,45 @@
+//! Positional reads (`pread`) that leave the file cursor untouched, so any
+//! number of readers can share one handle with the appending writer.
+
+use std::{fs::File, io::{self, Read, Seek, SeekFrom}};
+
+pub(crate) struct ReadAt<'a> {
+    f: &'a File,
+    position: u64,
+}
+
+impl<'a> ReadAt<'a> {
+    pub fn new(f: &'a File, position: u64) -> Self {
+        ReadAt { f, position }
+    }
+}
+
+impl<'a> Read for ReadAt<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = read_at(self.f, buf, self.position)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for ReadAt<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.f.metadata()?.len().checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(f: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(f, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(f: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(f, buf, offset)
+}
@@ -0,0 +1,244 @@
+//! The binary protocol spoken between `client::Client` and the server.
+//!
+//! Every message is a frame, `len u32 | body`, with all integers little
+//! endian and byte strings written as `len u32 | bytes`:
+//!
+//! ```text
+//! request  id u32 | op u8 | arguments
+//! response id u32 | status u8 | payload
+//! ```
+//!
+//! | op | request                                     | OK payload                                  |
+//! |----|---------------------------------------------|---------------------------------------------|
+//! | 1  | GET key                                     | value                                       |
+//! | 2  | INSERT key value                            |                                             |
+//! | 3  | UPDATE key value                            |                                             |
+//! | 4  | DELETE key                                  |                                             |
+//! | 5  | SCAN prefix has_after u8 [after] limit u32  | count u32, count * (key value), has_next u8 |
+//! | 6  | PING                                        |                                             |
+//!
+//! The status is 0 for OK, 1 when the key does not exist and 2 for any
+//! other error, whose payload is the message. A client may send any number
+//! of requests before reading the responses, which come back in order and
+//! echo the id of their request.
+
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{ByteStr, ByteString, Error, Result};
+
+/// Frames larger than this are rejected rather than buffered.
+pub const MAX_FRAME_LEN: usize = 256 * 1024 * 1024;
+
+const OP_GET: u8 = 1;
+const OP_INSERT: u8 = 2;
+const OP_UPDATE: u8 = 3;
+const OP_DELETE: u8 = 4;
+const OP_SCAN: u8 = 5;
+const OP_PING: u8 = 6;
+
+const STATUS_OK: u8 = 0;
+const STATUS_NOT_FOUND: u8 = 1;
+const STATUS_ERROR: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Get(ByteString),
+    Insert(ByteString, ByteString),
+    /// Fails with `Error::NotFound` unless the key exists.
+    Update(ByteString, ByteString),
+    Delete(ByteString),
+    /// Up to `limit` pairs whose keys start with `prefix` and sort after
+    /// `after`, in key order. Servers return at most 1000 at a time.
+    Scan { prefix: ByteString, after: Option<ByteString>, limit: u32 },
+    Ping,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// The answer to a write or a ping.
+    Done,
+    Value(ByteString),
+    Page(Page),
+}
+
+/// One page of a scan.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Page {
+    pub pairs: Vec<(ByteString, ByteString)>,
+    /// The `after` of the following page, `None` after the last one.
+    pub next: Option<ByteString>,
+}
+
+/// Reads one frame, or `None` when the stream ends cleanly before it.
+pub(crate) fn read_frame<R: Read>(r: &mut R) -> Result<Option<ByteString>> {
+    let mut len = [0; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {},
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(Error::Protocol("frame too large"));
+    }
+    let mut body = vec![0; len];
+    r.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+pub(crate) fn write_frame<W: Write>(w: &mut W, body: &ByteStr) -> Result<()> {
+    if body.len() > MAX_FRAME_LEN {
+        return Err(Error::Protocol("frame too large"));
+    }
+    w.write_u32::<LittleEndian>(body.len() as u32)?;
+    w.write_all(body)?;
+    Ok(())
+}
+
+fn put_bytes(out: &mut ByteString, bytes: &ByteStr) -> Result<()> {
+    let len = u32::try_from(bytes.len()).map_err(|_| Error::Protocol("field too large"))?;
+    out.write_u32::<LittleEndian>(len)?;
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn get_bytes(r: &mut &ByteStr) -> Result<ByteString> {
+    let len = r.read_u32::<LittleEndian>()? as usize;
+    if r.len() < len {
+        return Err(Error::Protocol("field runs past the end of the frame"));
+    }
+    let (bytes, rest) = r.split_at(len);
+    *r = rest;
+    Ok(bytes.to_vec())
+}
+
+// any short read inside a frame means the peer sent a malformed one
+fn malformed(err: Error) -> Error {
+    if err.is_eof() { Error::Protocol("truncated frame") } else { err }
+}
+
+impl Request {
+    pub(crate) fn encode(&self, id: u32) -> Result<ByteString> {
+        let mut out = ByteString::new();
+        out.write_u32::<LittleEndian>(id)?;
+        match self {
+            Request::Get(key) => {
+                out.push(OP_GET);
+                put_bytes(&mut out, key)?;
+            },
+            Request::Insert(key, value) | Request::Update(key, value) => {
+                out.push(if matches!(self, Request::Insert(..)) { OP_INSERT } else { OP_UPDATE });
+                put_bytes(&mut out, key)?;
+                put_bytes(&mut out, value)?;
+            },
+            Request::Delete(key) => {
+                out.push(OP_DELETE);
+                put_bytes(&mut out, key)?;
+            },
+            Request::Scan { prefix, after, limit } => {
+                out.push(OP_SCAN);
+                put_bytes(&mut out, prefix)?;
+                out.push(after.is_some().into());
+                if let Some(after) = after {
+                    put_bytes(&mut out, after)?;
+                }
+                out.write_u32::<LittleEndian>(*limit)?;
+            },
+            Request::Ping => out.push(OP_PING),
+        }
+        Ok(out)
+    }
+
+    pub(crate) fn decode(frame: &ByteStr) -> Result<(u32, Request)> {
+        let mut r = frame;
+        Request::decode_from(&mut r).map_err(malformed)
+    }
+
+    fn decode_from(r: &mut &ByteStr) -> Result<(u32, Request)> {
+        let id = r.read_u32::<LittleEndian>()?;
+        let request = match r.read_u8()? {
+            OP_GET => Request::Get(get_bytes(r)?),
+            OP_INSERT => Request::Insert(get_bytes(r)?, get_bytes(r)?),
+            OP_UPDATE => Request::Update(get_bytes(r)?, get_bytes(r)?),
+            OP_DELETE => Request::Delete(get_bytes(r)?),
+            OP_SCAN => {
+                let prefix = get_bytes(r)?;
+                let after = if r.read_u8()? != 0 { Some(get_bytes(r)?) } else { None };
+                Request::Scan { prefix, after, limit: r.read_u32::<LittleEndian>()? }
+            },
+            OP_PING => Request::Ping,
+            _ => return Err(Error::Protocol("unknown request")),
+        };
+        if !r.is_empty() {
+            return Err(Error::Protocol("trailing bytes after request"));
+        }
+        Ok((id, request))
+    }
+}
+
+/// Encodes the response to request `id`.
+pub(crate) fn encode_response(id: u32, response: &Result<Reply>) -> Result<ByteString> {
+    let mut out = ByteString::new();
+    out.write_u32::<LittleEndian>(id)?;
+    match response {
+        Ok(Reply::Done) => out.push(STATUS_OK),
+        Ok(Reply::Value(value)) => {
+            out.push(STATUS_OK);
+            put_bytes(&mut out, value)?;
+        },
+        Ok(Reply::Page(Page { pairs, next })) => {
+            out.push(STATUS_OK);
+            out.write_u32::<LittleEndian>(pairs.len() as u32)?;
+            for (key, value) in pairs {
+                put_bytes(&mut out, key)?;
+                put_bytes(&mut out, value)?;
+            }
+            out.push(next.is_some().into());
+            if let Some(next) = next {
+                put_bytes(&mut out, next)?;
+            }
+        },
+        Err(Error::NotFound) => out.push(STATUS_NOT_FOUND),
+        Err(err) => {
+            out.push(STATUS_ERROR);
+            put_bytes(&mut out, err.to_string().as_bytes())?;
+        },
+    }
+    Ok(out)
+}
+
+/// Decodes the response to `request`, whose payload depends on the op.
+/// Errors reported by the server come back as `Error::NotFound` or
+/// `Error::Server`.
+pub(crate) fn decode_response(frame: &ByteStr, request: &Request) -> Result<(u32, Result<Reply>)> {
+    let mut r = frame;
+    decode_response_from(&mut r, request).map_err(malformed)
+}
+
+fn decode_response_from(r: &mut &ByteStr, request: &Request) -> Result<(u32, Result<Reply>)> {
+    let id = r.read_u32::<LittleEndian>()?;
+    let reply = match r.read_u8()? {
+        STATUS_OK => Ok(match request {
+            Request::Get(_) => Reply::Value(get_bytes(r)?),
+            Request::Scan { .. } => {
+                let count = r.read_u32::<LittleEndian>()?;
+                let mut pairs = Vec::new();
+                for _ in 0..count {
+                    pairs.push((get_bytes(r)?, get_bytes(r)?));
+                }
+                let next = if r.read_u8()? != 0 { Some(get_bytes(r)?) } else { None };
+                Reply::Page(Page { pairs, next })
+            },
+            _ => Reply::Done,
+        }),
+        STATUS_NOT_FOUND => Err(Error::NotFound),
+        STATUS_ERROR => Err(Error::Server(String::from_utf8_lossy(&get_bytes(r)?).into_owned())),
+        _ => return Err(Error::Protocol("unknown response status")),
+    };
+    if !r.is_empty() {
+        return Err(Error::Protocol("trailing bytes after response"));
+    }
+    Ok((id, reply))
+}
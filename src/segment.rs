@@ -0,0 +1,114 @@
+//! A data file of the log. A store opened on a file has that file as its
+//! only segment; a store opened on a directory keeps numbered segment files
+//! in it and appends to the newest one.
+
+use std::{fs::{self, File, OpenOptions}, io, path::{Path, PathBuf}, sync::{Arc, Mutex, PoisonError}};
+
+use crate::{header::{self, Detected}, ChecksumAlgorithm, Codec, Error, FileHeader, Mmap, Result};
+
+const EXTENSION: &str = "seg";
+
+#[derive(Debug)]
+pub(crate) struct Segment {
+    pub id: u32,
+    pub path: PathBuf,
+    pub f: File,
+    pub header: Option<FileHeader>,
+    pub format_version: u16,
+    pub checksum: ChecksumAlgorithm,
+    /// Offset of the first record.
+    pub data_start: u64,
+    /// Whether the header allows records with extra fields.
+    pub record_fields: bool,
+    map: Mutex<Option<Arc<Mmap>>>,
+}
+
+impl Segment {
+    /// Opens the segment at `path`. An empty, writable file is given a
+    /// header for `checksum` and `codec` first unless `write_header` is false.
+    pub fn open(id: u32, path: PathBuf, open: &OpenOptions, read_only: bool, write_header: bool, checksum: ChecksumAlgorithm, codec: Option<Codec>) -> Result<Self> {
+        let mut f = open.open(&path)?;
+
+        // (header, format version, checksum, offset of the first record)
+        let (header, format_version, checksum, data_start) = match FileHeader::detect(&mut f)? {
+            Detected::Empty if read_only || !write_header => {
+                if checksum != ChecksumAlgorithm::default() && !read_only {
+                    return Err(Error::InvalidOptions("a custom checksum is recorded in the file header"));
+                }
+                if codec.is_some() && !read_only {
+                    return Err(Error::InvalidOptions("the codec is recorded in the file header"));
+                }
+                (None, 0, ChecksumAlgorithm::default(), 0)
+            },
+            Detected::Empty => {
+                let header = FileHeader { codec, ..FileHeader::new(checksum) };
+                header.write(&mut f)?;
+                f.sync_data()?;
+                (Some(header), header.version, header.checksum, header.data_start())
+            },
+            Detected::Current(header) => (Some(header), header.version, header.checksum, header.data_start()),
+            Detected::Legacy { version, checksum, data_start } => (None, version, checksum, data_start),
+        };
+
+        let record_fields = header.is_some_and(|header| header.flags & header::FLAG_RECORD_FIELDS != 0);
+        Ok(Segment { id, path, f, header, format_version, checksum, data_start, record_fields, map: Mutex::new(None) })
+    }
+
+    /// Creates a new, empty segment in the current format whose header
+    /// records `next_seq`.
+    pub fn create(id: u32, path: PathBuf, checksum: ChecksumAlgorithm, codec: Option<Codec>, next_seq: u64) -> Result<Self> {
+        let mut f = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        FileHeader { codec, next_seq: Some(next_seq), ..FileHeader::new(checksum) }.write(&mut f)?;
+        f.sync_data()?;
+        drop(f);
+
+        let mut open = OpenOptions::new();
+        open.read(true).append(true);
+        Segment::open(id, path, &open, false, true, checksum, codec)
+    }
+
+    pub fn file_len(&self) -> Result<u64> {
+        Ok(self.f.metadata()?.len())
+    }
+
+    /// A mapping of the segment that covers at least its first `end` bytes,
+    /// remapping the file at its current length if needed.
+    pub fn mapping(&self, end: u64) -> Result<Arc<Mmap>> {
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(map) = map.as_ref().filter(|map| map.len() as u64 >= end) {
+            return Ok(map.clone());
+        }
+
+        let file_len = self.file_len()?;
+        if file_len < end {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let new_map = Arc::new(Mmap::map(&self.f, file_len)?);
+        *map = Some(new_map.clone());
+        Ok(new_map)
+    }
+
+    // called whenever the file is about to be truncated or replaced
+    pub fn unmap(&mut self) {
+        *self.map.get_mut().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+pub(crate) fn segment_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("{:08}.{}", id, EXTENSION))
+}
+
+/// The segment files in `dir`, in id order. Other files are ignored.
+pub(crate) fn list(dir: &Path) -> Result<Vec<(u32, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == EXTENSION) {
+            if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+                segments.push((id, path));
+            }
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
@@ -0,0 +1,204 @@
+//! Serves a store to other processes over TCP or a Unix socket, speaking
+//! the protocol described in `client`, RESP for Redis tooling, or HTTP.
+//! Each connection gets a thread of its own; they all share one `SharedKv`,
+//! so reads run in parallel and writes are serialized by the store.
+
+use std::{io::{BufRead, BufReader, BufWriter, Read, Write}, net::TcpListener, ops::Bound, thread};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+use crate::{index, protocol::{self, Page, Reply, Request}, ActionKv, ByteStr, ByteString, Error, Result, SharedKv};
+
+mod http;
+mod resp;
+
+/// The most pairs or keys one scan returns, whatever the client asks for;
+/// clients page through the rest.
+const MAX_SCAN_LIMIT: usize = 1000;
+
+/// The protocol a server speaks on every connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    /// The binary protocol of `client::Client`.
+    #[default]
+    Native,
+    /// The Redis serialization protocol, answering `GET`, `SET`, `DEL`,
+    /// `EXISTS`, `KEYS`, `SCAN`, `EXPIRE`, `TTL`, `MGET`, `MSET` and `PING`.
+    Resp,
+    /// HTTP/1.1 with JSON or raw bodies, for scripts and browsers.
+    Http,
+}
+
+#[derive(Debug, Clone)]
+pub struct Server {
+    store: SharedKv,
+    protocol: Protocol,
+}
+
+impl Server {
+    pub fn new(store: SharedKv) -> Self {
+        Server { store, protocol: Protocol::default() }
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn store(&self) -> &SharedKv {
+        &self.store
+    }
+
+    /// Accepts connections until the listener fails.
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                // the client went away before the connection was accepted
+                Err(_) => continue,
+            };
+            let _ = stream.set_nodelay(true);
+            let writer = stream.try_clone()?;
+            self.spawn(stream, writer);
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: UnixListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let writer = stream.try_clone()?;
+            self.spawn(stream, writer);
+        }
+        Ok(())
+    }
+
+    fn spawn<R: Read + Send + 'static, W: Write + Send + 'static>(&self, reader: R, writer: W) {
+        let server = self.clone();
+        thread::spawn(move || {
+            // a broken connection only affects its own client
+            let _ = server.handle(reader, writer);
+        });
+    }
+
+    /// Answers requests from `reader` until it is closed. Responses are
+    /// buffered while more pipelined requests are waiting and flushed once
+    /// the client has to wait for them.
+    pub fn handle<R: Read, W: Write>(&self, reader: R, writer: W) -> Result<()> {
+        let reader = BufReader::new(reader);
+        let writer = BufWriter::new(writer);
+        match self.protocol {
+            Protocol::Native => self.handle_native(reader, writer),
+            Protocol::Resp => resp::handle(&self.store, reader, writer),
+            Protocol::Http => http::handle(&self.store, reader, writer),
+        }
+    }
+
+    fn handle_native<R: Read, W: Write>(&self, mut reader: BufReader<R>, mut writer: BufWriter<W>) -> Result<()> {
+        while let Some(frame) = protocol::read_frame(&mut reader)? {
+            let (id, request) = Request::decode(&frame)?;
+            let response = self.execute(request);
+            protocol::write_frame(&mut writer, &protocol::encode_response(id, &response)?)?;
+            if reader.buffer().is_empty() {
+                writer.flush()?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn execute(&self, request: Request) -> Result<Reply> {
+        match request {
+            Request::Get(key) => self.store.get(&key)?.map(Reply::Value).ok_or(Error::NotFound),
+            Request::Insert(key, value) => {
+                self.store.insert(&key, &value)?;
+                Ok(Reply::Done)
+            },
+            Request::Update(key, value) => {
+                self.update_existing(&key, &value)?;
+                Ok(Reply::Done)
+            },
+            Request::Delete(key) => {
+                if !self.store.contains_key(&key) {
+                    return Err(Error::NotFound);
+                }
+                self.store.delete(&key)?;
+                Ok(Reply::Done)
+            },
+            // an empty page could not say whether keys remain
+            Request::Scan { limit: 0, .. } => Err(Error::Protocol("scan limit must be at least 1")),
+            Request::Scan { prefix, after, limit } => {
+                Ok(Reply::Page(scan(&self.store.read(), &prefix, after.as_deref(), (limit as usize).min(MAX_SCAN_LIMIT))?))
+            },
+            Request::Ping => Ok(Reply::Done),
+        }
+    }
+
+    // the existence check and the write have to see the same version, or a
+    // delete in between would be undone
+    fn update_existing(&self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        loop {
+            let version = self.store.version(key).ok_or(Error::NotFound)?;
+            match self.store.update_if_version(key, version, value) {
+                Err(Error::Conflict { .. }) => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+// the keys starting with `prefix` that sort after `after`
+fn start_bound<'a>(prefix: &'a ByteStr, after: Option<&'a ByteStr>) -> Bound<&'a ByteStr> {
+    match after {
+        Some(after) if after >= prefix => Bound::Excluded(after),
+        _ => Bound::Included(prefix),
+    }
+}
+
+/// Up to `limit` live pairs whose keys start with `prefix` and sort after
+/// `after`, in key order.
+pub(crate) fn scan(kv: &ActionKv, prefix: &ByteStr, after: Option<&ByteStr>, limit: usize) -> Result<Page> {
+    let start = start_bound(prefix, after);
+    let end = index::prefix_end(prefix);
+    let end = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+
+    let mut pairs = Vec::new();
+    let mut iter = kv.range::<&ByteStr, _>((start, end))?;
+    for pair in iter.by_ref().take(limit) {
+        pairs.push(pair?);
+    }
+    let next = match iter.next() {
+        Some(_) => pairs.last().map(|(key, _)| key.clone()),
+        None => None,
+    };
+    Ok(Page { pairs, next })
+}
+
+/// Like `scan` but for keys alone, which it takes from the index without
+/// reading any values. Also returns whether more keys follow.
+pub(crate) fn scan_keys(kv: &ActionKv, prefix: &ByteStr, after: Option<&ByteStr>, limit: usize) -> (Vec<ByteString>, bool) {
+    let end = index::prefix_end(prefix);
+    let end = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+    let mut live = kv.index.range(start_bound(prefix, after), end).filter(|(_, entry)| !entry.is_expired()).map(|(key, _)| key);
+    let keys = live.by_ref().take(limit).cloned().collect();
+    (keys, live.next().is_some())
+}
+
+/// One line without its line ending, or `None` at the end of the stream.
+/// Lines longer than `limit` are a protocol error.
+fn read_line<R: Read>(r: &mut BufReader<R>, limit: usize) -> Result<Option<ByteString>> {
+    let mut line = ByteString::new();
+    r.by_ref().take(limit as u64 + 1).read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return if line.len() > limit { Err(Error::Protocol("line too long")) } else { Ok(None) };
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
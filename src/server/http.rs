@@ -0,0 +1,504 @@
+//! HTTP/1.1 for `Protocol::Http`:
+//!
+//! ```text
+//! GET    /keys/{key}                          the value of key
+//! PUT    /keys/{key}                          sets it to the request body
+//! DELETE /keys/{key}                          removes it
+//! GET    /keys?prefix=&limit=&cursor=         one page of keys
+//! GET    /stats                               the size of the store
+//! ```
+//!
+//! Keys in paths and query strings are percent-encoded. A value travels as
+//! raw bytes under `application/octet-stream` or as base64 in a JSON
+//! object, `{"key": ..., "value": ...}` for a response and `{"value": ...}`
+//! for a request body: `Accept` picks the form of a response and
+//! `Content-Type` that of a body, with JSON responses and raw bodies when
+//! they say nothing. Keys inside JSON are base64 too. A page of keys ends
+//! with a `cursor` to pass back for the next one, `null` on the last page.
+//!
+//! A value comes with its version as its `ETag`. A PUT with `If-Match`
+//! only writes over that version, or over any value for `*`, and one with
+//! `If-None-Match: *` only writes a key that is absent; otherwise it gets
+//! 412. A PUT has to say how long its body is, or it gets 411, and a body
+//! larger than a frame of the native protocol gets 413.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{read_line, scan_keys, MAX_SCAN_LIMIT};
+use crate::{protocol::MAX_FRAME_LEN, ByteStr, ByteString, Codec, Error, Result, SharedKv};
+
+const MAX_LINE_LEN: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const DEFAULT_LIMIT: usize = 100;
+
+const BODY_TOO_LARGE: &str = "request body too large";
+
+const JSON: &str = "application/json";
+const OCTET_STREAM: &str = "application/octet-stream";
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    /// Names are lowercase.
+    headers: Vec<(String, String)>,
+    body: ByteString,
+    keep_alive: bool,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    fn query_param(&self, name: &str) -> Option<&str> {
+        self.query.split('&').filter_map(|pair| pair.split_once('=')).find(|(n, _)| *n == name).map(|(_, value)| value)
+    }
+
+    /// Whether the client asked for a raw value rather than JSON. Only the
+    /// two types themselves count; wildcards leave it at JSON.
+    fn accepts_raw(&self) -> bool {
+        let (mut json, mut raw) = (0.0, 0.0);
+        for range in self.header("accept").unwrap_or("").split(',') {
+            let mut params = range.split(';');
+            let media = params.next().unwrap_or("").trim();
+            let q = params.find_map(|param| param.trim().strip_prefix("q=")).and_then(|q| q.parse().ok()).unwrap_or(1.0);
+            if media.eq_ignore_ascii_case(OCTET_STREAM) {
+                raw = f32::max(raw, q);
+            } else if media.eq_ignore_ascii_case(JSON) {
+                json = f32::max(json, q);
+            }
+        }
+        raw > json
+    }
+
+    fn has_length(&self) -> bool {
+        self.header("content-length").is_some() || self.is_chunked()
+    }
+
+    fn is_chunked(&self) -> bool {
+        self.header("transfer-encoding").is_some_and(|coding| coding.to_ascii_lowercase().contains("chunked"))
+    }
+
+    fn has_json_body(&self) -> bool {
+        self.header("content-type").is_some_and(|ty| ty.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case(JSON))
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: ByteString,
+    allow: Option<&'static str>,
+    etag: Option<u64>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: ByteString) -> Self {
+        Response { status, content_type, body, allow: None, etag: None }
+    }
+
+    fn json<T: serde::Serialize>(value: &T) -> Result<Self> {
+        Ok(Response::new(200, JSON, Codec::Json.encode(value)?))
+    }
+
+    fn no_content() -> Self {
+        Response::new(204, JSON, ByteString::new())
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        let body = Codec::Json.encode(&ErrorBody { error: message }).unwrap_or_default();
+        Response::new(status, JSON, body)
+    }
+
+    fn method_not_allowed(allow: &'static str) -> Self {
+        Response { allow: Some(allow), ..Response::error(405, "method not allowed") }
+    }
+
+    fn write_to<W: Write>(&self, out: &mut W, keep_alive: bool) -> io::Result<()> {
+        write!(out, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        // a 204 may not carry a body or say how long it is
+        if self.status != 204 {
+            write!(out, "Content-Type: {}\r\nContent-Length: {}\r\n", self.content_type, self.body.len())?;
+        }
+        if let Some(allow) = self.allow {
+            write!(out, "Allow: {}\r\n", allow)?;
+        }
+        if let Some(version) = self.etag {
+            write!(out, "ETag: \"{}\"\r\n", version)?;
+        }
+        if !keep_alive {
+            out.write_all(b"Connection: close\r\n")?;
+        }
+        out.write_all(b"\r\n")?;
+        out.write_all(&self.body)
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+#[derive(Serialize)]
+struct Pair {
+    key: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct PutBody {
+    value: String,
+}
+
+#[derive(Serialize)]
+struct KeyPage {
+    keys: Vec<String>,
+    cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    keys: usize,
+    segments: usize,
+    disk_bytes: u64,
+    live_bytes: u64,
+    dead_bytes: u64,
+    format_version: u16,
+    codec: Option<String>,
+    read_only: bool,
+}
+
+pub(super) fn handle<R: Read, W: Write>(store: &SharedKv, mut reader: BufReader<R>, mut writer: BufWriter<W>) -> Result<()> {
+    loop {
+        let request = match read_request(&mut reader, &mut writer) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(Error::Protocol(msg)) => {
+                let status = if msg == BODY_TOO_LARGE { 413 } else { 400 };
+                Response::error(status, msg).write_to(&mut writer, false)?;
+                break;
+            },
+            Err(err) => return Err(err),
+        };
+        route(store, &request).write_to(&mut writer, request.keep_alive)?;
+        if !request.keep_alive {
+            break;
+        }
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_request<R: Read, W: Write>(r: &mut BufReader<R>, w: &mut BufWriter<W>) -> Result<Option<Request>> {
+    // blank lines before a request are allowed
+    let line = loop {
+        match read_line(r, MAX_LINE_LEN)? {
+            None => return Ok(None),
+            Some(line) if line.is_empty() => continue,
+            Some(line) => break String::from_utf8(line).map_err(|_| Error::Protocol("request line is not UTF-8"))?,
+        }
+    };
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(Error::Protocol("malformed request line"));
+    };
+    let keep_alive = match version {
+        "HTTP/1.1" => true,
+        "HTTP/1.0" => false,
+        _ => return Err(Error::Protocol("unsupported HTTP version")),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(r, MAX_LINE_LEN)?.ok_or(Error::Protocol("unexpected end of request"))?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(Error::Protocol("too many headers"));
+        }
+        let line = String::from_utf8_lossy(&line);
+        let (name, value) = line.split_once(':').ok_or(Error::Protocol("malformed header"))?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let mut request = Request { method: method.to_string(), path: path.to_string(), query: query.to_string(), headers, body: ByteString::new(), keep_alive };
+    match request.header("connection") {
+        Some(connection) if connection.eq_ignore_ascii_case("close") => request.keep_alive = false,
+        Some(connection) if connection.eq_ignore_ascii_case("keep-alive") => request.keep_alive = true,
+        _ => {},
+    }
+
+    let chunked = request.is_chunked();
+    let len = match request.header("content-length") {
+        Some(len) if !chunked => len.parse::<usize>().map_err(|_| Error::Protocol("invalid content length"))?,
+        _ => 0,
+    };
+    if chunked || len > 0 {
+        if request.header("expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue")) {
+            w.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            w.flush()?;
+        }
+        request.body = if chunked { read_chunked(r)? } else { read_body(r, len)? };
+    }
+    Ok(Some(request))
+}
+
+fn read_body<R: Read>(r: &mut BufReader<R>, len: usize) -> Result<ByteString> {
+    if len > MAX_FRAME_LEN {
+        return Err(Error::Protocol(BODY_TOO_LARGE));
+    }
+    let mut body = vec![0; len];
+    r.read_exact(&mut body)?;
+    Ok(body)
+}
+
+fn read_chunked<R: Read>(r: &mut BufReader<R>) -> Result<ByteString> {
+    let mut body = ByteString::new();
+    loop {
+        let line = read_line(r, MAX_LINE_LEN)?.ok_or(Error::Protocol("unexpected end of request"))?;
+        let size = String::from_utf8_lossy(&line);
+        let size = size.split(';').next().unwrap_or("").trim();
+        let size = Some(size).filter(|size| size.bytes().all(|b| b.is_ascii_hexdigit()));
+        let size = size.and_then(|size| usize::from_str_radix(size, 16).ok()).ok_or(Error::Protocol("invalid chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        if size > MAX_FRAME_LEN - body.len() {
+            return Err(Error::Protocol(BODY_TOO_LARGE));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        r.read_exact(&mut body[start..])?;
+        let mut end = [0; 2];
+        r.read_exact(&mut end)?;
+        if &end != b"\r\n" {
+            return Err(Error::Protocol("malformed chunk"));
+        }
+    }
+    // trailers carry nothing we use
+    while !read_line(r, MAX_LINE_LEN)?.ok_or(Error::Protocol("unexpected end of request"))?.is_empty() {}
+    Ok(body)
+}
+
+fn route(store: &SharedKv, request: &Request) -> Response {
+    let result = match request.path.as_str() {
+        "/stats" => match request.method.as_str() {
+            "GET" => stats(store),
+            _ => Ok(Response::method_not_allowed("GET")),
+        },
+        "/keys" => match request.method.as_str() {
+            "GET" => list(store, request),
+            _ => Ok(Response::method_not_allowed("GET")),
+        },
+        path => match path.strip_prefix("/keys/").map(|key| percent_decode(key, false)) {
+            Some(Some(key)) => match request.method.as_str() {
+                "GET" => get(store, request, &key),
+                "PUT" => put(store, request, &key),
+                "DELETE" => delete(store, &key),
+                _ => Ok(Response::method_not_allowed("GET, PUT, DELETE")),
+            },
+            Some(None) => Ok(Response::error(400, "invalid percent-encoding in key")),
+            None => Ok(Response::error(404, "no such resource")),
+        },
+    };
+    result.unwrap_or_else(|err| Response::error(500, &err.to_string()))
+}
+
+fn get(store: &SharedKv, request: &Request, key: &ByteStr) -> Result<Response> {
+    let Some((value, version)) = store.get_versioned(key)? else {
+        return Ok(Response::error(404, "key not found"));
+    };
+    let response = if request.accepts_raw() {
+        Response::new(200, OCTET_STREAM, value)
+    } else {
+        Response::json(&Pair { key: base64_encode(key, STANDARD, true), value: base64_encode(&value, STANDARD, true) })?
+    };
+    Ok(Response { etag: Some(version), ..response })
+}
+
+fn put(store: &SharedKv, request: &Request, key: &ByteStr) -> Result<Response> {
+    if !request.has_length() {
+        return Ok(Response::error(411, "content length required"));
+    }
+    let value = if !request.has_json_body() {
+        request.body.clone()
+    } else {
+        let value = match Codec::Json.decode::<PutBody>(&request.body) {
+            Ok(body) => base64_decode(&body.value),
+            Err(_) => return Ok(Response::error(400, "expected {\"value\": base64}")),
+        };
+        match value {
+            Some(value) => value,
+            None => return Ok(Response::error(400, "value is not valid base64")),
+        }
+    };
+
+    let result = match (request.header("if-match"), request.header("if-none-match")) {
+        (Some(_), Some(_)) => return Ok(Response::error(400, "If-Match and If-None-Match together")),
+        (Some("*"), None) => put_existing(store, key, &value),
+        // an entity tag that is not a version matches none
+        (Some(etag), None) => match etag.strip_prefix('"').and_then(|etag| etag.strip_suffix('"')).and_then(|etag| etag.parse().ok()) {
+            Some(version) => store.update_if_version(key, version, &value),
+            None => Err(Error::Conflict { key: key.to_vec(), version: store.version(key) }),
+        },
+        (None, Some("*")) => store.insert_if_absent(key, &value),
+        (None, Some(_)) => return Ok(Response::error(400, "If-None-Match only supports *")),
+        (None, None) => store.insert(key, &value),
+    };
+    match result {
+        Ok(()) => Ok(Response::no_content()),
+        Err(Error::Conflict { .. }) => Ok(Response::error(412, "precondition failed")),
+        Err(err) => Err(err),
+    }
+}
+
+// the existence check and the write have to see the same version, or a
+// delete in between would be undone
+fn put_existing(store: &SharedKv, key: &ByteStr, value: &ByteStr) -> Result<()> {
+    loop {
+        let Some(version) = store.version(key) else {
+            return Err(Error::Conflict { key: key.to_vec(), version: None });
+        };
+        match store.update_if_version(key, version, value) {
+            Err(Error::Conflict { .. }) => continue,
+            result => return result,
+        }
+    }
+}
+
+fn delete(store: &SharedKv, key: &ByteStr) -> Result<Response> {
+    if !store.contains_key(key) {
+        return Ok(Response::error(404, "key not found"));
+    }
+    store.delete(key)?;
+    Ok(Response::no_content())
+}
+
+fn list(store: &SharedKv, request: &Request) -> Result<Response> {
+    let param = |name| request.query_param(name).map(|value| percent_decode(value, true));
+    let prefix = match param("prefix") {
+        Some(Some(prefix)) => prefix,
+        Some(None) => return Ok(Response::error(400, "invalid percent-encoding in prefix")),
+        None => ByteString::new(),
+    };
+    let limit = match param("limit") {
+        Some(limit) => match limit.and_then(|limit| String::from_utf8(limit).ok()?.parse().ok()) {
+            Some(limit) if (1..=MAX_SCAN_LIMIT).contains(&limit) => limit,
+            _ => return Ok(Response::error(400, "limit must be between 1 and 1000")),
+        },
+        None => DEFAULT_LIMIT,
+    };
+    let after = match param("cursor") {
+        Some(cursor) => match cursor.and_then(|cursor| base64_decode(std::str::from_utf8(&cursor).ok()?)) {
+            Some(after) => Some(after),
+            None => return Ok(Response::error(400, "invalid cursor")),
+        },
+        None => None,
+    };
+
+    let (keys, more) = scan_keys(&store.read(), &prefix, after.as_deref(), limit);
+    let cursor = keys.last().filter(|_| more).map(|last| base64_encode(last, URL_SAFE, false));
+    let keys = keys.iter().map(|key| base64_encode(key, STANDARD, true)).collect();
+    Response::json(&KeyPage { keys, cursor })
+}
+
+fn stats(store: &SharedKv) -> Result<Response> {
+    let kv = store.read();
+    let segments = kv.segment_stats()?;
+    Response::json(&Stats {
+        keys: kv.index.live_len(),
+        segments: segments.len(),
+        disk_bytes: segments.iter().map(|stats| stats.file_bytes).sum(),
+        live_bytes: segments.iter().map(|stats| stats.live_bytes).sum(),
+        dead_bytes: segments.iter().map(|stats| stats.dead_bytes).sum(),
+        format_version: kv.format_version(),
+        codec: kv.codec().map(|codec| codec.to_string()),
+        read_only: kv.is_read_only(),
+    })
+}
+
+/// Decodes `%XX` escapes and, in a query string, `+` for a space.
+fn percent_decode(text: &str, plus_as_space: bool) -> Option<ByteString> {
+    let mut out = ByteString::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next() {
+        out.push(match b {
+            b'%' => {
+                // from_str_radix would take a sign as well
+                let hex = [bytes.next()?, bytes.next()?];
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            },
+            b'+' if plus_as_space => b' ',
+            b => b,
+        });
+    }
+    Some(out)
+}
+
+fn base64_encode(bytes: &ByteStr, alphabet: &[u8; 64], pad: bool) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(alphabet[(n >> (18 - 6 * i)) as usize & 63] as char);
+        }
+        if pad {
+            out.extend(std::iter::repeat_n('=', 3 - chunk.len()));
+        }
+    }
+    out
+}
+
+/// Decodes either alphabet, with or without padding.
+fn base64_decode(text: &str) -> Option<ByteString> {
+    let text = text.trim_end_matches('=');
+    if text.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = ByteString::with_capacity(text.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let sextet = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6 | sextet as u32) & 0xffffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,387 @@
+//! RESP, the protocol of Redis, for `Protocol::Resp`. Requests arrive as
+//! arrays of bulk strings or, when typed by hand, as inline lines of words,
+//! and each command maps onto one `SharedKv` call.
+
+use std::{collections::{BTreeMap, BTreeSet}, io::{self, BufReader, BufWriter, Read, Write}, time::Duration};
+
+use super::{read_line, scan_keys, MAX_SCAN_LIMIT};
+use crate::{conditional::Condition, protocol::MAX_FRAME_LEN, ByteStr, ByteString, Error, RecordMeta, Result, SharedKv, WriteBatch};
+
+const MAX_INLINE_LEN: usize = 64 * 1024;
+const MAX_ARGS: usize = 1024 * 1024;
+const DEFAULT_SCAN_COUNT: usize = 10;
+// the cursors of abandoned scans are forgotten beyond this many
+const MAX_CURSORS: usize = 1024;
+
+enum Value {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<ByteString>),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match self {
+            Value::Simple(s) => write!(out, "+{}\r\n", s),
+            Value::Error(msg) => write!(out, "-{}\r\n", msg.replace(['\r', '\n'], " ")),
+            Value::Integer(n) => write!(out, ":{}\r\n", n),
+            Value::Bulk(None) => out.write_all(b"$-1\r\n"),
+            Value::Bulk(Some(bytes)) => {
+                write!(out, "${}\r\n", bytes.len())?;
+                out.write_all(bytes)?;
+                out.write_all(b"\r\n")
+            },
+            Value::Array(items) => {
+                write!(out, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.write_to(out))
+            },
+        }
+    }
+}
+
+fn syntax_error() -> Value {
+    Value::Error("ERR syntax error".into())
+}
+
+fn not_an_integer() -> Value {
+    Value::Error("ERR value is not an integer or out of range".into())
+}
+
+pub(super) fn handle<R: Read, W: Write>(store: &SharedKv, mut reader: BufReader<R>, mut writer: BufWriter<W>) -> Result<()> {
+    let mut session = Session { store, cursors: BTreeMap::new(), next_cursor: 1 };
+    loop {
+        let args = match read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => break,
+            // like Redis, answer a malformed request and hang up
+            Err(Error::Protocol(msg)) => {
+                Value::Error(format!("ERR Protocol error: {}", msg)).write_to(&mut writer)?;
+                break;
+            },
+            Err(err) => return Err(err),
+        };
+        let Some(name) = args.first() else { continue };
+        if name.eq_ignore_ascii_case(b"quit") {
+            Value::Simple("OK").write_to(&mut writer)?;
+            break;
+        }
+        session.execute(&args).write_to(&mut writer)?;
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads one command, or `None` when the stream ends before it.
+fn read_command<R: Read>(r: &mut BufReader<R>) -> Result<Option<Vec<ByteString>>> {
+    let line = match read_line(r, MAX_INLINE_LEN)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    if line.first() != Some(&b'*') {
+        let words = line.split(u8::is_ascii_whitespace).filter(|word| !word.is_empty());
+        return Ok(Some(words.map(<[u8]>::to_vec).collect()));
+    }
+
+    let count = parse_len(&line[1..], MAX_ARGS).ok_or(Error::Protocol("invalid multibulk length"))?;
+    let mut args = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let line = read_line(r, MAX_INLINE_LEN)?.ok_or(Error::Protocol("unexpected end of stream"))?;
+        if line.first() != Some(&b'$') {
+            return Err(Error::Protocol("expected '$'"));
+        }
+        let len = parse_len(&line[1..], MAX_FRAME_LEN).ok_or(Error::Protocol("invalid bulk length"))?;
+        let mut arg = vec![0; len + 2];
+        r.read_exact(&mut arg)?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(Error::Protocol("bulk string not terminated"));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+fn parse_len(digits: &ByteStr, max: usize) -> Option<usize> {
+    std::str::from_utf8(digits).ok()?.parse().ok().filter(|len| *len <= max)
+}
+
+fn parse_int(digits: &ByteStr) -> Option<i64> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+type Command<'a> = fn(&mut Session<'a>, &[ByteString]) -> Result<Value>;
+
+struct Session<'a> {
+    store: &'a SharedKv,
+    // SCAN cursors are numbers, so each stands for the last key of a page
+    cursors: BTreeMap<u64, ByteString>,
+    next_cursor: u64,
+}
+
+impl<'a> Session<'a> {
+    fn execute(&mut self, args: &[ByteString]) -> Value {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+        let args = &args[1..];
+        let (command, arity_ok): (Command<'a>, bool) = match name.as_str() {
+            "ping" => (Session::ping, args.len() <= 1),
+            "get" => (Session::get, args.len() == 1),
+            "set" => (Session::set, args.len() >= 2),
+            "del" => (Session::del, !args.is_empty()),
+            "exists" => (Session::exists, !args.is_empty()),
+            "keys" => (Session::keys, args.len() == 1),
+            "scan" => (Session::scan, !args.is_empty()),
+            "expire" => (Session::expire, args.len() == 2),
+            "ttl" => (Session::ttl, args.len() == 1),
+            "mget" => (Session::mget, !args.is_empty()),
+            "mset" => (Session::mset, !args.is_empty() && args.len().is_multiple_of(2)),
+            _ => return Value::Error(format!("ERR unknown command '{}'", name)),
+        };
+        if !arity_ok {
+            return Value::Error(format!("ERR wrong number of arguments for '{}' command", name));
+        }
+        command(self, args).unwrap_or_else(|err| Value::Error(format!("ERR {}", err)))
+    }
+
+    fn ping(&mut self, args: &[ByteString]) -> Result<Value> {
+        Ok(match args.first() {
+            Some(message) => Value::Bulk(Some(message.clone())),
+            None => Value::Simple("PONG"),
+        })
+    }
+
+    fn get(&mut self, args: &[ByteString]) -> Result<Value> {
+        Ok(Value::Bulk(self.store.get(&args[0])?))
+    }
+
+    /// `SET key value [EX seconds | PX milliseconds] [NX | XX]`
+    fn set(&mut self, args: &[ByteString]) -> Result<Value> {
+        let (key, value) = (&args[0], &args[1]);
+        let (mut ttl, mut nx, mut xx) = (None, false, false);
+        let mut options = args[2..].iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                unit @ (b"EX" | b"PX") => {
+                    let amount = match options.next().map(|amount| parse_int(amount)) {
+                        Some(Some(amount)) if amount > 0 => amount as u64,
+                        Some(Some(_)) => return Ok(Value::Error("ERR invalid expire time in 'set' command".into())),
+                        Some(None) => return Ok(not_an_integer()),
+                        None => return Ok(syntax_error()),
+                    };
+                    ttl = Some(if unit == b"EX" { Duration::from_secs(amount) } else { Duration::from_millis(amount) });
+                },
+                _ => return Ok(syntax_error()),
+            }
+        }
+        if nx && xx {
+            return Ok(syntax_error());
+        }
+
+        let meta = ttl.map_or_else(RecordMeta::default, RecordMeta::expiring_in);
+        let written = if xx {
+            // the key has to exist when the value is written, not just before
+            loop {
+                let Some(version) = self.store.version(key) else { break false };
+                match self.store.append_if(key, Some(value), &meta, Some(Condition::Version(version))) {
+                    Err(Error::Conflict { .. }) => continue,
+                    result => break result.map(|()| true)?,
+                }
+            }
+        } else {
+            let condition = if nx { Some(Condition::Value(None)) } else { None };
+            match self.store.append_if(key, Some(value), &meta, condition) {
+                Ok(()) => true,
+                Err(Error::Conflict { .. }) => false,
+                Err(err) => return Err(err),
+            }
+        };
+        Ok(if written { Value::Simple("OK") } else { Value::Bulk(None) })
+    }
+
+    fn del(&mut self, args: &[ByteString]) -> Result<Value> {
+        let mut batch = WriteBatch::new();
+        let existing: BTreeSet<&ByteStr> = {
+            let kv = self.store.read();
+            args.iter().map(Vec::as_slice).filter(|key| kv.contains_key(key)).collect()
+        };
+        for key in &existing {
+            batch.delete(key);
+        }
+        self.store.write_batch(&batch)?;
+        Ok(Value::Integer(existing.len() as i64))
+    }
+
+    fn exists(&mut self, args: &[ByteString]) -> Result<Value> {
+        let kv = self.store.read();
+        Ok(Value::Integer(args.iter().filter(|key| kv.contains_key(key)).count() as i64))
+    }
+
+    fn keys(&mut self, args: &[ByteString]) -> Result<Value> {
+        let pattern = &args[0];
+        let (keys, _) = scan_keys(&self.store.read(), literal_prefix(pattern), None, usize::MAX);
+        Ok(matching(keys, pattern))
+    }
+
+    /// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]`
+    fn scan(&mut self, args: &[ByteString]) -> Result<Value> {
+        let Some(cursor) = parse_int(&args[0]).and_then(|cursor| u64::try_from(cursor).ok()) else {
+            return Ok(Value::Error("ERR invalid cursor".into()));
+        };
+        let (mut pattern, mut count, mut strings) = (&b"*"[..], DEFAULT_SCAN_COUNT, true);
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            let Some(argument) = options.next() else { return Ok(syntax_error()) };
+            match option.to_ascii_uppercase().as_slice() {
+                b"MATCH" => pattern = argument,
+                b"COUNT" => match parse_int(argument) {
+                    // only a hint, as in Redis
+                    Some(n) if n >= 1 => count = (n as usize).min(MAX_SCAN_LIMIT),
+                    Some(_) => return Ok(syntax_error()),
+                    None => return Ok(not_an_integer()),
+                },
+                // every value is a string
+                b"TYPE" => strings = argument.eq_ignore_ascii_case(b"string"),
+                _ => return Ok(syntax_error()),
+            }
+        }
+        let after = match cursor {
+            0 => None,
+            cursor => match self.cursors.get(&cursor) {
+                Some(after) => Some(after.clone()),
+                None => return Ok(Value::Error("ERR invalid cursor".into())),
+            },
+        };
+
+        let (keys, more) = scan_keys(&self.store.read(), literal_prefix(pattern), after.as_deref(), count);
+        let next = match keys.last() {
+            Some(last) if more => self.new_cursor(last.clone()),
+            _ => 0,
+        };
+        let keys = if strings { matching(keys, pattern) } else { Value::Array(Vec::new()) };
+        Ok(Value::Array(vec![Value::Bulk(Some(next.to_string().into_bytes())), keys]))
+    }
+
+    fn new_cursor(&mut self, after: ByteString) -> u64 {
+        let cursor = self.next_cursor;
+        self.next_cursor += 1;
+        self.cursors.insert(cursor, after);
+        if self.cursors.len() > MAX_CURSORS {
+            self.cursors.pop_first();
+        }
+        cursor
+    }
+
+    fn expire(&mut self, args: &[ByteString]) -> Result<Value> {
+        let key = &args[0];
+        let Some(seconds) = parse_int(&args[1]) else { return Ok(not_an_integer()) };
+        // a deadline that has already passed deletes the key
+        if seconds <= 0 {
+            return self.del(std::slice::from_ref(key));
+        }
+        let set = self.store.expire(key, Duration::from_secs(seconds as u64))?;
+        Ok(Value::Integer(set.into()))
+    }
+
+    fn ttl(&mut self, args: &[ByteString]) -> Result<Value> {
+        Ok(Value::Integer(match self.store.ttl(&args[0]) {
+            Ok(Some(left)) => left.as_millis().div_ceil(1000) as i64,
+            Ok(None) => -1,
+            Err(Error::NotFound) => -2,
+            Err(err) => return Err(err),
+        }))
+    }
+
+    fn mget(&mut self, args: &[ByteString]) -> Result<Value> {
+        let kv = self.store.read();
+        let values = args.iter().map(|key| Ok(Value::Bulk(kv.get(key)?))).collect::<Result<_>>()?;
+        Ok(Value::Array(values))
+    }
+
+    fn mset(&mut self, args: &[ByteString]) -> Result<Value> {
+        let mut batch = WriteBatch::new();
+        for pair in args.chunks(2) {
+            batch.insert(&pair[0], &pair[1]);
+        }
+        self.store.write_batch(&batch)?;
+        Ok(Value::Simple("OK"))
+    }
+}
+
+fn matching(keys: Vec<ByteString>, pattern: &ByteStr) -> Value {
+    Value::Array(keys.into_iter().filter(|key| glob_match(pattern, key)).map(|key| Value::Bulk(Some(key))).collect())
+}
+
+/// The part of a glob pattern before its first special character, which
+/// every matching key starts with.
+fn literal_prefix(pattern: &ByteStr) -> &ByteStr {
+    let end = pattern.iter().position(|b| matches!(b, b'*' | b'?' | b'[' | b'\\')).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/// Matches `text` against a Redis glob pattern: `*`, `?`, classes such as
+/// `[a-z]` or `[^abc]`, and `\` to escape the next character.
+fn glob_match(pattern: &ByteStr, text: &ByteStr) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // where to retry, one character further on, when the rest fails
+    let mut star = None;
+    while t < text.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, t));
+            continue;
+        }
+        let matched = if p < pattern.len() { match_one(&pattern[p..], text[t]) } else { None };
+        if let Some(len) = matched {
+            p += len;
+            t += 1;
+            continue;
+        }
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p;
+                t = star_t + 1;
+                star = Some((star_p, t));
+            },
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+// the length of the token at the start of `pattern` if it matches `c`
+fn match_one(pattern: &ByteStr, c: u8) -> Option<usize> {
+    match pattern[0] {
+        b'?' => Some(1),
+        b'\\' if pattern.len() > 1 => (pattern[1] == c).then_some(2),
+        b'[' => {
+            let mut i = 1;
+            let negate = pattern.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
+            }
+            let mut matched = false;
+            while i < pattern.len() && pattern[i] != b']' {
+                if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                    matched |= pattern[i + 1] == c;
+                    i += 2;
+                } else if pattern.get(i + 1) == Some(&b'-') && i + 2 < pattern.len() && pattern[i + 2] != b']' {
+                    let (low, high) = (pattern[i].min(pattern[i + 2]), pattern[i].max(pattern[i + 2]));
+                    matched |= (low..=high).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= pattern[i] == c;
+                    i += 1;
+                }
+            }
+            // an unclosed class runs to the end of the pattern
+            (matched != negate).then_some((i + 1).min(pattern.len()))
+        },
+        literal => (literal == c).then_some(1),
+    }
+}
@@ -0,0 +1,386 @@
+use std::{sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak}, thread, time::Duration};
+
+use crate::{conditional::Condition, ActionKv, AsOf, Durability, Revision, ByteStr, ByteString, Error, RecordMeta, Result, Snapshot, Transaction, WriteBatch};
+
+/// A cloneable, `Send + Sync` handle to one store.
+///
+/// Reads use positional I/O and only take a shared lock, so any number of
+/// threads can read in parallel. Writes are serialized: a writer appends to
+/// the log while readers carry on, then holds the exclusive lock only long
+/// enough to publish the new record to the index. Readers therefore never
+/// see a key whose record is not fully written.
+///
+/// A store opened with `ActionKvOptions::background_compaction` is merged
+/// by a background thread for as long as any handle to it exists, and one
+/// opened with `Durability::SyncInterval` is synced by another, so writes
+/// followed by a quiet spell are still synced within the interval.
+///
+/// Reads and writes through a `SharedKv`, and the snapshots, history and
+/// transactions it hands out, only see the default namespace. Other
+/// namespaces are reached through `ActionKv::namespace` before the store is
+/// shared.
+#[derive(Debug, Clone)]
+pub struct SharedKv {
+    inner: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    kv: RwLock<ActionKv>,
+    writer: Mutex<()>,
+    // held for the whole of a merge or compaction so they never overlap
+    merging: Mutex<()>,
+    background_error: Mutex<Option<Error>>,
+}
+
+impl SharedKv {
+    pub fn new(kv: ActionKv) -> Self {
+        let policy = kv.compaction_policy();
+        let durability = kv.durability();
+        let shared = SharedKv {
+            inner: Arc::new(Shared {
+                kv: RwLock::new(kv),
+                writer: Mutex::new(()),
+                merging: Mutex::new(()),
+                background_error: Mutex::new(None),
+            }),
+        };
+        if let Some(policy) = policy {
+            let weak = Arc::downgrade(&shared.inner);
+            thread::Builder::new()
+                .name("kstore-compaction".into())
+                .spawn(move || compaction_thread(weak, policy.check_interval))
+                .expect("failed to spawn the compaction thread");
+        }
+        if let Durability::SyncInterval(interval) = durability {
+            let weak = Arc::downgrade(&shared.inner);
+            thread::Builder::new()
+                .name("kstore-sync".into())
+                .spawn(move || sync_thread(weak, interval))
+                .expect("failed to spawn the sync thread");
+        }
+        shared
+    }
+
+    /// Shared access to the store for scans (`iter`, `range`, `prefix`,
+    /// `find`) and accessors. Writers wait to publish while the guard is held.
+    pub fn read(&self) -> RwLockReadGuard<'_, ActionKv> {
+        self.inner.kv.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, ActionKv> {
+        self.inner.kv.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_writer(&self) -> MutexGuard<'_, ()> {
+        self.inner.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_merging(&self) -> MutexGuard<'_, ()> {
+        self.inner.merging.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self, key: &ByteStr) -> Result<Option<ByteString>> {
+        self.read().get(key)
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.read().contains_key(key)
+    }
+
+    pub fn ttl(&self, key: &ByteStr) -> Result<Option<Duration>> {
+        self.read().ttl(key)
+    }
+
+    pub fn version(&self, key: &ByteStr) -> Option<u64> {
+        self.read().version(key)
+    }
+
+    pub fn get_versioned(&self, key: &ByteStr) -> Result<Option<(ByteString, u64)>> {
+        self.read().get_versioned(key)
+    }
+
+    pub fn last_seq(&self) -> Option<u64> {
+        self.read().last_seq()
+    }
+
+    /// See `ActionKv::history`. The whole log is read under the shared lock,
+    /// so writers wait to publish until it has been.
+    pub fn history(&self, key: &ByteStr) -> Result<Vec<Revision>> {
+        self.read().history(key)
+    }
+
+    /// See `ActionKv::get_as_of`; holds up writers the way `history` does.
+    pub fn get_as_of(&self, key: &ByteStr, at: impl Into<AsOf>) -> Result<Option<ByteString>> {
+        self.read().get_as_of(key, at)
+    }
+
+    /// Starts a transaction over any number of keys; see `Transaction`.
+    pub fn begin(&self) -> Transaction {
+        Transaction::new(self.clone())
+    }
+
+    /// A read view of the store as it is now, which later writes do not
+    /// change. It starts with a copy of the index, so taking one is O(n) in
+    /// the number of keys.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.clone())
+    }
+
+    /// The number of keys that have not expired; see `Index::live_len`.
+    pub fn len(&self) -> usize {
+        self.read().index.live_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.append(key, Some(value), &RecordMeta::default())
+    }
+
+    pub fn insert_with_ttl(&self, key: &ByteStr, value: &ByteStr, ttl: Duration) -> Result<()> {
+        self.append(key, Some(value), &RecordMeta::expiring_in(ttl))
+    }
+
+    pub fn update(&self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.insert(key, value)
+    }
+
+    /// See `ActionKv::expire`.
+    pub fn expire(&self, key: &ByteStr, ttl: Duration) -> Result<bool> {
+        loop {
+            let (value, version) = match self.get_versioned(key)? {
+                Some(found) => found,
+                None => return Ok(false),
+            };
+            match self.append_if(key, Some(&value), &RecordMeta::expiring_in(ttl), Some(Condition::Version(version))) {
+                Err(Error::Conflict { .. }) => continue,
+                result => return result.map(|()| true),
+            }
+        }
+    }
+
+    pub fn delete(&self, key: &ByteStr) -> Result<()> {
+        self.append(key, None, &RecordMeta::default())
+    }
+
+    /// See `ActionKv::compare_and_swap`. The check and the write happen
+    /// under the writer lock, so no other write can slip in between.
+    pub fn compare_and_swap(&self, key: &ByteStr, expected: Option<&ByteStr>, new: Option<&ByteStr>) -> Result<()> {
+        if new.is_none() && expected.is_none() {
+            return self.read().check(key, Condition::Value(None));
+        }
+        self.append_if(key, new, &RecordMeta::default(), Some(Condition::Value(expected)))
+    }
+
+    pub fn insert_if_absent(&self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.compare_and_swap(key, None, Some(value))
+    }
+
+    pub fn update_if_version(&self, key: &ByteStr, version: u64, value: &ByteStr) -> Result<()> {
+        self.append_if(key, Some(value), &RecordMeta::default(), Some(Condition::Version(version)))
+    }
+
+    fn append(&self, key: &ByteStr, value: Option<&ByteStr>, meta: &RecordMeta) -> Result<()> {
+        self.append_if(key, value, meta, None)
+    }
+
+    pub(crate) fn append_if(&self, key: &ByteStr, value: Option<&ByteStr>, meta: &RecordMeta, condition: Option<Condition>) -> Result<()> {
+        let _writer = self.lock_writer();
+        let (entry, synced) = {
+            let kv = self.read();
+            if let Some(condition) = condition {
+                kv.check(key, condition)?;
+            }
+            kv.append_detached(key, value, meta)?
+        };
+        {
+            let mut kv = self.write();
+            kv.note_appended(entry.segment, entry.offset, 1, synced);
+            match value {
+                Some(_) => kv.index_insert(key.to_vec(), entry),
+                None => kv.index_remove(meta.namespace.unwrap_or(0), key),
+            }
+            kv.maybe_rotate()?;
+        }
+        self.maybe_write_hint()
+    }
+
+    /// See `ActionKv::write_batch`; none of the batch is visible to readers
+    /// until all of it is.
+    pub fn write_batch(&self, batch: &WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.write_batch_if(batch, &[])
+    }
+
+    /// Like `write_batch`, but only if every key in `conditions` meets its
+    /// condition, which is checked even when `batch` is empty.
+    pub(crate) fn write_batch_if(&self, batch: &WriteBatch, conditions: &[(&ByteStr, Condition)]) -> Result<()> {
+        let _writer = self.lock_writer();
+        let (segment, frame_position, entries) = {
+            let kv = self.read();
+            for (key, condition) in conditions {
+                kv.check(key, *condition)?;
+            }
+            if batch.is_empty() {
+                return Ok(());
+            }
+            kv.write_batch_detached(batch)?
+        };
+        {
+            let mut kv = self.write();
+            kv.apply_batch(batch, segment, frame_position, entries);
+            kv.maybe_rotate()?;
+        }
+        self.maybe_write_hint()
+    }
+
+    // the caller holds the writer lock, so nothing is appended while the
+    // hint is written under the shared lock
+    fn maybe_write_hint(&self) -> Result<()> {
+        let kv = self.read();
+        if !kv.hint_due() {
+            return Ok(());
+        }
+        kv.store_hint()?;
+        drop(kv);
+        self.write().hint_written();
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.read().flush()
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        let _writer = self.lock_writer();
+        self.read().sync_data()?;
+        self.write().mark_synced();
+        Ok(())
+    }
+
+    /// Compacts the log. Readers are blocked for the whole rewrite; `merge`
+    /// does the same for a segmented store without blocking them.
+    pub fn compact(&self) -> Result<()> {
+        let _merging = self.lock_merging();
+        let _writer = self.lock_writer();
+        self.write().compact()
+    }
+
+    /// Merges every segment of a segmented store into new ones holding only
+    /// live records. Reads and writes carry on while the records are copied;
+    /// the store is only locked to rotate the active segment at the start and
+    /// to swap the new segments in at the end. The log is walked for the
+    /// history to keep before that, and again under the lock only for the
+    /// records appended meanwhile.
+    pub fn merge(&self) -> Result<()> {
+        let _merging = self.lock_merging();
+        let mut retention = self.read().retention()?;
+        if let Some(retention) = &mut retention {
+            retention.walk()?;
+        }
+        let mut plan = {
+            let _writer = self.lock_writer();
+            let survivors = self.read().survivors(retention)?;
+            self.write().begin_merge(survivors)?
+        };
+        if let Err(err) = plan.write() {
+            plan.discard();
+            return Err(err);
+        }
+        let _writer = self.lock_writer();
+        self.write().finish_merge(plan)
+    }
+
+    // Syncs writes that no later write has synced once the interval has
+    // passed, and returns how long to wait before looking again; `None`
+    // once the store is closed.
+    fn sync_overdue(&self, interval: Duration) -> Result<Option<Duration>> {
+        let _writer = self.lock_writer();
+        {
+            let kv = self.read();
+            if kv.closed {
+                return Ok(None);
+            }
+            let elapsed = kv.last_sync.elapsed();
+            if kv.unsynced_writes == 0 {
+                return Ok(Some(interval));
+            }
+            if elapsed < interval {
+                return Ok(Some(interval - elapsed));
+            }
+            kv.sync_data()?;
+        }
+        self.write().mark_synced();
+        Ok(Some(interval))
+    }
+
+    /// The error of the last background merge or sync that failed, if any.
+    pub fn take_background_error(&self) -> Option<Error> {
+        self.inner.background_error.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+
+    pub fn close(&self) -> Result<()> {
+        let _writer = self.lock_writer();
+        self.write().close()
+    }
+
+    /// Returns the store if this is the last handle to it.
+    pub fn into_inner(self) -> Option<ActionKv> {
+        Arc::try_unwrap(self.inner).ok().map(|shared| shared.kv.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+fn compaction_thread(shared: Weak<Shared>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let kv = match shared.upgrade() {
+            Some(inner) => SharedKv { inner },
+            None => return,
+        };
+        let due = {
+            let store = kv.read();
+            if store.closed {
+                return;
+            }
+            store.compaction_due()
+        };
+        if let Err(err) = due.and_then(|due| if due { kv.merge() } else { Ok(()) }) {
+            *kv.inner.background_error.lock().unwrap_or_else(PoisonError::into_inner) = Some(err);
+        }
+    }
+}
+
+fn sync_thread(shared: Weak<Shared>, interval: Duration) {
+    let mut wait = interval;
+    loop {
+        thread::sleep(wait);
+        let kv = match shared.upgrade() {
+            Some(inner) => SharedKv { inner },
+            None => return,
+        };
+        wait = match kv.sync_overdue(interval) {
+            Ok(Some(wait)) => wait,
+            Ok(None) => return,
+            Err(err) => {
+                *kv.inner.background_error.lock().unwrap_or_else(PoisonError::into_inner) = Some(err);
+                interval
+            },
+        };
+    }
+}
+
+impl From<ActionKv> for SharedKv {
+    fn from(kv: ActionKv) -> Self {
+        SharedKv::new(kv)
+    }
+}
+
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedKv>();
+};
@@ -0,0 +1,213 @@
+//! Read views pinned at one point in the log.
+//!
+//! A snapshot starts out with a copy of the index, which keeps pointing at
+//! the right records until compaction moves them. From then on it rebuilds
+//! its copy from the log, taking for each key the last record up to its
+//! seq; compaction keeps those records and their seqs for as long as the
+//! snapshot is pinned.
+//!
+//! The copy is what a snapshot costs: taking one clones every key of the
+//! default namespace under the shared lock, so it is O(n) in time and
+//! memory. Filtering the store's own index by seq instead would not do, as
+//! a delete after the snapshot takes the key out of it altogether.
+
+use std::{collections::{BTreeMap, BTreeSet}, ops::{Bound, RangeBounds}, sync::{Arc, Mutex, MutexGuard, PoisonError}, vec};
+
+use crate::{index, ActionKv, ByteStr, ByteString, Index, IndexEntry, Result, SharedKv};
+
+/// A consistent read view of a `SharedKv`, taken by `SharedKv::snapshot`.
+/// Reads through it see the store as it was when it was taken and ignore
+/// every later write; values still expire as time passes. Like the
+/// `SharedKv` it came from, it only covers the default namespace.
+///
+/// Taking one copies the index, in time and memory proportional to the
+/// number of keys, so it pays off over many reads rather than one.
+///
+/// Records the snapshot reads survive compaction until it is dropped. The
+/// ones a rewrite keeps only for it count as retained bytes rather than
+/// dead, so holding a snapshot does not keep the compaction policy merging.
+#[derive(Debug)]
+pub struct Snapshot {
+    kv: SharedKv,
+    seq: Option<u64>,
+    pins: Arc<Mutex<BTreeMap<u64, usize>>>,
+    view: Mutex<View>,
+}
+
+/// The index of a snapshot and the compaction generation it is valid for.
+#[derive(Debug)]
+struct View {
+    generation: u64,
+    index: Index,
+}
+
+impl Snapshot {
+    pub(crate) fn new(kv: SharedKv) -> Self {
+        let (seq, pins, view) = {
+            let store = kv.read();
+            let seq = store.last_seq();
+            if let Some(seq) = seq {
+                *lock(&store.snapshots).entry(seq).or_default() += 1;
+            }
+            (seq, store.snapshots.clone(), View { generation: store.generation, index: store.index.clone() })
+        };
+        Snapshot { kv, seq, pins, view: Mutex::new(view) }
+    }
+
+    /// The seq of the newest record the snapshot sees, `None` if the store
+    /// was empty.
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    pub fn get(&self, key: &ByteStr) -> Result<Option<ByteString>> {
+        let store = self.kv.read();
+        let entry = match self.view(&store)?.index.get(key) {
+            Some(entry) if !entry.is_expired() => *entry,
+            _ => return Ok(None),
+        };
+        Ok(Some(store.get_at(entry.segment, entry.offset)?.value))
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> Result<bool> {
+        let store = self.kv.read();
+        Ok(self.view(&store)?.index.get(key).is_some_and(|entry| !entry.is_expired()))
+    }
+
+    /// All pairs in key order.
+    pub fn iter(&self) -> Result<SnapshotIter<'_>> {
+        self.range::<&ByteStr, _>(..)
+    }
+
+    /// Pairs whose keys fall within `range`, in key order.
+    pub fn range<K: AsRef<ByteStr>, R: RangeBounds<K>>(&self, range: R) -> Result<SnapshotIter<'_>> {
+        let start = range.start_bound().map(|key| key.as_ref());
+        let end = range.end_bound().map(|key| key.as_ref());
+        let store = self.kv.read();
+        let keys: Vec<ByteString> = self.view(&store)?.index.range(start, end).map(|(key, _)| key.clone()).collect();
+        Ok(SnapshotIter { snapshot: self, keys: keys.into_iter() })
+    }
+
+    /// Pairs whose keys start with `prefix`, in key order.
+    pub fn prefix(&self, prefix: &ByteStr) -> Result<SnapshotIter<'_>> {
+        match index::prefix_end(prefix) {
+            Some(end) => self.range::<&ByteStr, _>((Bound::Included(prefix), Bound::Excluded(end.as_slice()))),
+            None => self.range::<&ByteStr, _>((Bound::Included(prefix), Bound::Unbounded)),
+        }
+    }
+
+    // the index of the snapshot, rebuilt if compaction has moved records
+    // since it was last resolved
+    fn view(&self, store: &ActionKv) -> Result<MutexGuard<'_, View>> {
+        let mut view = lock(&self.view);
+        if view.generation != store.generation {
+            view.index = match self.seq {
+                Some(seq) => store.index_at(seq)?,
+                None => Index::new(store.index.kind()),
+            };
+            view.generation = store.generation;
+        }
+        Ok(view)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let Some(seq) = self.seq else {
+            return;
+        };
+        let mut pins = lock(&self.pins);
+        if let Some(count) = pins.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                pins.remove(&seq);
+            }
+        }
+    }
+}
+
+/// Reads the pairs of a snapshot one at a time. The keys are fixed when the
+/// iterator is created; each value is read as the iterator reaches it, so
+/// writers are not held up for the length of a scan. Supports `.rev()`.
+pub struct SnapshotIter<'a> {
+    snapshot: &'a Snapshot,
+    keys: vec::IntoIter<ByteString>,
+}
+
+impl SnapshotIter<'_> {
+    // skips keys that expired since the iterator was created
+    fn read(&self, key: ByteString) -> Option<Result<(ByteString, ByteString)>> {
+        match self.snapshot.get(&key) {
+            Ok(Some(value)) => Some(Ok((key, value))),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+impl Iterator for SnapshotIter<'_> {
+    type Item = Result<(ByteString, ByteString)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self.keys.next()?;
+            if let Some(item) = self.read(key) {
+                return Some(item);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.keys.len()))
+    }
+}
+
+impl DoubleEndedIterator for SnapshotIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self.keys.next_back()?;
+            if let Some(item) = self.read(key) {
+                return Some(item);
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl ActionKv {
+    /// The seqs live snapshots are pinned at.
+    pub(crate) fn pinned_seqs(&self) -> BTreeSet<u64> {
+        lock(&self.snapshots).keys().copied().collect()
+    }
+
+    /// The index as it stood just after the record with `seq` was appended.
+    pub(crate) fn index_at(&self, seq: u64) -> Result<Index> {
+        let mut index = Index::new(self.index.kind());
+        self.walk_log(|record| {
+            if record.seq > seq || record.namespace != 0 {
+                return;
+            }
+            match record.value {
+                Some(_) => {
+                    let entry = IndexEntry {
+                        segment: record.segment,
+                        offset: record.offset,
+                        len: record.len,
+                        seq: record.seq,
+                        version: record.meta.version_at(record.seq),
+                        expires_at: record.meta.expires_at,
+                        namespace: 0,
+                    };
+                    index.insert(record.key, entry);
+                },
+                None => {
+                    index.remove(&record.key);
+                },
+            }
+        })?;
+        Ok(index)
+    }
+}
@@ -0,0 +1,80 @@
+//! Optimistic transactions: nothing is locked while one runs, and `commit`
+//! fails if another writer got to a key it read first.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{conditional::Condition, ByteStr, ByteString, Result, SharedKv, WriteBatch};
+
+/// Reads and writes over several keys of a `SharedKv`'s default namespace
+/// that take effect together or not at all, begun by `SharedKv::begin`.
+///
+/// Writes are buffered until `commit`, and reads through the transaction
+/// see them. `commit` fails with `Error::Conflict` if a key the transaction
+/// read has been written since, and otherwise appends every write as one
+/// batch frame. A transaction that is aborted, dropped or fails to commit
+/// leaves nothing in the log or the index.
+///
+/// A key read as absent has no version to check, and one that was written
+/// and deleted again in the meantime looks the same as before. So a
+/// transaction that read an absent key also conflicts when any key of the
+/// store has been deleted, or has expired and been compacted away, since
+/// the read.
+#[derive(Debug)]
+pub struct Transaction {
+    kv: SharedKv,
+    /// What each key has to still be at commit, as first read.
+    reads: HashMap<ByteString, Condition<'static>>,
+    writes: BTreeMap<ByteString, Option<ByteString>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(kv: SharedKv) -> Self {
+        Transaction { kv, reads: HashMap::new(), writes: BTreeMap::new() }
+    }
+
+    /// The value of `key`, as written by this transaction if it has been.
+    pub fn get(&mut self, key: &ByteStr) -> Result<Option<ByteString>> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        let kv = self.kv.read();
+        let found = kv.get_versioned(key)?;
+        let condition = match &found {
+            Some((_, version)) => Condition::Version(*version),
+            None => kv.absence(key),
+        };
+        self.reads.entry(key.to_vec()).or_insert(condition);
+        Ok(found.map(|(value, _)| value))
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> &mut Self {
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+        self
+    }
+
+    pub fn delete(&mut self, key: &ByteStr) -> &mut Self {
+        self.writes.insert(key.to_vec(), None);
+        self
+    }
+
+    /// Checks that every key read is still at the version it was read at, or
+    /// still absent, and appends the writes. Versions are never reused, so a
+    /// key that was deleted and written again since also conflicts. A
+    /// transaction that only read still checks.
+    pub fn commit(self) -> Result<()> {
+        let conditions: Vec<(&ByteStr, Condition)> = self.reads.iter()
+            .map(|(key, condition)| (key.as_slice(), *condition))
+            .collect();
+        let mut batch = WriteBatch::new();
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => batch.insert(key, value),
+                None => batch.delete(key),
+            };
+        }
+        self.kv.write_batch_if(&batch, &conditions)
+    }
+
+    /// Drops the transaction and everything it buffered.
+    pub fn abort(self) {}
+}
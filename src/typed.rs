@@ -0,0 +1,124 @@
+use std::{marker::PhantomData, path::Path, time::Duration};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{ActionKv, ActionKvOptions, Codec, Error, Result};
+
+/// A store whose keys and values are serde types, encoded with one codec.
+///
+/// The codec is recorded in the file header when the store is created and
+/// opening it with any other codec fails with `Error::CodecMismatch`. Keys
+/// are compared by their encoding, so scans run in the order of the encoded
+/// bytes rather than the order of `K`.
+///
+/// ```no_run
+/// # use kstore::{ActionKvOptions, Codec, TypedStore};
+/// let mut users: TypedStore<u64, String> = TypedStore::open_with(
+///     "users.kv".as_ref(),
+///     Codec::Json,
+///     ActionKvOptions::new().create_if_missing(true),
+/// )?;
+/// users.insert(&7, &"ada".to_string())?;
+/// # Ok::<(), kstore::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct TypedStore<K, V> {
+    kv: ActionKv,
+    codec: Codec,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> TypedStore<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn open(path: &Path, codec: Codec) -> Result<Self> {
+        TypedStore::open_with(path, codec, &ActionKvOptions::default())
+    }
+
+    /// Opens and loads the store with `options`; a new file records `codec`.
+    pub fn open_with(path: &Path, codec: Codec, options: &ActionKvOptions) -> Result<Self> {
+        let mut options = options.clone();
+        options.codec = Some(codec);
+        TypedStore::new(options.open(path)?, codec)
+    }
+
+    /// Wraps a loaded store, which has to have been created with `codec`.
+    pub fn new(kv: ActionKv, codec: Codec) -> Result<Self> {
+        if kv.codec() != Some(codec) {
+            return Err(Error::CodecMismatch { expected: codec, found: kv.codec() });
+        }
+        Ok(TypedStore { kv, codec, types: PhantomData })
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        match self.kv.get(&self.codec.encode(key)?)? {
+            Some(value) => Ok(Some(self.codec.decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> Result<bool> {
+        Ok(self.kv.contains_key(&self.codec.encode(key)?))
+    }
+
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<()> {
+        self.kv.insert(&self.codec.encode(key)?, &self.codec.encode(value)?)
+    }
+
+    pub fn insert_with_ttl(&mut self, key: &K, value: &V, ttl: Duration) -> Result<()> {
+        self.kv.insert_with_ttl(&self.codec.encode(key)?, &self.codec.encode(value)?, ttl)
+    }
+
+    pub fn insert_if_absent(&mut self, key: &K, value: &V) -> Result<()> {
+        self.kv.insert_if_absent(&self.codec.encode(key)?, &self.codec.encode(value)?)
+    }
+
+    pub fn update(&mut self, key: &K, value: &V) -> Result<()> {
+        self.insert(key, value)
+    }
+
+    pub fn delete(&mut self, key: &K) -> Result<()> {
+        self.kv.delete(&self.codec.encode(key)?)
+    }
+
+    /// Every live pair, decoded lazily.
+    pub fn iter(&self) -> Result<impl DoubleEndedIterator<Item = Result<(K, V)>> + '_> {
+        let codec = self.codec;
+        Ok(self.kv.iter()?.map(move |pair| {
+            let (key, value) = pair?;
+            Ok((codec.decode(&key)?, codec.decode(&value)?))
+        }))
+    }
+
+    /// The number of keys that have not expired; see `Index::live_len`.
+    pub fn len(&self) -> usize {
+        self.kv.index.live_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn compact(&mut self) -> Result<()> {
+        self.kv.compact()
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.kv.close()
+    }
+
+    /// The underlying store, for the operations that work on raw bytes.
+    pub fn inner(&self) -> &ActionKv {
+        &self.kv
+    }
+
+    pub fn into_inner(self) -> ActionKv {
+        self.kv
+    }
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::fs::{self, OpenOptions};
+
+use common::{open, TempDir};
+use kstore::{ActionKvOptions, WriteBatch};
+
+fn batch() -> WriteBatch {
+    let mut batch = WriteBatch::new();
+    batch.insert(b"a", b"2").insert(b"b", b"2").delete(b"c").insert(b"d", b"2");
+    batch
+}
+
+#[test]
+fn batch_applies_all_at_once() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"c", b"1").unwrap();
+    kv.write_batch(&batch()).unwrap();
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(kv.get(b"c").unwrap(), None);
+    kv.close().unwrap();
+
+    let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(kv.get(b"c").unwrap(), None);
+    assert_eq!(kv.get(b"d").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn batch_cut_short_by_a_crash_is_dropped_whole() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"c", b"1").unwrap();
+    kv.close().unwrap();
+    let before = fs::metadata(&path).unwrap().len();
+
+    let mut kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    kv.write_batch(&batch()).unwrap();
+    kv.close().unwrap();
+    let after = fs::metadata(&path).unwrap().len();
+
+    // every point the frame could have been cut at, down to its first byte
+    for len in (before + 1..after).rev() {
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len).unwrap();
+        let mut kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+        assert_eq!(kv.load_report().discarded_bytes, len - before, "cut at {}", len);
+        assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kv.get(b"b").unwrap(), None);
+        assert_eq!(kv.get(b"c").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kv.get(b"d").unwrap(), None);
+        kv.close().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), before);
+
+        // put the frame back for the next cut
+        let mut kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+        kv.write_batch(&batch()).unwrap();
+        kv.close().unwrap();
+    }
+}
+
+#[test]
+fn batch_with_a_damaged_byte_is_dropped_whole() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"c", b"1").unwrap();
+    kv.write_batch(&batch()).unwrap();
+    kv.close().unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    // the last byte of the frame, so the frame is the torn tail
+    let mut data = fs::read(&path).unwrap();
+    *data.last_mut().unwrap() ^= 0xff;
+    fs::write(&path, &data).unwrap();
+
+    let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert!(kv.load_report().discarded_bytes > 0);
+    assert_eq!(kv.get(b"a").unwrap(), None);
+    assert_eq!(kv.get(b"c").unwrap(), Some(b"1".to_vec()));
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use std::{fs, io::Write, path::Path, process::{Command, Output, Stdio}};
+
+use common::{open, open_segmented, TempDir};
+
+fn kstore(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kstore")).args(args).output().unwrap()
+}
+
+fn kstore_with_stdin(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kstore")).args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn code(output: &Output) -> i32 {
+    output.status.code().unwrap()
+}
+
+#[test]
+fn commands() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let file = path_str(&path);
+
+    assert_eq!(code(&kstore(&[file, "insert", "a", "1"])), 0);
+    assert_eq!(code(&kstore(&[file, "insert", "ab", "2"])), 0);
+    assert_eq!(code(&kstore_with_stdin(&[file, "insert", "b"], b"from\nstdin")), 0);
+
+    let output = kstore(&[file, "get", "a"]);
+    assert_eq!((code(&output), stdout(&output)), (0, "1\n"));
+    assert_eq!(stdout(&kstore(&[file, "get", "b"])), "from\nstdin\n");
+    assert_eq!(stdout(&kstore(&[file, "list"])), "a\t1\nab\t2\nb\tfrom\nstdin\n");
+    assert_eq!(stdout(&kstore(&[file, "list", "a"])), "a\t1\nab\t2\n");
+    assert_eq!(stdout(&kstore(&[file, "list", "z"])), "");
+
+    assert_eq!(code(&kstore(&[file, "update", "a", "3"])), 0);
+    assert_eq!(stdout(&kstore(&[file, "get", "a"])), "3\n");
+    assert_eq!(code(&kstore(&[file, "delete", "ab"])), 0);
+    assert_eq!(stdout(&kstore(&[file, "list"])), "a\t3\nb\tfrom\nstdin\n");
+
+    let output = kstore(&[file, "find", "a"]);
+    assert_eq!(code(&output), 0);
+    let (offset, value) = stdout(&output).trim_end().split_once('\t').unwrap();
+    assert!(offset.parse::<u64>().is_ok());
+    assert_eq!(value, "3");
+
+    let output = kstore(&[file, "stats"]);
+    assert_eq!(code(&output), 0);
+    assert!(stdout(&output).lines().any(|line| line == "keys            2"), "{}", stdout(&output));
+
+    // reads never change the file
+    let before = fs::read(&path).unwrap();
+    kstore(&[file, "get", "a"]);
+    kstore(&[file, "list"]);
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn formats_and_hex_input() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let file = path_str(&path);
+
+    assert_eq!(code(&kstore(&["--hex-input", file, "insert", "00ff", "c3a9"])), 0);
+    assert_eq!(stdout(&kstore(&["--hex-input", file, "get", "00FF"])), "é\n");
+    assert_eq!(stdout(&kstore(&["--format", "hex", file, "list"])), "00ff\tc3a9\n");
+    assert_eq!(kstore(&["--format", "raw", file, "list"]).stdout, b"\x00\xff\xc3\xa9");
+    // invalid UTF-8 is replaced rather than printed as is
+    assert_eq!(stdout(&kstore(&[file, "list"])), "\0\u{fffd}\té\n");
+
+    for arg in ["0", "zz", "+1", "-1"] {
+        assert_eq!(code(&kstore(&["--hex-input", file, "get", arg])), 2, "{}", arg);
+    }
+    assert_eq!(code(&kstore(&["--format", "base64", file, "list"])), 2);
+}
+
+#[test]
+fn segmented_store() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_segmented(&path, 128);
+    for i in 0..20u32 {
+        kv.insert(format!("k{:02}", i).as_bytes(), b"value").unwrap();
+    }
+    kv.close().unwrap();
+    let file = path_str(&path);
+
+    assert_eq!(stdout(&kstore(&[file, "get", "k07"])), "value\n");
+    let output = kstore(&[file, "find", "k19"]);
+    let (position, _) = stdout(&output).split_once('\t').unwrap();
+    let (segment, offset) = position.split_once(':').unwrap();
+    assert!(segment.parse::<u32>().unwrap() > 0 && offset.parse::<u64>().is_ok());
+    assert!(stdout(&kstore(&[file, "stats"])).lines().any(|line| line.starts_with("segments ")));
+}
+
+#[test]
+fn exit_codes() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let file = path_str(&path);
+    let mut kv = open(&path);
+    kv.insert(b"a", b"1").unwrap();
+    kv.close().unwrap();
+
+    // 1: no such key
+    for args in [[file, "get", "b"], [file, "delete", "b"], [file, "update", "b"], [file, "find", "b"]] {
+        let output = kstore(&args);
+        assert_eq!(code(&output), 1, "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "key not found\n");
+    }
+    assert_eq!(stdout(&kstore(&[file, "list"])), "a\t1\n");
+
+    // 2: bad usage
+    for args in [&[][..], &[file], &[file, "fetch", "a"], &[file, "get"], &[file, "get", "a", "b"], &[file, "stats", "x"], &[file, "migrate", "a", "b"], &["--format"]] {
+        let output = kstore(args);
+        assert_eq!(code(&output), 2, "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Usage:"), "{:?}", args);
+    }
+    let help = kstore(&["--help"]);
+    assert_eq!(code(&help), 0);
+    assert!(stdout(&help).contains("Exit status"));
+
+    // 3: not a kstore file, or damaged
+    let junk = dir.join("junk");
+    fs::write(&junk, b"this is not a kstore file at all").unwrap();
+    assert_eq!(code(&kstore(&[path_str(&junk), "get", "a"])), 3);
+    assert_eq!(code(&kstore(&[file, "insert", "c", "3"])), 0);
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+    let mut data = fs::read(&path).unwrap();
+    let value = data.windows(2).position(|w| w == b"a1").unwrap() + 1;
+    data[value] = b'2';
+    fs::write(&path, &data).unwrap();
+    for args in [&[file, "get", "c"][..], &[file, "stats"]] {
+        assert_eq!(code(&kstore(args)), 3, "{:?}", args);
+    }
+
+    // 4: anything else, such as a store that is not there
+    let missing = dir.join("missing");
+    assert_eq!(code(&kstore(&[path_str(&missing), "get", "a"])), 4);
+    assert!(!missing.exists());
+}
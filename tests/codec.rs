@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use kstore::{Codec, Error};
+use serde_derive::{Deserialize, Serialize};
+
+const CODECS: [Codec; 4] = [Codec::Bincode, Codec::Json, Codec::Cbor, Codec::MessagePack];
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Line(i32, i32),
+    Rect { width: u16, height: u16 },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Record {
+    id: u64,
+    delta: i64,
+    ratio: f64,
+    flag: bool,
+    name: String,
+    tags: Vec<String>,
+    bytes: Vec<u8>,
+    parent: Option<u32>,
+    shapes: Vec<Shape>,
+    counts: BTreeMap<String, i8>,
+    pair: (u8, char),
+}
+
+fn record() -> Record {
+    Record {
+        id: u64::MAX,
+        delta: i64::MIN,
+        ratio: -1.5e-7,
+        flag: true,
+        name: "quote \" slash \\ tab \t nul \u{0} e\u{301} \u{1f600}".into(),
+        tags: vec![String::new(), "x".repeat(300)],
+        bytes: (0..=255).collect(),
+        parent: None,
+        shapes: vec![Shape::Empty, Shape::Circle(0.25), Shape::Line(-1, 70000), Shape::Rect { width: 3, height: 65535 }],
+        counts: [("a".to_string(), -128), ("b".to_string(), 127)].into_iter().collect(),
+        pair: (0, 'é'),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Nested(Vec<Nested>);
+
+fn nested(depth: usize) -> Nested {
+    (0..depth).fold(Nested(Vec::new()), |inner, _| Nested(vec![inner]))
+}
+
+fn is_codec_error<T>(result: Result<T, Error>) -> bool {
+    matches!(result, Err(Error::Codec(_)))
+}
+
+#[test]
+fn every_codec_round_trips() {
+    for codec in CODECS {
+        let data = codec.encode(&record()).unwrap();
+        assert_eq!(codec.decode::<Record>(&data).unwrap(), record(), "{}", codec);
+
+        for value in [0, 23, 24, 255, 256, 65535, 65536, u32::MAX as i64 + 1, -1, -32, -33, -129, i64::MIN] {
+            let data = codec.encode(&value).unwrap();
+            assert_eq!(codec.decode::<i64>(&data).unwrap(), value, "{} {}", codec, value);
+        }
+        for len in [0, 15, 16, 31, 32, 255, 256, 65536] {
+            let text = "a".repeat(len);
+            let data = codec.encode(&text).unwrap();
+            assert_eq!(codec.decode::<String>(&data).unwrap(), text, "{} {}", codec, len);
+        }
+    }
+}
+
+#[test]
+fn nesting_below_the_limit_round_trips() {
+    for codec in CODECS {
+        let data = codec.encode(&nested(100)).unwrap();
+        assert_eq!(codec.decode::<Nested>(&data).unwrap(), nested(100), "{}", codec);
+    }
+}
+
+#[test]
+fn truncated_input_is_an_error() {
+    for codec in CODECS {
+        let data = codec.encode(&record()).unwrap();
+        for len in 0..data.len() {
+            assert!(is_codec_error(codec.decode::<Record>(&data[..len])), "{} cut at {}", codec, len);
+        }
+    }
+}
+
+#[test]
+fn trailing_bytes_are_an_error() {
+    for codec in CODECS {
+        let mut data = codec.encode(&record()).unwrap();
+        data.push(b'x');
+        assert!(is_codec_error(codec.decode::<Record>(&data)), "{}", codec);
+    }
+}
+
+#[test]
+fn json_surrogates_must_pair() {
+    let json = |text: &str| Codec::Json.decode::<String>(text.as_bytes());
+    assert_eq!(json(r#""\ud83d\ude00""#).unwrap(), "\u{1f600}");
+    assert_eq!(json(r#""\u00e9\u0041""#).unwrap(), "éA");
+
+    for text in [
+        r#""\ud83d""#,
+        r#""\ud83dx""#,
+        r#""\ud83dA""#,
+        r#""\ud83d\ud83d""#,
+        r#""\ude00""#,
+        r#""\ude00\ud83d""#,
+        r#""\u+041""#,
+        r#""\u00g1""#,
+        r#""\u00""#,
+    ] {
+        assert!(is_codec_error(json(text)), "{}", text);
+    }
+}
+
+#[test]
+fn json_strings_reject_raw_control_characters() {
+    assert!(is_codec_error(Codec::Json.decode::<String>(b"\"a\nb\"")));
+    assert!(is_codec_error(Codec::Json.decode::<String>(b"\"a\\qb\"")));
+    assert!(is_codec_error(Codec::Json.decode::<String>(b"\"abc")));
+}
+
+#[test]
+fn json_numbers_follow_the_grammar() {
+    let json = |text: &str| Codec::Json.decode::<f64>(text.as_bytes());
+    for (text, expected) in [("0", 0.0), ("-0", 0.0), ("12", 12.0), ("-1.25", -1.25), ("1e3", 1000.0), ("2E-2", 0.02), ("0.5e+1", 5.0)] {
+        assert_eq!(json(text).unwrap(), expected, "{}", text);
+    }
+    assert_eq!(Codec::Json.decode::<i64>(b"-9223372036854775808").unwrap(), i64::MIN);
+    assert_eq!(Codec::Json.decode::<u64>(b"18446744073709551615").unwrap(), u64::MAX);
+
+    for text in ["1-2e", "-", "+1", "01", "1.", ".5", "1e", "1e+", "1.e3", "--1", "1ee2", "0x10", "1e999", "NaN", "Infinity"] {
+        assert!(is_codec_error(json(text)), "{}", text);
+    }
+}
+
+#[test]
+fn deep_nesting_is_an_error() {
+    let depth = 100_000;
+    let json = format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(is_codec_error(Codec::Json.decode::<Nested>(json.as_bytes())));
+    let json = "{\"a\":".repeat(depth);
+    assert!(is_codec_error(Codec::Json.decode::<Nested>(json.as_bytes())));
+
+    // arrays of one element, and a chain of tags, around null
+    let mut cbor = vec![0x81; depth];
+    cbor.push(0xf6);
+    assert!(is_codec_error(Codec::Cbor.decode::<Nested>(&cbor)));
+    let mut cbor = vec![0xc6; depth];
+    cbor.push(0xf6);
+    assert!(is_codec_error(Codec::Cbor.decode::<Option<u8>>(&cbor)));
+
+    let mut msgpack = vec![0x91; depth];
+    msgpack.push(0xc0);
+    assert!(is_codec_error(Codec::MessagePack.decode::<Nested>(&msgpack)));
+}
+
+#[test]
+fn malformed_markers_are_errors() {
+    // indefinite lengths, a reserved additional-information value and an
+    // unsupported simple value
+    for cbor in [&[0x9f, 0xff][..], &[0x1c], &[0xf0], &[0x7f, 0xff], &[0x3b, 0x80, 0, 0, 0, 0, 0, 0, 0]] {
+        assert!(is_codec_error(Codec::Cbor.decode::<Option<i64>>(cbor)), "{:02x?}", cbor);
+    }
+    // a never-used marker, an extension type and a string that is not UTF-8
+    for msgpack in [&[0xc1][..], &[0xd4, 0x01, 0x00], &[0xa1, 0xff]] {
+        assert!(is_codec_error(Codec::MessagePack.decode::<Option<String>>(msgpack)), "{:02x?}", msgpack);
+    }
+    for json in ["", " ", "nul", "tru", "[1,]", "[1 2]", "{\"a\" 1}", "{1:2}", "{\"a\":1,}", "\u{7f}"] {
+        assert!(is_codec_error(Codec::Json.decode::<Option<Vec<u8>>>(json.as_bytes())), "{:?}", json);
+    }
+    assert!(is_codec_error(Codec::Json.decode::<String>(b"\"\xff\"")));
+
+    // a bool that is neither 0 nor 1, a char that is not a scalar value, an
+    // enum variant that does not exist and a length longer than the input
+    assert!(is_codec_error(Codec::Bincode.decode::<bool>(&[2])));
+    assert!(is_codec_error(Codec::Bincode.decode::<char>(&[0xed, 0xa0, 0x80])));
+    assert!(is_codec_error(Codec::Bincode.decode::<Shape>(&[9, 0, 0, 0])));
+    assert!(is_codec_error(Codec::Bincode.decode::<Vec<u8>>(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1])));
+}
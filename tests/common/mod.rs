@@ -0,0 +1,43 @@
+#![allow(dead_code)]
+
+use std::{env, fs, path::{Path, PathBuf}, process, sync::atomic::{AtomicUsize, Ordering}};
+
+use kstore::{ActionKv, ActionKvOptions};
+
+/// A directory under the system temp dir that is removed on drop.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("kstore-test-{}-{}", process::id(), COUNT.fetch_add(1, Ordering::Relaxed));
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn open(path: &Path) -> ActionKv {
+    ActionKvOptions::new().create_if_missing(true).open(path).unwrap()
+}
+
+pub fn open_segmented(path: &Path, max_segment_size: u64) -> ActionKv {
+    ActionKvOptions::new().create_if_missing(true).segmented(true).max_segment_size(max_segment_size).open(path).unwrap()
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use common::{open, open_segmented, TempDir};
+use kstore::{ActionKv, ActionKvOptions};
+
+// rewrites every key many times and deletes some, returning what is left
+fn churn(kv: &mut ActionKv) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut expected = BTreeMap::new();
+    for round in 0..20u32 {
+        for i in 0..50u32 {
+            let key = format!("key-{:02}", i).into_bytes();
+            if i % 10 == round % 10 {
+                kv.delete(&key).unwrap();
+                expected.remove(&key);
+            } else {
+                let value = format!("{}-{}", round, "v".repeat(i as usize)).into_bytes();
+                kv.insert(&key, &value).unwrap();
+                expected.insert(key, value);
+            }
+        }
+    }
+    expected
+}
+
+fn contents(kv: &ActionKv) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    kv.iter().unwrap().map(Result::unwrap).collect()
+}
+
+fn disk_bytes(path: &Path) -> u64 {
+    match fs::metadata(path).unwrap().is_dir() {
+        true => fs::read_dir(path).unwrap().map(|entry| entry.unwrap().metadata().unwrap().len()).sum(),
+        false => fs::metadata(path).unwrap().len(),
+    }
+}
+
+#[test]
+fn compaction_keeps_only_live_values() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    let expected = churn(&mut kv);
+    let before = disk_bytes(&path);
+
+    kv.compact().unwrap();
+    let after = disk_bytes(&path);
+    assert!(after * 10 < before, "{} -> {}", before, after);
+    assert_eq!(contents(&kv), expected);
+    let stats = kv.segment_stats().unwrap();
+    assert_eq!(stats.iter().map(|stats| stats.dead_bytes).sum::<u64>(), 0);
+
+    // nothing left behind by the rename
+    let names: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert!(names.iter().all(|name| name == "kv" || name == "kv.hint"), "{:?}", names);
+
+    // compacting again has nothing to drop
+    kv.compact().unwrap();
+    assert_eq!(disk_bytes(&path), after);
+    kv.close().unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    let mut kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.load_report().records as usize, expected.len());
+    assert_eq!(kv.load_report().discarded_bytes, 0);
+    assert_eq!(contents(&kv), expected);
+
+    // the compacted file takes appends like any other
+    kv.insert(b"key-00", b"new").unwrap();
+    kv.delete(b"key-01").unwrap();
+    kv.close().unwrap();
+    let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.get(b"key-00").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(kv.get(b"key-01").unwrap(), None);
+    assert_eq!(kv.get(b"key-02").unwrap(), expected.get(&b"key-02"[..]).cloned());
+}
+
+#[test]
+fn compaction_of_a_segmented_store() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_segmented(&path, 1024);
+    let expected = churn(&mut kv);
+    let segments = kv.segment_ids();
+    let before = disk_bytes(&path);
+
+    kv.compact().unwrap();
+    assert!(disk_bytes(&path) * 5 < before);
+    assert!(kv.segment_ids().iter().all(|id| id > segments.last().unwrap()));
+    assert_eq!(contents(&kv), expected);
+    kv.close().unwrap();
+
+    fs::remove_file(path.join("hint")).unwrap();
+    let kv = ActionKvOptions::new().segmented(true).open(&path).unwrap();
+    assert_eq!(kv.load_report().records as usize, expected.len());
+    assert_eq!(contents(&kv), expected);
+}
+
+#[test]
+fn compacting_an_empty_store() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.compact().unwrap();
+    kv.insert(b"k", b"v").unwrap();
+    kv.delete(b"k").unwrap();
+    kv.compact().unwrap();
+    assert_eq!(contents(&kv), BTreeMap::new());
+    kv.close().unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.load_report().records, 0);
+    assert_eq!(kv.get(b"k").unwrap(), None);
+}
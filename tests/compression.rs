@@ -0,0 +1,125 @@
+use kstore::{Compression, Error};
+
+const MIN_MATCH: usize = 4;
+
+// xorshift, so the incompressible inputs are the same on every run
+fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        })
+        .collect()
+}
+
+fn round_trip(data: &[u8]) -> Vec<u8> {
+    let compressed = Compression::Lz4.compress(data).unwrap();
+    assert_eq!(Compression::Lz4.decompress(compressed.clone()).unwrap(), data, "{} bytes", data.len());
+    compressed
+}
+
+fn lz4(block: &[u8], len: u32) -> Result<Vec<u8>, Error> {
+    let mut data = len.to_le_bytes().to_vec();
+    data.extend_from_slice(block);
+    Compression::Lz4.decompress(data)
+}
+
+#[test]
+fn empty_and_short_inputs_round_trip() {
+    assert_eq!(round_trip(b""), [0, 0, 0, 0, 0]);
+    // anything under 13 bytes is too short for a match and stays literal
+    for len in 1..=13 {
+        let data = vec![b'a'; len];
+        let compressed = round_trip(&data);
+        if len < 13 {
+            assert_eq!(compressed.len(), 4 + 1 + len);
+        }
+    }
+    for len in 0..300 {
+        round_trip(&noise(len, len as u64 + 1));
+        round_trip(&b"abcd".repeat(len));
+    }
+}
+
+#[test]
+fn incompressible_input_round_trips() {
+    for len in [14, 15, 16, 270, 4096, 100_000] {
+        let data = noise(len, 7);
+        let compressed = round_trip(&data);
+        // literal runs only cost a length byte per 255
+        assert!(compressed.len() <= 4 + 1 + len + len / 255 + 1, "{} -> {}", len, compressed.len());
+    }
+}
+
+#[test]
+fn repetitive_input_compresses() {
+    for data in [vec![0u8; 100_000], b"0123456789".repeat(10_000)] {
+        let compressed = round_trip(&data);
+        assert!(compressed.len() * 50 < data.len(), "{} -> {}", data.len(), compressed.len());
+    }
+    // a match length of exactly 15 + 255 needs a trailing zero length byte
+    round_trip(&vec![b'x'; 1 + MIN_MATCH + 15 + 255 + 5]);
+
+    // a block repeated further back than a match offset can reach
+    let block = noise(70_000, 3);
+    let data = [block.as_slice(), &block].concat();
+    round_trip(&data);
+
+    let mut data = noise(10_000, 5);
+    data.extend_from_within(..5000);
+    data.extend_from_slice(&[9; 3000]);
+    data.extend_from_within(2000..9000);
+    let compressed = round_trip(&data);
+    assert!(compressed.len() < data.len() / 2);
+}
+
+#[test]
+fn reads_blocks_from_other_encoders() {
+    // three literals, a nine byte match that overlaps itself, five literals
+    let block = [0x35, b'a', b'b', b'c', 3, 0, 0x50, b'a', b'b', b'c', b'a', b'b'];
+    assert_eq!(lz4(&block, 17).unwrap(), b"abcabcabcabcabcab");
+
+    // both lengths spill into extra bytes: 15 + 1 literals, 4 + 15 + 255 + 0 match
+    let mut block = vec![0xff, 1];
+    block.extend_from_slice(b"0123456789abcdef");
+    block.extend_from_slice(&[1, 0, 255, 0, 0x00]);
+    let out = lz4(&block, 16 + 274).unwrap();
+    assert_eq!(&out[..16], b"0123456789abcdef");
+    assert!(out[16..].iter().all(|&b| b == b'f'));
+}
+
+#[test]
+fn malformed_blocks_are_errors() {
+    let malformed = |result: Result<Vec<u8>, Error>| matches!(result, Err(Error::Codec(_)));
+
+    assert!(malformed(Compression::Lz4.decompress(Vec::new())));
+    assert!(malformed(Compression::Lz4.decompress(vec![0, 0, 0])));
+    // no token at all
+    assert!(malformed(lz4(&[], 0)));
+    // fewer literals than the token promises
+    assert!(malformed(lz4(&[0x30, b'a', b'b'], 3)));
+    // a literal length that runs off the end
+    assert!(malformed(lz4(&[0xf0, 255], 300)));
+    // half an offset
+    assert!(malformed(lz4(&[0x10, b'a', 1], 5)));
+    // offsets of zero and from before the start of the output
+    assert!(malformed(lz4(&[0x10, b'a', 0, 0, 0x00], 5)));
+    assert!(malformed(lz4(&[0x10, b'a', 2, 0, 0x00], 5)));
+    // a match length that runs off the end
+    assert!(malformed(lz4(&[0x1f, b'a', 1, 0, 255], 300)));
+    // a match that overflows the stored length
+    assert!(malformed(lz4(&[0x1f, b'a', 1, 0, 10, 0x00], 5)));
+    // a stored length the block does not fill, or overruns with literals
+    assert!(malformed(lz4(&[0x50, b'a', b'b', b'c', b'd', b'e'], 6)));
+    assert!(malformed(lz4(&[0x50, b'a', b'b', b'c', b'd', b'e'], 4)));
+    // a huge stored length is not trusted for the allocation
+    assert!(malformed(lz4(&[0x10, b'a'], u32::MAX)));
+
+    // every truncation of a real block
+    let compressed = Compression::Lz4.compress(&b"hello hello hello hello world".repeat(20)).unwrap();
+    for len in 4..compressed.len() {
+        assert!(malformed(Compression::Lz4.decompress(compressed[..len].to_vec())), "cut at {}", len);
+    }
+}
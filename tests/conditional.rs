@@ -0,0 +1,144 @@
+mod common;
+
+use std::{fs, time::Duration};
+
+use common::{open, open_segmented, TempDir};
+use kstore::{Error, SharedKv};
+
+#[test]
+fn stale_version_conflicts() {
+    let dir = TempDir::new();
+    let mut kv = open(&dir.join("kv"));
+    kv.insert(b"k", b"a").unwrap();
+    let version = kv.version(b"k").unwrap();
+    kv.insert(b"k", b"b").unwrap();
+
+    assert!(matches!(kv.update_if_version(b"k", version, b"c"), Err(Error::Conflict { .. })));
+    let current = kv.version(b"k").unwrap();
+    assert!(current > version);
+    kv.update_if_version(b"k", current, b"c").unwrap();
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"c".to_vec()));
+}
+
+#[test]
+fn version_is_not_reused_after_delete() {
+    let dir = TempDir::new();
+    let mut kv = open(&dir.join("kv"));
+    kv.insert(b"k", b"a").unwrap();
+    let version = kv.version(b"k").unwrap();
+    kv.delete(b"k").unwrap();
+    kv.insert(b"k", b"b").unwrap();
+
+    assert_ne!(kv.version(b"k"), Some(version));
+    assert!(matches!(kv.update_if_version(b"k", version, b"c"), Err(Error::Conflict { .. })));
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"b".to_vec()));
+}
+
+#[test]
+fn version_is_not_reused_after_expiry() {
+    let dir = TempDir::new();
+    let mut kv = open(&dir.join("kv"));
+    kv.insert_with_ttl(b"k", b"a", Duration::from_millis(1)).unwrap();
+    let version = kv.version(b"k").unwrap();
+    std::thread::sleep(Duration::from_millis(5));
+    kv.insert(b"k", b"b").unwrap();
+
+    assert!(matches!(kv.update_if_version(b"k", version, b"c"), Err(Error::Conflict { .. })));
+}
+
+#[test]
+fn versions_survive_reopen_and_compaction() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"k", b"a").unwrap();
+    kv.insert(b"k", b"b").unwrap();
+    let version = kv.version(b"k").unwrap();
+    kv.compact().unwrap();
+    kv.close().unwrap();
+
+    let mut kv = open(&path);
+    assert_eq!(kv.version(b"k"), Some(version));
+    kv.delete(b"k").unwrap();
+    kv.insert(b"k", b"c").unwrap();
+    assert_ne!(kv.version(b"k"), Some(version));
+}
+
+#[test]
+fn seqs_are_not_reused_once_compaction_drops_the_newest_records() {
+    let dir = TempDir::new();
+    for segmented in [false, true] {
+        let path = dir.join(if segmented { "segmented" } else { "kv" });
+        let mut kv = if segmented { open_segmented(&path, 256) } else { open(&path) };
+        for i in 0..20u32 {
+            kv.insert(b"k", &i.to_le_bytes()).unwrap();
+        }
+        let version = kv.version(b"k").unwrap();
+        let last_seq = kv.last_seq();
+        kv.delete(b"k").unwrap();
+        kv.compact().unwrap();
+        kv.close().unwrap();
+        let hint = if segmented { path.join("hint") } else { dir.join("kv.hint") };
+        fs::remove_file(hint).unwrap();
+
+        let mut kv = open(&path);
+        assert!(kv.last_seq() > last_seq);
+        kv.insert(b"k", b"again").unwrap();
+        assert!(kv.version(b"k").unwrap() > version);
+        kv.close().unwrap();
+    }
+
+    // a merge drops the tombstone while the store is open
+    let kv = SharedKv::new(open_segmented(&dir.join("merged"), 256));
+    for i in 0..20u32 {
+        kv.insert(b"k", &i.to_le_bytes()).unwrap();
+    }
+    let version = kv.version(b"k").unwrap();
+    kv.delete(b"k").unwrap();
+    kv.merge().unwrap();
+    drop(kv);
+    fs::remove_file(dir.join("merged").join("hint")).ok();
+    let mut kv = open(&dir.join("merged"));
+    kv.insert(b"k", b"again").unwrap();
+    assert!(kv.version(b"k").unwrap() > version);
+}
+
+#[test]
+fn compare_and_swap() {
+    let dir = TempDir::new();
+    let mut kv = open(&dir.join("kv"));
+    kv.insert_if_absent(b"k", b"a").unwrap();
+    assert!(matches!(kv.insert_if_absent(b"k", b"b"), Err(Error::Conflict { .. })));
+    assert!(matches!(kv.compare_and_swap(b"k", Some(b"x"), Some(b"b")), Err(Error::Conflict { .. })));
+    kv.compare_and_swap(b"k", Some(b"a"), Some(b"b")).unwrap();
+    kv.compare_and_swap(b"k", Some(b"b"), None).unwrap();
+    assert_eq!(kv.get(b"k").unwrap(), None);
+}
+
+#[test]
+fn shared_counter_loses_no_updates() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(b"n", b"0").unwrap();
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let kv = kv.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    loop {
+                        let (value, version) = kv.get_versioned(b"n").unwrap().unwrap();
+                        let n: u64 = String::from_utf8(value).unwrap().parse().unwrap();
+                        match kv.update_if_version(b"n", version, (n + 1).to_string().as_bytes()) {
+                            Err(Error::Conflict { .. }) => continue,
+                            result => break result.unwrap(),
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(kv.get(b"n").unwrap(), Some(b"200".to_vec()));
+}
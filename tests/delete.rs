@@ -0,0 +1,89 @@
+mod common;
+
+use std::fs;
+
+use common::{open, TempDir};
+use kstore::ActionKvOptions;
+
+#[test]
+fn deleted_key_stays_deleted() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"gone", b"1").unwrap();
+    kv.insert(b"kept", b"2").unwrap();
+    kv.delete(b"gone").unwrap();
+    // deleting an absent key is not an error
+    kv.delete(b"never").unwrap();
+    assert_eq!(kv.get(b"gone").unwrap(), None);
+    kv.close().unwrap();
+
+    // with and without the hint
+    for hint in [true, false] {
+        if !hint {
+            fs::remove_file(dir.join("kv.hint")).unwrap();
+        }
+        let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+        assert_eq!(kv.load_report().used_hint, hint);
+        assert_eq!(kv.get(b"gone").unwrap(), None);
+        assert!(!kv.contains_key(b"gone"));
+        assert_eq!(kv.get(b"kept").unwrap(), Some(b"2".to_vec()));
+    }
+
+    let mut kv = open(&path);
+    kv.compact().unwrap();
+    assert_eq!(kv.get(b"gone").unwrap(), None);
+    kv.close().unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    // compaction dropped the value and its tombstone alike
+    let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.load_report().records, 1);
+    assert_eq!(kv.get(b"gone").unwrap(), None);
+    assert_eq!(kv.find(b"gone").unwrap(), None);
+}
+
+#[test]
+fn key_can_come_back_after_a_delete() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"k", b"1").unwrap();
+    kv.delete(b"k").unwrap();
+    kv.insert(b"k", b"2").unwrap();
+    kv.close().unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    let mut kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"2".to_vec()));
+    kv.compact().unwrap();
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn empty_value_is_not_a_delete() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"empty", b"").unwrap();
+    kv.insert(b"deleted", b"1").unwrap();
+    kv.delete(b"deleted").unwrap();
+    assert_eq!(kv.get(b"empty").unwrap(), Some(Vec::new()));
+    assert!(kv.contains_key(b"empty"));
+    kv.close().unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    let mut kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.get(b"empty").unwrap(), Some(Vec::new()));
+    assert_eq!(kv.get(b"deleted").unwrap(), None);
+    let keys: Vec<_> = kv.iter().unwrap().map(|pair| pair.unwrap()).collect();
+    assert_eq!(keys, [(b"empty".to_vec(), Vec::new())]);
+
+    kv.compact().unwrap();
+    assert_eq!(kv.get(b"empty").unwrap(), Some(Vec::new()));
+    kv.close().unwrap();
+
+    let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.get(b"empty").unwrap(), Some(Vec::new()));
+    assert_eq!(kv.get(b"deleted").unwrap(), None);
+}
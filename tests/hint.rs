@@ -0,0 +1,117 @@
+mod common;
+
+use std::{fs, path::Path, time::Duration};
+
+use common::{open_segmented, TempDir};
+use kstore::{ActionKv, ActionKvOptions, WriteBatch};
+
+type Contents = Vec<(Vec<u8>, Vec<u8>, Option<u64>)>;
+
+fn contents(kv: &ActionKv) -> Contents {
+    kv.prefix(b"")
+        .unwrap()
+        .map(|pair| {
+            let (key, value) = pair.unwrap();
+            let version = kv.version(&key);
+            (key, value, version)
+        })
+        .collect()
+}
+
+fn reopen(path: &Path) -> ActionKv {
+    ActionKvOptions::new().segmented(true).hint_every(None).open(path).unwrap()
+}
+
+fn write_some(kv: &mut ActionKv, round: u32) {
+    for i in 0..40u32 {
+        let key = format!("key-{:03}", (i * 7 + round) % 50);
+        match i % 5 {
+            0 => kv.delete(key.as_bytes()).unwrap(),
+            1 => kv.insert_with_ttl(key.as_bytes(), b"expiring", Duration::from_secs(3600)).unwrap(),
+            _ => kv.insert(key.as_bytes(), format!("{}-{}", round, i).as_bytes()).unwrap(),
+        }
+    }
+    let mut batch = WriteBatch::new();
+    batch.insert(format!("batch-{}", round).as_bytes(), b"x").delete(b"key-001");
+    kv.write_batch(&batch).unwrap();
+}
+
+#[test]
+fn replay_from_the_hint_matches_a_full_scan() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let hint = path.join("hint");
+    let stale = dir.join("stale-hint");
+
+    let mut kv = open_segmented(&path, 512);
+    write_some(&mut kv, 0);
+    write_some(&mut kv, 1);
+    kv.write_hint().unwrap();
+    fs::copy(&hint, &stale).unwrap();
+    // more segments after the ones the hint covers, and the hint put back
+    // once close has rewritten it
+    write_some(&mut kv, 2);
+    write_some(&mut kv, 3);
+    assert!(kv.segment_ids().len() > 3);
+    let expected = contents(&kv);
+    let last_seq = kv.last_seq();
+    kv.close().unwrap();
+    fs::copy(&stale, &hint).unwrap();
+
+    let kv = reopen(&path);
+    let report = kv.load_report();
+    assert!(report.used_hint);
+    assert!(report.records > 0);
+    assert_eq!(contents(&kv), expected);
+    assert_eq!(kv.last_seq(), last_seq);
+    drop(kv);
+
+    fs::remove_file(&hint).unwrap();
+    let kv = reopen(&path);
+    assert!(!kv.load_report().used_hint);
+    assert_eq!(contents(&kv), expected);
+    assert_eq!(kv.last_seq(), last_seq);
+}
+
+#[test]
+fn hint_written_after_compaction_matches_a_full_scan() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_segmented(&path, 512);
+    write_some(&mut kv, 0);
+    write_some(&mut kv, 1);
+    kv.compact().unwrap();
+    write_some(&mut kv, 2);
+    let expected = contents(&kv);
+    kv.close().unwrap();
+
+    let kv = ActionKvOptions::new().segmented(true).open(&path).unwrap();
+    assert!(kv.load_report().used_hint);
+    assert_eq!(kv.load_report().records, 0);
+    assert_eq!(contents(&kv), expected);
+    drop(kv);
+
+    fs::remove_file(path.join("hint")).unwrap();
+    let kv = reopen(&path);
+    assert_eq!(contents(&kv), expected);
+}
+
+#[test]
+fn hint_that_does_not_match_the_log_is_ignored() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_segmented(&path, 512);
+    write_some(&mut kv, 0);
+    kv.write_hint().unwrap();
+    let stale = fs::read(path.join("hint")).unwrap();
+    // compaction rewrites every segment the hint points into
+    kv.compact().unwrap();
+    write_some(&mut kv, 1);
+    let expected = contents(&kv);
+    kv.close().unwrap();
+    fs::write(path.join("hint"), stale).unwrap();
+
+    let kv = reopen(&path);
+    assert!(!kv.load_report().used_hint);
+    assert_eq!(contents(&kv), expected);
+}
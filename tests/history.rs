@@ -0,0 +1,133 @@
+mod common;
+
+use std::{thread, time::{Duration, SystemTime}};
+
+use common::{open, TempDir};
+use kstore::{ActionKvOptions, AsOf, CompactionPolicy, SharedKv};
+
+fn policy() -> CompactionPolicy {
+    CompactionPolicy { garbage_ratio: None, dead_bytes: Some(1), check_interval: Duration::from_secs(3600) }
+}
+
+#[test]
+fn history_lists_every_record() {
+    let dir = TempDir::new();
+    let mut kv = open(&dir.join("kv"));
+    kv.insert(b"k", b"a").unwrap();
+    kv.insert(b"other", b"x").unwrap();
+    kv.insert(b"k", b"b").unwrap();
+    kv.delete(b"k").unwrap();
+
+    let history = kv.history(b"k").unwrap();
+    let values: Vec<_> = history.iter().map(|revision| revision.value.clone()).collect();
+    assert_eq!(values, vec![Some(b"a".to_vec()), Some(b"b".to_vec()), None]);
+    assert!(history.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+    assert_eq!(history[2].version, None);
+}
+
+#[test]
+fn get_as_of_seq_and_time() {
+    let dir = TempDir::new();
+    let mut kv = open(&dir.join("kv"));
+    kv.insert(b"k", b"a").unwrap();
+    let first = kv.last_seq().unwrap();
+    thread::sleep(Duration::from_millis(2));
+    let between = SystemTime::now();
+    thread::sleep(Duration::from_millis(2));
+    kv.insert(b"k", b"b").unwrap();
+    kv.delete(b"k").unwrap();
+
+    assert_eq!(kv.get_as_of(b"k", first).unwrap(), Some(b"a".to_vec()));
+    assert_eq!(kv.get_as_of(b"k", first + 1).unwrap(), Some(b"b".to_vec()));
+    assert_eq!(kv.get_as_of(b"k", first + 2).unwrap(), None);
+    assert_eq!(kv.get_as_of(b"k", AsOf::Time(between)).unwrap(), Some(b"a".to_vec()));
+}
+
+#[test]
+fn compaction_drops_history_without_a_window() {
+    let dir = TempDir::new();
+    let mut kv = open(&dir.join("kv"));
+    kv.insert(b"k", b"a").unwrap();
+    kv.insert(b"k", b"b").unwrap();
+    kv.compact().unwrap();
+
+    let history = kv.history(b"k").unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].value, Some(b"b".to_vec()));
+}
+
+#[test]
+fn compaction_keeps_history_within_the_window() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = ActionKvOptions::new().create_if_missing(true).history_retention(Some(Duration::from_secs(3600))).open(&path).unwrap();
+    kv.insert(b"k", b"a").unwrap();
+    let first = kv.last_seq().unwrap();
+    kv.insert(b"k", b"b").unwrap();
+    kv.delete(b"gone").unwrap();
+    kv.compact().unwrap();
+    kv.close().unwrap();
+
+    let kv = ActionKvOptions::new().history_retention(Some(Duration::from_secs(3600))).open(&path).unwrap();
+    assert_eq!(kv.history(b"k").unwrap().len(), 2);
+    assert_eq!(kv.get_as_of(b"k", first).unwrap(), Some(b"a".to_vec()));
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"b".to_vec()));
+}
+
+#[test]
+fn retained_history_does_not_count_as_dead() {
+    let dir = TempDir::new();
+    let kv = ActionKvOptions::new()
+        .create_if_missing(true)
+        .segmented(true)
+        .max_segment_size(512)
+        .history_retention(Some(Duration::from_secs(3600)))
+        .background_compaction(Some(policy()))
+        .open(&dir.join("kv"))
+        .unwrap();
+    let kv = SharedKv::new(kv);
+    for i in 0..50u32 {
+        kv.insert(b"k", &i.to_le_bytes()).unwrap();
+    }
+    assert!(kv.read().compaction_due().unwrap());
+
+    kv.merge().unwrap();
+    let kv = kv.read();
+    assert_eq!(kv.dead_bytes().unwrap(), 0);
+    assert!(kv.segment_stats().unwrap().iter().map(|stats| stats.retained_bytes).sum::<u64>() > 0);
+    assert!(!kv.compaction_due().unwrap());
+    assert_eq!(kv.history(b"k").unwrap().len(), 50);
+}
+
+#[test]
+fn merge_keeps_history_written_while_it_runs() {
+    let dir = TempDir::new();
+    let kv = ActionKvOptions::new()
+        .create_if_missing(true)
+        .segmented(true)
+        .max_segment_size(1024)
+        .history_retention(Some(Duration::from_secs(3600)))
+        .open(&dir.join("kv"))
+        .unwrap();
+    let kv = SharedKv::new(kv);
+    let writer = {
+        let kv = kv.clone();
+        thread::spawn(move || {
+            for i in 0..500u32 {
+                kv.insert(&(i % 7).to_le_bytes(), &i.to_le_bytes()).unwrap();
+            }
+        })
+    };
+    for _ in 0..10 {
+        kv.merge().unwrap();
+    }
+    writer.join().unwrap();
+    kv.merge().unwrap();
+
+    let total: usize = (0..7u32).map(|key| kv.history(&key.to_le_bytes()).unwrap().len()).sum();
+    assert_eq!(total, 500);
+    for key in 0..7u32 {
+        let last = (0..500u32).rev().find(|i| i % 7 == key).unwrap();
+        assert_eq!(kv.get(&key.to_le_bytes()).unwrap(), Some(last.to_le_bytes().to_vec()));
+    }
+}
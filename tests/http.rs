@@ -0,0 +1,330 @@
+mod common;
+
+use common::{open, TempDir};
+use kstore::{server::{Protocol, Server}, SharedKv};
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    fn text(&self) -> &str {
+        std::str::from_utf8(&self.body).unwrap()
+    }
+}
+
+fn store(dir: &TempDir) -> SharedKv {
+    SharedKv::new(open(&dir.join("kv")))
+}
+
+// one connection that sends `input` and hangs up, with the responses it got
+fn send(kv: &SharedKv, input: &[u8]) -> Vec<Response> {
+    let mut out = Vec::new();
+    Server::new(kv.clone()).protocol(Protocol::Http).handle(input, &mut out).unwrap();
+
+    let mut responses = Vec::new();
+    let mut rest = &out[..];
+    while !rest.is_empty() {
+        let end = rest.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = std::str::from_utf8(&rest[..end]).unwrap();
+        rest = &rest[end + 4..];
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+        let headers: Vec<(String, String)> = lines
+            .map(|line| {
+                let (name, value) = line.split_once(": ").unwrap();
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+        let len = headers.iter().find(|(name, _)| name == "Content-Length").map_or(0, |(_, len)| len.parse().unwrap());
+        responses.push(Response { status, headers, body: rest[..len].to_vec() });
+        rest = &rest[len..];
+    }
+    responses
+}
+
+fn send_one(kv: &SharedKv, input: &str) -> Response {
+    let mut responses = send(kv, input.as_bytes());
+    assert_eq!(responses.len(), 1, "{:?}", input);
+    responses.pop().unwrap()
+}
+
+fn put(kv: &SharedKv, key: &str, value: &str) -> u16 {
+    send_one(kv, &format!("PUT /keys/{} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", key, value.len(), value)).status
+}
+
+fn get(kv: &SharedKv, key: &str) -> Response {
+    send_one(kv, &format!("GET /keys/{} HTTP/1.1\r\n\r\n", key))
+}
+
+#[test]
+fn put_get_and_delete() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+
+    assert_eq!(put(&kv, "a", "hello"), 204);
+    let response = get(&kv, "a");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert_eq!(response.text(), r#"{"key":"YQ==","value":"aGVsbG8="}"#);
+    assert_eq!(response.header("ETag").map(str::to_string), kv.version(b"a").map(|version| format!("\"{}\"", version)));
+
+    let response = send_one(&kv, "GET /keys/a HTTP/1.1\r\nAccept: application/octet-stream\r\n\r\n");
+    assert_eq!(response.header("Content-Type"), Some("application/octet-stream"));
+    assert_eq!(response.body, b"hello");
+
+    assert_eq!(send_one(&kv, "DELETE /keys/a HTTP/1.1\r\n\r\n").status, 204);
+    assert_eq!(kv.get(b"a").unwrap(), None);
+    let response = send_one(&kv, "DELETE /keys/a HTTP/1.1\r\n\r\n");
+    assert_eq!((response.status, response.text()), (404, r#"{"error":"key not found"}"#));
+    assert_eq!(get(&kv, "a").status, 404);
+}
+
+#[test]
+fn accept_picks_the_form_of_a_value() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    kv.insert(b"k", b"v").unwrap();
+
+    for (accept, raw) in [
+        ("application/octet-stream", true),
+        ("application/json", false),
+        ("*/*", false),
+        ("text/html, application/octet-stream", true),
+        ("application/json;q=0.5, application/octet-stream", true),
+        ("application/octet-stream; q=0.1, application/json", false),
+        ("APPLICATION/OCTET-STREAM", true),
+        // a tie goes to JSON
+        ("application/octet-stream, application/json", false),
+    ] {
+        let response = send_one(&kv, &format!("GET /keys/k HTTP/1.1\r\nAccept: {}\r\n\r\n", accept));
+        assert_eq!(response.body == b"v", raw, "{}", accept);
+    }
+}
+
+#[test]
+fn values_are_base64_in_json() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    let put_json = |key: &str, body: &str| {
+        let request = format!("PUT /keys/{} HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}", key, body.len(), body);
+        send_one(&kv, &request)
+    };
+
+    for (value, encoded) in [("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg==")] {
+        kv.insert(value.as_bytes(), value.as_bytes()).unwrap();
+        assert_eq!(get(&kv, value).text(), format!(r#"{{"key":"{}","value":"{}"}}"#, encoded, encoded));
+    }
+    kv.insert(b"empty", b"").unwrap();
+    assert_eq!(get(&kv, "empty").text(), r#"{"key":"ZW1wdHk=","value":""}"#);
+
+    // padded or not, and either alphabet
+    for (body, value) in [
+        (r#"{"value":"aGVsbG8="}"#, &b"hello"[..]),
+        (r#"{"value":"aGVsbG8"}"#, b"hello"),
+        (r#"{"value":"+/+/"}"#, b"\xfb\xff\xbf"),
+        (r#"{"value":"-_-_"}"#, b"\xfb\xff\xbf"),
+        (r#"{"value":""}"#, b""),
+    ] {
+        assert_eq!(put_json("k", body).status, 204, "{}", body);
+        assert_eq!(kv.get(b"k").unwrap().as_deref(), Some(value), "{}", body);
+    }
+
+    for (body, error) in [
+        (r#"{"value":"a"}"#, "value is not valid base64"),
+        (r#"{"value":"ab$="}"#, "value is not valid base64"),
+        (r#"{"value":1}"#, r#"expected {\"value\": base64}"#),
+        ("hello", r#"expected {\"value\": base64}"#),
+    ] {
+        let response = put_json("k", body);
+        assert_eq!((response.status, response.text()), (400, format!(r#"{{"error":"{}"}}"#, error).as_str()), "{}", body);
+    }
+    assert_eq!(kv.get(b"k").unwrap(), Some(Vec::new()));
+}
+
+#[test]
+fn keys_are_percent_decoded() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+
+    assert_eq!(put(&kv, "a%20b%2Fc%00", "1"), 204);
+    assert_eq!(kv.get(b"a b/c\0").unwrap(), Some(b"1".to_vec()));
+    // a plus only means a space in a query string
+    assert_eq!(put(&kv, "a+b", "2"), 204);
+    assert_eq!(kv.get(b"a+b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(put(&kv, "%ff%FE", "3"), 204);
+    assert_eq!(kv.get(b"\xff\xfe").unwrap(), Some(b"3".to_vec()));
+
+    for key in ["%zz", "%4", "a%", "%+1"] {
+        let response = get(&kv, key);
+        assert_eq!((response.status, response.text()), (400, r#"{"error":"invalid percent-encoding in key"}"#), "{}", key);
+    }
+}
+
+#[test]
+fn chunked_bodies() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+
+    let request = "PUT /keys/k HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2;name=value\r\nde\r\nA\r\n0123456789\r\n0\r\nX-Trailer: ignored\r\n\r\n";
+    assert_eq!(send_one(&kv, request).status, 204);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"abcde0123456789".to_vec()));
+    // the chunks win over a length that disagrees
+    let request = "PUT /keys/k HTTP/1.1\r\nContent-Length: 1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n2\r\nxy\r\n0\r\n\r\n";
+    assert_eq!(send_one(&kv, request).status, 204);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"xy".to_vec()));
+
+    for (body, status, error) in [
+        ("zz\r\n", 400, "invalid chunk size"),
+        ("+3\r\nabc\r\n0\r\n\r\n", 400, "invalid chunk size"),
+        ("3\r\nabcX\r\n0\r\n\r\n", 400, "malformed chunk"),
+        ("3\r\nabc\r\n", 400, "unexpected end of request"),
+        ("20000000\r\n", 413, "request body too large"),
+    ] {
+        let request = format!("PUT /keys/k HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}", body);
+        let response = send_one(&kv, &request);
+        assert_eq!((response.status, response.text()), (status, format!(r#"{{"error":"{}"}}"#, error).as_str()), "{:?}", body);
+        assert_eq!(response.header("Connection"), Some("close"));
+    }
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"xy".to_vec()));
+}
+
+#[test]
+fn status_codes() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+
+    for path in ["/", "/key", "/keys/", "/stats/", "/KEYS/a"] {
+        assert_eq!(send_one(&kv, &format!("GET {} HTTP/1.1\r\n\r\n", path)).status, 404, "{}", path);
+    }
+    for (method, path, allow) in [("POST", "/keys/a", "GET, PUT, DELETE"), ("PUT", "/keys", "GET"), ("DELETE", "/stats", "GET"), ("get", "/stats", "GET")] {
+        let response = send_one(&kv, &format!("{} {} HTTP/1.1\r\nContent-Length: 0\r\n\r\n", method, path));
+        assert_eq!((response.status, response.header("Allow")), (405, Some(allow)), "{} {}", method, path);
+    }
+
+    let response = send_one(&kv, "PUT /keys/a HTTP/1.1\r\n\r\n");
+    assert_eq!(response.status, 411);
+    assert!(kv.get(b"a").unwrap().is_none());
+    assert_eq!(send_one(&kv, "PUT /keys/a HTTP/1.1\r\nContent-Length: 0\r\n\r\n").status, 204);
+    assert_eq!(kv.get(b"a").unwrap(), Some(Vec::new()));
+
+    let response = send_one(&kv, "PUT /keys/a HTTP/1.1\r\nContent-Length: 300000000\r\n\r\n");
+    assert_eq!((response.status, response.header("Connection")), (413, Some("close")));
+
+    for (request, error) in [
+        ("PUT /keys/a HTTP/1.1\r\nContent-Length: ten\r\n\r\n", "invalid content length"),
+        ("GET /keys/a HTTP/2.0\r\n\r\n", "unsupported HTTP version"),
+        ("GET /keys/a\r\n\r\n", "malformed request line"),
+        ("GET  /keys/a HTTP/1.1\r\n\r\n", "malformed request line"),
+        ("GET /keys/a HTTP/1.1\r\nno colon\r\n\r\n", "malformed header"),
+        ("GET /keys/a HTTP/1.1\r\n", "unexpected end of request"),
+    ] {
+        let response = send_one(&kv, request);
+        assert_eq!((response.status, response.text()), (400, format!(r#"{{"error":"{}"}}"#, error).as_str()), "{:?}", request);
+    }
+}
+
+#[test]
+fn conditional_put() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    let put_if = |condition: &str, value: &str| {
+        let request = format!("PUT /keys/k HTTP/1.1\r\n{}\r\nContent-Length: {}\r\n\r\n{}", condition, value.len(), value);
+        send_one(&kv, &request).status
+    };
+
+    assert_eq!(put_if("If-Match: *", "1"), 412);
+    assert_eq!(put_if("If-None-Match: *", "1"), 204);
+    assert_eq!(put_if("If-None-Match: *", "2"), 412);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"1".to_vec()));
+
+    let etag = get(&kv, "k").header("ETag").unwrap().to_string();
+    assert_eq!(put_if(&format!("If-Match: {}", etag), "3"), 204);
+    // the write moved the version on
+    assert_eq!(put_if(&format!("If-Match: {}", etag), "4"), 412);
+    assert_ne!(get(&kv, "k").header("ETag").unwrap(), etag);
+    assert_eq!(put_if("If-Match: *", "5"), 204);
+    assert_eq!(put_if("If-Match: \"not a version\"", "6"), 412);
+    assert_eq!(put_if("If-Match: 1", "6"), 412);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"5".to_vec()));
+
+    assert_eq!(put_if("If-None-Match: \"1\"", "7"), 400);
+    assert_eq!(put_if("If-Match: *\r\nIf-None-Match: *", "7"), 400);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"5".to_vec()));
+}
+
+#[test]
+fn key_pages() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    for key in ["a", "b 1", "b 2", "b 3", "c"] {
+        kv.insert(key.as_bytes(), b"v").unwrap();
+    }
+    let list = |query: &str| send_one(&kv, &format!("GET /keys{} HTTP/1.1\r\n\r\n", query));
+
+    assert_eq!(list("").text(), r#"{"keys":["YQ==","YiAx","YiAy","YiAz","Yw=="],"cursor":null}"#);
+    // a plus in a query string is a space
+    let page = list("?prefix=b+&limit=2");
+    assert_eq!(page.text(), r#"{"keys":["YiAx","YiAy"],"cursor":"YiAy"}"#);
+    assert_eq!(list("?limit=2&prefix=b%20&cursor=YiAy").text(), r#"{"keys":["YiAz"],"cursor":null}"#);
+    assert_eq!(list("?prefix=zzz").text(), r#"{"keys":[],"cursor":null}"#);
+    assert_eq!(list("?limit=1000").status, 200);
+
+    for (query, error) in [
+        ("?limit=0", "limit must be between 1 and 1000"),
+        ("?limit=1001", "limit must be between 1 and 1000"),
+        ("?limit=many", "limit must be between 1 and 1000"),
+        ("?cursor=a", "invalid cursor"),
+        ("?cursor=%zz", "invalid cursor"),
+        ("?prefix=%g0", "invalid percent-encoding in prefix"),
+    ] {
+        let response = list(query);
+        assert_eq!((response.status, response.text()), (400, format!(r#"{{"error":"{}"}}"#, error).as_str()), "{}", query);
+    }
+}
+
+#[test]
+fn stats_count_live_keys() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"b", b"1").unwrap();
+    kv.delete(b"a").unwrap();
+
+    let response = send_one(&kv, "GET /stats HTTP/1.1\r\n\r\n");
+    assert_eq!(response.status, 200);
+    assert!(response.text().starts_with(r#"{"keys":1,"segments":1,"#), "{}", response.text());
+    assert!(response.text().ends_with(r#""read_only":false}"#), "{}", response.text());
+}
+
+#[test]
+fn connections_stay_open_unless_asked_to_close() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    kv.insert(b"k", b"v").unwrap();
+    let get_k = "GET /keys/k HTTP/1.1\r\n\r\n";
+
+    // pipelined, with blank lines between requests
+    let responses = send(&kv, format!("{}\r\n{}{}", get_k, get_k, get_k).as_bytes());
+    assert_eq!(responses.len(), 3);
+    assert!(responses.iter().all(|response| response.status == 200 && response.header("Connection").is_none()));
+
+    for request in ["GET /keys/k HTTP/1.0\r\n\r\n", "GET /keys/k HTTP/1.1\r\nConnection: close\r\n\r\n"] {
+        let responses = send(&kv, format!("{}{}", request, get_k).as_bytes());
+        assert_eq!(responses.len(), 1, "{:?}", request);
+        assert_eq!(responses[0].header("Connection"), Some("close"));
+    }
+    let responses = send(&kv, format!("GET /keys/k HTTP/1.0\r\nConnection: keep-alive\r\n\r\n{}", get_k).as_bytes());
+    assert_eq!(responses.len(), 2);
+
+    // a client that waits for 100 Continue gets it before its response
+    let responses = send(&kv, b"PUT /keys/k HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 1\r\n\r\nw");
+    assert_eq!(responses.iter().map(|response| response.status).collect::<Vec<_>>(), [100, 204]);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"w".to_vec()));
+}
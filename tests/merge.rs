@@ -0,0 +1,142 @@
+mod common;
+
+use std::{collections::BTreeMap, fs, path::Path, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::Duration};
+
+use common::TempDir;
+use kstore::{ActionKvOptions, CompactionPolicy, SharedKv};
+
+const WRITERS: u32 = 4;
+const WRITES: u32 = 400;
+
+fn open(path: &Path, policy: Option<CompactionPolicy>) -> SharedKv {
+    SharedKv::new(
+        ActionKvOptions::new()
+            .create_if_missing(true)
+            .segmented(true)
+            .max_segment_size(1024)
+            .background_compaction(policy)
+            .open(path)
+            .unwrap(),
+    )
+}
+
+// each writer owns its own keys, so what it last wrote is what should be left
+fn write(kv: &SharedKv, writer: u32) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut expected = BTreeMap::new();
+    for i in 0..WRITES {
+        let key = format!("w{}-{:02}", writer, i % 25).into_bytes();
+        if i % 7 == 3 {
+            kv.delete(&key).unwrap();
+            expected.remove(&key);
+        } else {
+            let value = format!("{}-{}", i, "x".repeat((i % 13) as usize)).into_bytes();
+            kv.insert(&key, &value).unwrap();
+            expected.insert(key, value);
+        }
+    }
+    expected
+}
+
+fn write_concurrently(kv: &SharedKv) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let kv = kv.clone();
+            thread::spawn(move || write(&kv, writer))
+        })
+        .collect();
+    writers.into_iter().flat_map(|writer| writer.join().unwrap()).collect()
+}
+
+fn contents(kv: &SharedKv) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    kv.read().prefix(b"").unwrap().map(Result::unwrap).collect()
+}
+
+fn file_bytes(kv: &SharedKv) -> u64 {
+    kv.read().segment_stats().unwrap().iter().map(|stats| stats.file_bytes).sum()
+}
+
+fn assert_reopens_to(path: &Path, expected: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    let _ = fs::remove_file(path.join("hint"));
+    let kv = open(path, None);
+    assert!(!kv.read().load_report().used_hint);
+    assert_eq!(&contents(&kv), expected);
+}
+
+#[test]
+fn merges_while_writes_continue() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let kv = open(&path, None);
+
+    let done = Arc::new(AtomicBool::new(false));
+    let merger = {
+        let (kv, done) = (kv.clone(), done.clone());
+        thread::spawn(move || {
+            let mut merges = 0;
+            while !done.load(Ordering::Relaxed) {
+                kv.merge().unwrap();
+                merges += 1;
+            }
+            merges
+        })
+    };
+    let expected = write_concurrently(&kv);
+    done.store(true, Ordering::Relaxed);
+    assert!(merger.join().unwrap() > 0);
+
+    assert_eq!(contents(&kv), expected);
+    for (key, value) in &expected {
+        assert_eq!(kv.get(key).unwrap().as_ref(), Some(value));
+    }
+    kv.merge().unwrap();
+    assert_eq!(contents(&kv), expected);
+    kv.close().unwrap();
+    drop(kv);
+
+    assert_reopens_to(&path, &expected);
+}
+
+#[test]
+fn background_compaction_keeps_up_with_writes() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let policy = CompactionPolicy { garbage_ratio: Some(0.3), dead_bytes: None, check_interval: Duration::from_millis(5) };
+    let kv = open(&path, Some(policy));
+
+    let expected = write_concurrently(&kv);
+    let written = kv.read().segment_ids().len();
+    // give the thread a few checks to merge what is left over
+    thread::sleep(Duration::from_millis(100));
+    assert!(kv.take_background_error().is_none());
+    assert_eq!(contents(&kv), expected);
+
+    let stats = kv.read().segment_stats().unwrap();
+    let dead: u64 = stats.iter().map(|stats| stats.dead_bytes).sum();
+    assert!((dead as f64) <= 0.3 * file_bytes(&kv) as f64 + 1024.0, "{} of {} bytes dead", dead, file_bytes(&kv));
+    assert!(kv.read().segment_ids().len() <= written);
+    kv.close().unwrap();
+    drop(kv);
+
+    assert_reopens_to(&path, &expected);
+}
+
+#[test]
+fn rotation_keeps_every_write() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let kv = open(&path, None);
+    let expected = write_concurrently(&kv);
+    let segments = kv.read().segment_ids();
+    assert!(segments.len() > 10);
+    // only the active segment may grow past the size limit, and by no more
+    // than one record
+    let stats = kv.read().segment_stats().unwrap();
+    for stats in &stats[..stats.len() - 1] {
+        assert!(stats.file_bytes <= 1024 + 64, "segment {} is {} bytes", stats.id, stats.file_bytes);
+    }
+    assert_eq!(contents(&kv), expected);
+    kv.close().unwrap();
+    drop(kv);
+
+    assert_reopens_to(&path, &expected);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::{fs::{self, File, OpenOptions}, path::Path, time::Duration};
+
+use common::{open_segmented, TempDir};
+use kstore::{migrate, migrate_in_place, ActionKvOptions, ChecksumAlgorithm, Error, FileHeader};
+
+// a current-format file from before records could carry fields
+fn write_fieldless(path: &Path) {
+    let mut f = File::create(path).unwrap();
+    FileHeader { flags: 0, ..FileHeader::new(ChecksumAlgorithm::default()) }.write(&mut f).unwrap();
+}
+
+#[test]
+fn fieldless_file_rejects_ttl_until_migrated() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    write_fieldless(&path);
+
+    let mut kv = ActionKvOptions::new().open(&path).unwrap();
+    kv.insert(b"k", b"v").unwrap();
+    assert!(matches!(kv.insert_with_ttl(b"k", b"v", Duration::from_secs(60)), Err(Error::Unsupported(_))));
+    kv.close().unwrap();
+
+    let report = migrate_in_place(&path).unwrap();
+    assert_eq!(report.records, 1);
+
+    let mut kv = ActionKvOptions::new().open(&path).unwrap();
+    assert_eq!(kv.header().unwrap().flags, FileHeader::new(ChecksumAlgorithm::default()).flags);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"v".to_vec()));
+    kv.insert_with_ttl(b"k", b"w", Duration::from_secs(60)).unwrap();
+    assert!(kv.ttl(b"k").unwrap().is_some());
+}
+
+#[test]
+fn migrate_option_upgrades_fieldless_file() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    write_fieldless(&path);
+
+    let mut kv = ActionKvOptions::new().migrate(true).open(&path).unwrap();
+    kv.insert_with_ttl(b"k", b"v", Duration::from_secs(60)).unwrap();
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"v".to_vec()));
+}
+
+#[test]
+fn migrating_a_current_file_leaves_it_alone() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = ActionKvOptions::new().create_if_missing(true).open(&path).unwrap();
+    kv.insert(b"k", b"v").unwrap();
+    kv.close().unwrap();
+
+    let report = migrate_in_place(&path).unwrap();
+    assert_eq!(report.records, 0);
+}
+
+fn cut(path: &Path, bytes: u64) {
+    let f = OpenOptions::new().write(true).open(path).unwrap();
+    f.set_len(f.metadata().unwrap().len() - bytes).unwrap();
+}
+
+#[test]
+fn torn_tail_is_left_behind() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    write_fieldless(&path);
+    let mut kv = ActionKvOptions::new().open(&path).unwrap();
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"b", b"2").unwrap();
+    kv.close().unwrap();
+    cut(&path, 1);
+
+    let report = migrate_in_place(&path).unwrap();
+    assert_eq!((report.records, report.discarded_bytes > 0), (1, true));
+    let kv = ActionKvOptions::new().open(&path).unwrap();
+    assert_eq!(kv.load_report().discarded_bytes, 0);
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(kv.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn only_the_last_segment_can_be_torn() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_segmented(&path, 128);
+    // two records fill a segment, so the last of these starts a new one
+    for i in 0..21u32 {
+        kv.insert(&i.to_be_bytes(), b"value").unwrap();
+    }
+    let segments = kv.segment_ids();
+    kv.close().unwrap();
+    let segment = |id: u32| path.join(format!("{:08}.seg", id));
+
+    // the active segment: its last record is dropped like `load` would
+    cut(&segment(*segments.last().unwrap()), 1);
+    let report = migrate(&path, &dir.join("copy")).unwrap();
+    assert_eq!(report.records, 20);
+    assert!(report.discarded_bytes > 0);
+    let kv = ActionKvOptions::new().open(&dir.join("copy")).unwrap();
+    assert_eq!(kv.get(&19u32.to_be_bytes()).unwrap(), Some(b"value".to_vec()));
+    assert_eq!(kv.get(&20u32.to_be_bytes()).unwrap(), None);
+
+    // a sealed one was damaged after it was written, and dropping its tail
+    // would lose the records after it, whether it is short or fails its checksum
+    let sealed = fs::read(segment(segments[0])).unwrap();
+    cut(&segment(segments[0]), 1);
+    assert!(matches!(migrate(&path, &dir.join("short")), Err(Error::Corruption { .. })));
+    let mut damaged = sealed.clone();
+    *damaged.last_mut().unwrap() ^= 1;
+    fs::write(segment(segments[0]), &damaged).unwrap();
+    assert!(matches!(migrate(&path, &dir.join("damaged")), Err(Error::Corruption { .. })));
+}
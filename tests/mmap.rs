@@ -0,0 +1,135 @@
+mod common;
+
+use std::{fs::{self, File, OpenOptions}, io::{Seek, SeekFrom, Write}, path::Path, time::Duration};
+
+use common::TempDir;
+use kstore::{ActionKv, ActionKvOptions, ChecksumAlgorithm, Error, FileHeader};
+
+fn open_mapped(path: &Path) -> ActionKv {
+    ActionKvOptions::new().create_if_missing(true).mmap(true).open(path).unwrap()
+}
+
+fn len(path: &Path) -> u64 {
+    fs::metadata(path).unwrap().len()
+}
+
+#[test]
+fn get_bytes_borrows_from_the_map() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_mapped(&path);
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"b", b"").unwrap();
+
+    let value = kv.get_bytes(b"a").unwrap().unwrap();
+    assert!(value.is_mapped());
+    assert_eq!(&*value, b"1");
+    assert_eq!(Vec::from(value.clone()), b"1");
+    assert_eq!(&*kv.get_bytes(b"b").unwrap().unwrap(), b"");
+    assert_eq!(kv.get_bytes(b"c").unwrap(), None);
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+    kv.close().unwrap();
+
+    let kv = ActionKvOptions::new().open(&path).unwrap();
+    let value = kv.get_bytes(b"a").unwrap().unwrap();
+    assert!(!value.is_mapped());
+    assert_eq!(&*value, b"1");
+}
+
+#[test]
+fn reads_past_the_mapping_remap_the_file() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_mapped(&path);
+    kv.insert(b"first", b"1").unwrap();
+    let first = kv.get_bytes(b"first").unwrap().unwrap();
+
+    // well past the page the first mapping covered
+    let big = vec![7u8; 64 * 1024];
+    for i in 0..8u8 {
+        kv.insert(&[i], &big).unwrap();
+        assert_eq!(kv.get_bytes(&[i]).unwrap().unwrap(), big[..]);
+    }
+    // the handle from the old mapping is still good
+    assert_eq!(&*first, b"1");
+    assert_eq!(&*kv.get_bytes(b"first").unwrap().unwrap(), b"1");
+}
+
+#[test]
+fn handles_outlive_compaction() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_mapped(&path);
+    for i in 0..100u32 {
+        kv.insert(b"k", &i.to_le_bytes()).unwrap();
+        kv.insert(&i.to_le_bytes(), b"other").unwrap();
+    }
+    let before = kv.get_bytes(b"k").unwrap().unwrap();
+    let size = len(&path);
+
+    kv.compact().unwrap();
+    assert!(len(&path) < size);
+    assert_eq!(&*before, 99u32.to_le_bytes());
+    let after = kv.get_bytes(b"k").unwrap().unwrap();
+    assert!(after.is_mapped());
+    assert_eq!(after, before);
+
+    // writes after the swap land in the new file and its mapping
+    kv.insert(b"k", b"new").unwrap();
+    assert_eq!(&*kv.get_bytes(b"k").unwrap().unwrap(), b"new");
+    assert_eq!(&*before, 99u32.to_le_bytes());
+}
+
+#[test]
+fn mapped_reads_of_extended_records() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_mapped(&path);
+    kv.insert_with_ttl(b"a", b"expiring", Duration::from_secs(3600)).unwrap();
+    let (position, value) = kv.find(b"a").unwrap().unwrap();
+    assert_eq!(value, b"expiring");
+    assert_eq!(&*kv.get_bytes(b"a").unwrap().unwrap(), b"expiring");
+    assert!(kv.get_at(position.0, position.1).is_ok());
+
+    let tombstone = len(&path);
+    kv.delete(b"a").unwrap();
+    assert_eq!(kv.get_bytes(b"a").unwrap(), None);
+    assert!(matches!(kv.get_at(0, tombstone), Err(Error::NotFound)));
+}
+
+#[test]
+fn mapped_reads_of_fieldless_records() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut f = File::create(&path).unwrap();
+    FileHeader { flags: 0, ..FileHeader::new(ChecksumAlgorithm::default()) }.write(&mut f).unwrap();
+    drop(f);
+
+    let mut kv = ActionKvOptions::new().mmap(true).open(&path).unwrap();
+    kv.insert(b"a", b"plain").unwrap();
+    let value = kv.get_bytes(b"a").unwrap().unwrap();
+    assert!(value.is_mapped());
+    assert_eq!(&*value, b"plain");
+
+    let tombstone = len(&path);
+    kv.delete(b"a").unwrap();
+    assert_eq!(kv.get_bytes(b"a").unwrap(), None);
+    assert!(matches!(kv.get_at(0, tombstone), Err(Error::NotFound)));
+}
+
+#[test]
+fn mapped_reads_check_the_checksum() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_mapped(&path);
+    kv.insert(b"a", b"value").unwrap();
+    let (_, offset) = kv.find(b"a").unwrap().unwrap().0;
+
+    // the map is shared, so damage to the file shows through it
+    let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+    f.seek(SeekFrom::Start(len(&path) - 1)).unwrap();
+    f.write_all(b"x").unwrap();
+    drop(f);
+
+    assert!(matches!(kv.get_bytes(b"a"), Err(Error::Corruption { offset: at, .. }) if at == offset));
+}
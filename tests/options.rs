@@ -0,0 +1,174 @@
+mod common;
+
+use std::{fs, io, time::Duration};
+
+use common::{open, open_segmented, TempDir};
+use kstore::{ActionKvOptions, ChecksumAlgorithm, Error, FileHeader, WriteBatch};
+
+fn io_error(result: Result<kstore::ActionKv, Error>, kind: io::ErrorKind) -> bool {
+    matches!(result, Err(Error::Io(err)) if err.kind() == kind)
+}
+
+#[test]
+fn create_if_missing() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+
+    assert!(io_error(ActionKvOptions::new().open(&path), io::ErrorKind::NotFound));
+    assert!(io_error(ActionKvOptions::new().segmented(true).open(&path), io::ErrorKind::NotFound));
+    assert!(!path.exists());
+
+    let mut kv = ActionKvOptions::new().create_if_missing(true).open(&path).unwrap();
+    kv.insert(b"k", b"v").unwrap();
+    kv.close().unwrap();
+    // an existing file opens the same either way
+    for create in [true, false] {
+        let kv = ActionKvOptions::new().create_if_missing(create).open(&path).unwrap();
+        assert_eq!(kv.get(b"k").unwrap(), Some(b"v".to_vec()));
+    }
+
+    let segmented = dir.join("segmented");
+    let kv = ActionKvOptions::new().create_if_missing(true).segmented(true).open(&segmented).unwrap();
+    assert!(segmented.is_dir());
+    assert_eq!(kv.segment_ids(), [0]);
+}
+
+#[test]
+fn error_if_exists() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+
+    let mut kv = ActionKvOptions::new().error_if_exists(true).open(&path).unwrap();
+    kv.insert(b"k", b"v").unwrap();
+    kv.close().unwrap();
+    let before = fs::read(&path).unwrap();
+    assert!(io_error(ActionKvOptions::new().error_if_exists(true).open(&path), io::ErrorKind::AlreadyExists));
+    assert!(io_error(ActionKvOptions::new().error_if_exists(true).create_if_missing(true).open(&path), io::ErrorKind::AlreadyExists));
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    let segmented = dir.join("segmented");
+    ActionKvOptions::new().error_if_exists(true).segmented(true).open(&segmented).unwrap();
+    assert!(io_error(ActionKvOptions::new().error_if_exists(true).segmented(true).open(&segmented), io::ErrorKind::AlreadyExists));
+}
+
+#[test]
+fn read_only_rejects_writes() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"k", b"v").unwrap();
+    kv.close().unwrap();
+    let before = fs::read(&path).unwrap();
+
+    let mut kv = ActionKvOptions::new().read_only(true).open(&path).unwrap();
+    assert!(kv.is_read_only());
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"v".to_vec()));
+    assert_eq!(kv.iter().unwrap().count(), 1);
+
+    let read_only = |result: kstore::Result<()>| matches!(result, Err(Error::ReadOnly));
+    assert!(read_only(kv.insert(b"k", b"w")));
+    assert!(read_only(kv.insert(b"new", b"w")));
+    assert!(read_only(kv.insert_with_ttl(b"k", b"w", Duration::from_secs(1))));
+    assert!(read_only(kv.update(b"k", b"w")));
+    assert!(read_only(kv.delete(b"k")));
+    assert!(read_only(kv.expire(b"k", Duration::from_secs(1)).map(drop)));
+    let mut batch = WriteBatch::new();
+    batch.insert(b"a", b"1");
+    assert!(read_only(kv.write_batch(&batch)));
+    assert!(read_only(kv.compact()));
+    assert!(read_only(kv.write_hint()));
+
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"v".to_vec()));
+    assert_eq!(kv.get(b"new").unwrap(), None);
+    kv.close().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    // a read-only store never creates anything
+    let missing = dir.join("missing");
+    assert!(io_error(ActionKvOptions::new().read_only(true).open(&missing), io::ErrorKind::NotFound));
+    for result in [
+        ActionKvOptions::new().read_only(true).create_if_missing(true).open(&missing),
+        ActionKvOptions::new().read_only(true).error_if_exists(true).open(&missing),
+        ActionKvOptions::new().read_only(true).migrate(true).open(&path),
+    ] {
+        assert!(matches!(result, Err(Error::InvalidOptions(_))));
+    }
+    assert!(!missing.exists());
+}
+
+#[test]
+fn read_only_segmented_store() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_segmented(&path, 128);
+    for i in 0..20u32 {
+        kv.insert(&i.to_be_bytes(), b"value").unwrap();
+    }
+    let segments = kv.segment_ids();
+    kv.close().unwrap();
+
+    let mut kv = ActionKvOptions::new().read_only(true).open(&path).unwrap();
+    assert_eq!(kv.segment_ids(), segments);
+    assert_eq!(kv.get(&7u32.to_be_bytes()).unwrap(), Some(b"value".to_vec()));
+    assert!(matches!(kv.insert(b"k", b"v"), Err(Error::ReadOnly)));
+    assert!(matches!(kv.compact(), Err(Error::ReadOnly)));
+    assert_eq!(kv.segment_ids(), segments);
+}
+
+#[test]
+fn checksum_is_chosen_when_the_file_is_created() {
+    let dir = TempDir::new();
+    for checksum in [ChecksumAlgorithm::Crc32Cksum, ChecksumAlgorithm::Crc32Iscsi, ChecksumAlgorithm::Crc32IsoHdlc] {
+        let path = dir.join(&format!("{:?}", checksum));
+        let mut kv = ActionKvOptions::new().create_if_missing(true).checksum(checksum).open(&path).unwrap();
+        kv.insert(b"a", b"1").unwrap();
+        kv.close().unwrap();
+        fs::remove_file(dir.join(&format!("{:?}.hint", checksum))).unwrap();
+
+        // the header wins over the option when the file exists
+        let other = if checksum == ChecksumAlgorithm::Crc32Iscsi { ChecksumAlgorithm::Crc32Cksum } else { ChecksumAlgorithm::Crc32Iscsi };
+        let mut kv = ActionKvOptions::new().checksum(other).hint_every(None).open(&path).unwrap();
+        assert_eq!(kv.header().unwrap().checksum, checksum);
+        assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+        kv.insert(b"b", b"2").unwrap();
+        kv.close().unwrap();
+
+        let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+        assert_eq!(kv.load_report().discarded_bytes, 0);
+        assert_eq!(kv.get(b"b").unwrap(), Some(b"2".to_vec()));
+    }
+}
+
+#[test]
+fn records_do_not_verify_under_another_checksum() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = ActionKvOptions::new().create_if_missing(true).checksum(ChecksumAlgorithm::Crc32Iscsi).hint_every(None).open(&path).unwrap();
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"b", b"2").unwrap();
+    let header = FileHeader { checksum: ChecksumAlgorithm::Crc32IsoHdlc, ..kv.header().unwrap() };
+    kv.close().unwrap();
+
+    // a valid header naming another algorithm, over the same records
+    let mut data = fs::read(&path).unwrap();
+    let mut rewritten = Vec::new();
+    header.write(&mut rewritten).unwrap();
+    data[..rewritten.len()].copy_from_slice(&rewritten);
+    fs::write(&path, &data).unwrap();
+    let result = ActionKvOptions::new().read_only(true).open(&path);
+    assert!(matches!(result, Err(Error::Corruption { .. })), "{:?}", result.map(drop));
+}
+
+#[test]
+fn headerless_file() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = ActionKvOptions::new().create_if_missing(true).write_header(false).open(&path).unwrap();
+    assert!(kv.header().is_none());
+    kv.insert(b"a", b"1").unwrap();
+    kv.close().unwrap();
+
+    let kv = ActionKvOptions::new().open(&path).unwrap();
+    assert!(kv.header().is_none());
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+}
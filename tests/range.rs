@@ -0,0 +1,170 @@
+mod common;
+
+use std::{ops::Bound::{self, Excluded, Included, Unbounded}, path::Path, thread, time::Duration};
+
+use common::TempDir;
+use kstore::{ActionKv, ActionKvOptions, Index, IndexEntry, IndexKind};
+
+const KINDS: [IndexKind; 2] = [IndexKind::Hash, IndexKind::Ordered];
+
+const KEYS: [&[u8]; 9] = [b"", b"a", b"ab", b"abc", b"b", b"b\xff", b"b\xff\xff", b"c", b"\xff\xff"];
+
+fn entry(offset: u64) -> IndexEntry {
+    IndexEntry { segment: 0, offset, len: 0, seq: offset, version: offset + 1, expires_at: None, namespace: 0 }
+}
+
+fn index(kind: IndexKind) -> Index {
+    let mut index = Index::new(kind);
+    // out of order, so a hash index has something to sort
+    for (i, key) in KEYS.iter().enumerate().rev() {
+        index.insert(key.to_vec(), entry(i as u64));
+    }
+    index
+}
+
+fn open(path: &Path, kind: IndexKind) -> ActionKv {
+    let mut kv = ActionKvOptions::new().create_if_missing(true).index_kind(kind).open(path).unwrap();
+    for key in KEYS.iter().rev() {
+        kv.insert(key, &[key, &b"!"[..]].concat()).unwrap();
+    }
+    kv
+}
+
+fn keys(kv: &ActionKv, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Vec<Vec<u8>> {
+    kv.range::<&[u8], _>((start, end)).unwrap().map(|pair| pair.unwrap().0).collect()
+}
+
+fn prefixed(kv: &ActionKv, prefix: &[u8]) -> Vec<Vec<u8>> {
+    kv.prefix(prefix).unwrap().map(|pair| pair.unwrap().0).collect()
+}
+
+fn expected(filter: impl Fn(&[u8]) -> bool) -> Vec<Vec<u8>> {
+    KEYS.iter().filter(|key| filter(key)).map(|key| key.to_vec()).collect()
+}
+
+#[test]
+fn index_bounds() {
+    for kind in KINDS {
+        let index = index(kind);
+        let range = |start: Bound<&[u8]>, end: Bound<&[u8]>| index.range(start, end).map(|(key, _)| key.clone()).collect::<Vec<_>>();
+
+        assert_eq!(range(Unbounded, Unbounded), expected(|_| true), "{:?}", kind);
+        assert_eq!(range(Included(b"ab"), Included(b"b")), expected(|key| (&b"ab"[..]..=b"b").contains(&key)), "{:?}", kind);
+        assert_eq!(range(Excluded(b"ab"), Excluded(b"b")), [b"abc".to_vec()], "{:?}", kind);
+        assert_eq!(range(Included(b"ab"), Excluded(b"b")), [b"ab".to_vec(), b"abc".to_vec()], "{:?}", kind);
+        assert_eq!(range(Excluded(b"ab"), Included(b"b")), [b"abc".to_vec(), b"b".to_vec()], "{:?}", kind);
+        // bounds that are not keys themselves
+        assert_eq!(range(Included(b"aa"), Excluded(b"ac")), [b"ab".to_vec(), b"abc".to_vec()], "{:?}", kind);
+        assert_eq!(range(Unbounded, Excluded(b"a")), [Vec::new()], "{:?}", kind);
+        assert_eq!(range(Excluded(b"c"), Unbounded), [b"\xff\xff".to_vec()], "{:?}", kind);
+
+        // a point, and empty or inverted ranges, which must not panic
+        assert_eq!(range(Included(b"b"), Included(b"b")), [b"b".to_vec()], "{:?}", kind);
+        for (start, end) in [
+            (Included(&b"b"[..]), Excluded(&b"b"[..])),
+            (Excluded(b"b"), Included(b"b")),
+            (Excluded(b"b"), Excluded(b"b")),
+            (Included(b"c"), Included(b"a")),
+            (Excluded(b"c"), Excluded(b"a")),
+            (Excluded(b"\xff\xff"), Unbounded),
+        ] {
+            assert!(range(start, end).is_empty(), "{:?} {:?} {:?}", kind, start, end);
+        }
+
+        let reversed: Vec<_> = index.range(Included(b"a"), Excluded(b"c")).rev().map(|(key, entry)| (key.clone(), entry.offset)).collect();
+        assert_eq!(reversed, [(b"b\xff\xff".to_vec(), 6), (b"b\xff".to_vec(), 5), (b"b".to_vec(), 4), (b"abc".to_vec(), 3), (b"ab".to_vec(), 2), (b"a".to_vec(), 1)]);
+    }
+}
+
+#[test]
+fn hash_and_ordered_indexes_agree() {
+    let bounds: Vec<Bound<&[u8]>> = [Unbounded]
+        .into_iter()
+        .chain([&b""[..], b"a", b"ab", b"abd", b"b", b"b\xff", b"c", b"\xff", b"\xff\xff", b"\xff\xff\xff"].into_iter().flat_map(|key| [Included(key), Excluded(key)]))
+        .collect();
+    let (hash, ordered) = (index(IndexKind::Hash), index(IndexKind::Ordered));
+    for &start in &bounds {
+        for &end in &bounds {
+            let from_hash: Vec<_> = hash.range(start, end).collect();
+            let from_ordered: Vec<_> = ordered.range(start, end).collect();
+            assert_eq!(from_hash, from_ordered, "{:?} {:?}", start, end);
+            let from_hash: Vec<_> = hash.range(start, end).rev().collect();
+            let from_ordered: Vec<_> = ordered.range(start, end).rev().collect();
+            assert_eq!(from_hash, from_ordered, "{:?} {:?}", start, end);
+        }
+    }
+}
+
+#[test]
+fn store_ranges() {
+    for kind in KINDS {
+        let dir = TempDir::new();
+        let kv = open(&dir.join("kv"), kind);
+
+        assert_eq!(keys(&kv, Unbounded, Unbounded), expected(|_| true), "{:?}", kind);
+        assert_eq!(keys(&kv, Included(b"a"), Excluded(b"b")), expected(|key| (&b"a"[..]..b"b").contains(&key)), "{:?}", kind);
+        assert_eq!(keys(&kv, Excluded(b"a"), Included(b"b")), [b"ab".to_vec(), b"abc".to_vec(), b"b".to_vec()], "{:?}", kind);
+        assert!(keys(&kv, Included(b"c"), Excluded(b"a")).is_empty(), "{:?}", kind);
+        assert!(keys(&kv, Excluded(b"b"), Excluded(b"b")).is_empty(), "{:?}", kind);
+
+        // `RangeBounds` of any key type
+        let pairs: Vec<_> = kv.range("a".."abd").unwrap().map(Result::unwrap).collect();
+        assert_eq!(pairs, [(b"a".to_vec(), b"a!".to_vec()), (b"ab".to_vec(), b"ab!".to_vec()), (b"abc".to_vec(), b"abc!".to_vec())]);
+        let pairs: Vec<_> = kv.range(b"b".to_vec()..).unwrap().rev().map(|pair| pair.unwrap().0).collect();
+        assert_eq!(pairs, [b"\xff\xff".to_vec(), b"c".to_vec(), b"b\xff\xff".to_vec(), b"b\xff".to_vec(), b"b".to_vec()]);
+
+        let all: Vec<_> = kv.iter().unwrap().map(|pair| pair.unwrap().0).collect();
+        assert_eq!(all, expected(|_| true));
+        let mut reversed: Vec<_> = kv.iter().unwrap().rev().map(|pair| pair.unwrap().0).collect();
+        reversed.reverse();
+        assert_eq!(reversed, all);
+
+        // both ends at once
+        let mut iter = kv.iter().unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().0, b"");
+        assert_eq!(iter.next_back().unwrap().unwrap().0, b"\xff\xff");
+        assert_eq!(iter.next().unwrap().unwrap().0, b"a");
+        assert_eq!(iter.count(), KEYS.len() - 3);
+    }
+}
+
+#[test]
+fn store_prefixes() {
+    for kind in KINDS {
+        let dir = TempDir::new();
+        let kv = open(&dir.join("kv"), kind);
+
+        assert_eq!(prefixed(&kv, b""), expected(|_| true), "{:?}", kind);
+        assert_eq!(prefixed(&kv, b"a"), [b"a".to_vec(), b"ab".to_vec(), b"abc".to_vec()], "{:?}", kind);
+        assert_eq!(prefixed(&kv, b"abc"), [b"abc".to_vec()], "{:?}", kind);
+        assert!(prefixed(&kv, b"abcd").is_empty(), "{:?}", kind);
+        // the upper bound of a prefix ending in 0xff carries into the byte before
+        assert_eq!(prefixed(&kv, b"b"), [b"b".to_vec(), b"b\xff".to_vec(), b"b\xff\xff".to_vec()], "{:?}", kind);
+        assert_eq!(prefixed(&kv, b"b\xff"), [b"b\xff".to_vec(), b"b\xff\xff".to_vec()], "{:?}", kind);
+        assert_eq!(prefixed(&kv, b"b\xff\xff"), [b"b\xff\xff".to_vec()], "{:?}", kind);
+        // and one of nothing but 0xff has no upper bound at all
+        assert_eq!(prefixed(&kv, b"\xff"), [b"\xff\xff".to_vec()], "{:?}", kind);
+        assert_eq!(prefixed(&kv, b"\xff\xff"), [b"\xff\xff".to_vec()], "{:?}", kind);
+        assert!(prefixed(&kv, b"\xff\xff\xff").is_empty(), "{:?}", kind);
+
+        let reversed: Vec<_> = kv.prefix(b"b").unwrap().rev().map(|pair| pair.unwrap().0).collect();
+        assert_eq!(reversed, [b"b\xff\xff".to_vec(), b"b\xff".to_vec(), b"b".to_vec()], "{:?}", kind);
+    }
+}
+
+#[test]
+fn scans_skip_deleted_and_expired_keys() {
+    for kind in KINDS {
+        let dir = TempDir::new();
+        let mut kv = open(&dir.join("kv"), kind);
+        kv.delete(b"ab").unwrap();
+        kv.insert_with_ttl(b"abc", b"gone", Duration::from_millis(20)).unwrap();
+        kv.insert_with_ttl(b"b", b"kept", Duration::from_secs(3600)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(prefixed(&kv, b"a"), [b"a".to_vec()], "{:?}", kind);
+        assert_eq!(keys(&kv, Included(b"a"), Included(b"b")), [b"a".to_vec(), b"b".to_vec()], "{:?}", kind);
+        let reversed: Vec<_> = kv.range(&b"a"[..]..=&b"b"[..]).unwrap().rev().map(|pair| pair.unwrap()).collect();
+        assert_eq!(reversed, [(b"b".to_vec(), b"kept".to_vec()), (b"a".to_vec(), b"a!".to_vec())], "{:?}", kind);
+    }
+}
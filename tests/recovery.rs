@@ -0,0 +1,168 @@
+mod common;
+
+use std::{fs::{self, OpenOptions}, io::{Seek, SeekFrom, Write}};
+
+use common::{open, open_segmented, TempDir};
+use kstore::{ActionKvOptions, Error};
+
+const HEADER_LEN: u64 = 26;
+// segments start with a header that also records the next seq
+const SEGMENT_HEADER_LEN: u64 = HEADER_LEN + 8;
+
+#[test]
+fn torn_tail_is_truncated() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"b", b"2").unwrap();
+    kv.close().unwrap();
+    let good_len = fs::metadata(&path).unwrap().len();
+
+    // the start of a record whose data never made it to disk
+    let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+    f.write_all(&[0xaa, 0xbb, 0xcc, 0xdd, 1, 0, 0, 0, 100, 0, 0, 0, b'c']).unwrap();
+    drop(f);
+
+    let mut kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert_eq!(kv.load_report().discarded_bytes, 13);
+    assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(kv.get(b"b").unwrap(), Some(b"2".to_vec()));
+    kv.insert(b"c", b"3").unwrap();
+    kv.close().unwrap();
+
+    let kv = open(&path);
+    assert_eq!(kv.get(b"c").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn torn_tail_with_a_bad_checksum_is_truncated() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"b", b"2").unwrap();
+    kv.close().unwrap();
+    let len = fs::metadata(&path).unwrap().len();
+
+    let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+    f.seek(SeekFrom::Start(len - 1)).unwrap();
+    f.write_all(b"x").unwrap();
+    drop(f);
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    let kv = ActionKvOptions::new().hint_every(None).open(&path).unwrap();
+    assert!(kv.load_report().discarded_bytes > 0);
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(kv.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn read_only_store_skips_a_torn_tail() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"a", b"1").unwrap();
+    kv.close().unwrap();
+    let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+    f.write_all(&[1, 2, 3]).unwrap();
+    drop(f);
+    let len = fs::metadata(&path).unwrap().len();
+
+    let kv = ActionKvOptions::new().read_only(true).open(&path).unwrap();
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+}
+
+#[test]
+fn bad_length_in_a_sealed_segment_is_corruption() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open_segmented(&path, 128);
+    for i in 0..20u32 {
+        kv.insert(&i.to_le_bytes(), b"value").unwrap();
+    }
+    assert!(kv.segment_ids().len() > 2);
+    kv.close().unwrap();
+
+    let sealed = path.join("00000000.seg");
+    let len = fs::metadata(&sealed).unwrap().len();
+    let mut f = OpenOptions::new().write(true).open(&sealed).unwrap();
+    f.seek(SeekFrom::Start(SEGMENT_HEADER_LEN + 8)).unwrap();
+    f.write_all(&0x00ff_ffffu32.to_le_bytes()).unwrap();
+    drop(f);
+    fs::remove_file(path.join("hint")).unwrap();
+
+    let result = ActionKvOptions::new().open(&path);
+    assert!(matches!(result, Err(Error::Corruption { offset: SEGMENT_HEADER_LEN, .. })));
+    assert_eq!(fs::metadata(&sealed).unwrap().len(), len);
+}
+
+#[test]
+fn read_only_store_walks_the_log_past_a_torn_tail() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"a", b"2").unwrap();
+    kv.insert(b"b", b"3").unwrap();
+    kv.close().unwrap();
+
+    // a bad checksum in the last record, which a writable open would truncate
+    let mut data = fs::read(&path).unwrap();
+    *data.last_mut().unwrap() ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    let kv = ActionKvOptions::new().read_only(true).open(&path).unwrap();
+    assert!(kv.load_report().discarded_bytes > 0);
+    assert_eq!(kv.get(b"b").unwrap(), None);
+    assert_eq!(kv.find(b"a").unwrap().map(|(_, value)| value), Some(b"2".to_vec()));
+    assert_eq!(kv.find(b"b").unwrap(), None);
+    assert_eq!(kv.history(b"a").unwrap().len(), 2);
+    assert_eq!(fs::metadata(&path).unwrap().len(), data.len() as u64);
+}
+
+#[test]
+fn damage_in_the_middle_of_the_log_is_corruption() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert(b"a", b"first").unwrap();
+    let middle = fs::metadata(&path).unwrap().len();
+    kv.insert(b"b", b"second").unwrap();
+    kv.insert(b"c", b"third").unwrap();
+    kv.close().unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+    let good = fs::read(&path).unwrap();
+
+    // the last byte of the second record's value
+    let end = good.windows(6).position(|w| w == b"second").unwrap() + 5;
+    let mut data = good.clone();
+    data[end] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    let result = ActionKvOptions::new().open(&path);
+    assert!(matches!(result, Err(Error::Corruption { offset, .. }) if offset == middle));
+    assert_eq!(fs::read(&path).unwrap(), data);
+
+    // damage after the store has loaded shows up on the read
+    fs::write(&path, &good).unwrap();
+    let kv = ActionKvOptions::new().open(&path).unwrap();
+    fs::write(&path, &data).unwrap();
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"first".to_vec()));
+    assert!(matches!(kv.get(b"b"), Err(Error::Corruption { offset, .. }) if offset == middle));
+    drop(kv);
+
+    // no single flipped byte makes a read-only open or a read panic
+    for at in HEADER_LEN as usize..good.len() {
+        let mut data = good.clone();
+        data[at] ^= 0x80;
+        fs::write(&path, &data).unwrap();
+        if let Ok(kv) = ActionKvOptions::new().read_only(true).open(&path) {
+            for key in [b"a", b"b", b"c"] {
+                let _ = kv.get(key);
+            }
+        }
+    }
+}
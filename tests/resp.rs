@@ -0,0 +1,202 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{open, TempDir};
+use kstore::{server::{Protocol, Server}, Error, SharedKv};
+
+fn store(dir: &TempDir) -> SharedKv {
+    SharedKv::new(open(&dir.join("kv")))
+}
+
+// one connection that sends `input` and hangs up
+fn send(kv: &SharedKv, input: &[u8]) -> String {
+    let mut out = Vec::new();
+    Server::new(kv.clone()).protocol(Protocol::Resp).handle(input, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn command(args: &[&str]) -> String {
+    args.iter().fold(format!("*{}\r\n", args.len()), |out, arg| format!("{}${}\r\n{}\r\n", out, arg.len(), arg))
+}
+
+fn bulk(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+fn array(items: &[&str]) -> String {
+    items.iter().fold(format!("*{}\r\n", items.len()), |out, item| out + &bulk(item))
+}
+
+const OK: &str = "+OK\r\n";
+const NIL: &str = "$-1\r\n";
+const SYNTAX: &str = "-ERR syntax error\r\n";
+const NOT_AN_INTEGER: &str = "-ERR value is not an integer or out of range\r\n";
+
+#[test]
+fn inline_and_multibulk_commands() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+
+    assert_eq!(send(&kv, b"PING\r\n"), "+PONG\r\n");
+    assert_eq!(send(&kv, command(&["ping"]).as_bytes()), "+PONG\r\n");
+    assert_eq!(send(&kv, b"ping hello\n"), bulk("hello"));
+    // blank lines are skipped and words can be split by any whitespace
+    assert_eq!(send(&kv, b"\r\n  set a \t 1\r\nGET a\r\n"), format!("{}{}", OK, bulk("1")));
+    assert_eq!(send(&kv, command(&["set", "b", "two words"]).as_bytes()), OK);
+    assert_eq!(send(&kv, command(&["GET", "b"]).as_bytes()), bulk("two words"));
+    // bulk strings may hold anything, line endings included
+    assert_eq!(send(&kv, b"*3\r\n$3\r\nSET\r\n$1\r\nc\r\n$4\r\n\r\n\0\xff\r\n"), OK);
+    assert_eq!(kv.get(b"c").unwrap(), Some(b"\r\n\0\xff".to_vec()));
+    assert_eq!(send(&kv, b"*0\r\nPING\r\n"), "+PONG\r\n");
+
+    assert_eq!(send(&kv, b"FLUSHALL\r\n"), "-ERR unknown command 'flushall'\r\n");
+    assert_eq!(send(&kv, b"GET\r\n"), "-ERR wrong number of arguments for 'get' command\r\n");
+    assert_eq!(send(&kv, b"MSET a\r\n"), "-ERR wrong number of arguments for 'mset' command\r\n");
+    // nothing after QUIT is run
+    assert_eq!(send(&kv, b"QUIT\r\nSET a 2\r\n"), OK);
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+}
+
+#[test]
+fn malformed_requests_are_answered_then_dropped() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    let error = |msg: &str| format!("-ERR Protocol error: {}\r\n", msg);
+
+    assert_eq!(send(&kv, b"*x\r\nPING\r\n"), error("invalid multibulk length"));
+    assert_eq!(send(&kv, b"*-1\r\nPING\r\n"), error("invalid multibulk length"));
+    assert_eq!(send(&kv, b"*99999999\r\n"), error("invalid multibulk length"));
+    assert_eq!(send(&kv, b"*1\r\n+PING\r\n"), error("expected '$'"));
+    assert_eq!(send(&kv, b"*1\r\n$-1\r\n"), error("invalid bulk length"));
+    assert_eq!(send(&kv, b"*1\r\n$4x\r\n"), error("invalid bulk length"));
+    assert_eq!(send(&kv, b"*1\r\n$4\r\nPINGxx\r\n"), error("bulk string not terminated"));
+    assert_eq!(send(&kv, b"*2\r\n$4\r\nPING\r\n"), error("unexpected end of stream"));
+    assert_eq!(send(&kv, &[b'a'; 70_000]), error("line too long"));
+
+    // a bulk string cut short is a broken connection, not a reply
+    let mut out = Vec::new();
+    let result = Server::new(kv.clone()).protocol(Protocol::Resp).handle(&b"*1\r\n$10\r\nPING\r\n"[..], &mut out);
+    assert!(matches!(result, Err(Error::Io(_))));
+    assert!(out.is_empty());
+}
+
+#[test]
+fn set_options() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+
+    assert_eq!(send(&kv, b"SET k 1 XX\r\n"), NIL);
+    assert_eq!(send(&kv, b"SET k 1 nx\r\n"), OK);
+    assert_eq!(send(&kv, b"SET k 2 NX\r\n"), NIL);
+    assert_eq!(send(&kv, b"SET k 3 XX\r\n"), OK);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(send(&kv, b"SET k 4 NX XX\r\n"), SYNTAX);
+    assert_eq!(send(&kv, b"SET k 4 EX\r\n"), SYNTAX);
+    assert_eq!(send(&kv, b"SET k 4 KEEPTTL\r\n"), SYNTAX);
+    assert_eq!(send(&kv, b"SET k 4 EX soon\r\n"), NOT_AN_INTEGER);
+    assert_eq!(send(&kv, b"SET k 4 EX 0\r\n"), "-ERR invalid expire time in 'set' command\r\n");
+    assert_eq!(send(&kv, b"SET k 4 PX -5\r\n"), "-ERR invalid expire time in 'set' command\r\n");
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"3".to_vec()));
+
+    assert_eq!(send(&kv, b"SET k 5 EX 100 XX\r\n"), OK);
+    assert_eq!(send(&kv, b"TTL k\r\n"), ":100\r\n");
+    assert_eq!(send(&kv, b"SET p 6 PX 30\r\n"), OK);
+    assert_eq!(send(&kv, b"TTL p\r\n"), ":1\r\n");
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(send(&kv, b"GET p\r\nTTL p\r\n"), format!("{}:-2\r\n", NIL));
+    // an expired key counts as absent
+    assert_eq!(send(&kv, b"SET p 7 XX\r\nSET p 8 NX\r\n"), format!("{}{}", NIL, OK));
+    // a plain SET drops the expiry
+    assert_eq!(send(&kv, b"SET k 9\r\nTTL k\r\n"), format!("{}:-1\r\n", OK));
+}
+
+#[test]
+fn scan_pages_with_cursors() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    let keys: Vec<String> = (0..25).map(|i| format!("key:{:02}", i)).collect();
+    for key in &keys {
+        kv.insert(key.as_bytes(), b"v").unwrap();
+    }
+    kv.insert(b"other", b"v").unwrap();
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let page = |cursor: &str, keys: &[&str]| format!("*2\r\n{}{}", bulk(cursor), array(keys));
+
+    // cursors belong to the connection, so a whole walk has to share one
+    let replies = send(&kv, b"SCAN 0 MATCH key:* COUNT 10\r\nSCAN 1 MATCH key:* COUNT 10\r\nSCAN 2 MATCH key:* COUNT 10\r\n");
+    assert_eq!(replies, [page("1", &keys[..10]), page("2", &keys[10..20]), page("0", &keys[20..])].concat());
+    assert_eq!(send(&kv, b"SCAN 1\r\n"), "-ERR invalid cursor\r\n");
+
+    // MATCH filters each page after the fact, so pages can come back short
+    let replies = send(&kv, b"SCAN 0 MATCH *:?5 COUNT 20\r\nSCAN 1 MATCH *:?5 COUNT 20\r\n");
+    assert_eq!(replies, [page("1", &["key:05", "key:15"]), page("0", &[])].concat());
+    assert_eq!(send(&kv, b"SCAN 0 COUNT 100\r\n"), page("0", &[&keys[..], &["other"]].concat()));
+    assert_eq!(send(&kv, b"SCAN 0 TYPE hash\r\n"), page("1", &[]));
+    assert_eq!(send(&kv, b"SCAN 0 MATCH other TYPE string\r\n"), page("0", &["other"]));
+
+    assert_eq!(send(&kv, b"SCAN -1\r\n"), "-ERR invalid cursor\r\n");
+    assert_eq!(send(&kv, b"SCAN x\r\n"), "-ERR invalid cursor\r\n");
+    assert_eq!(send(&kv, b"SCAN 0 COUNT 0\r\n"), SYNTAX);
+    assert_eq!(send(&kv, b"SCAN 0 COUNT many\r\n"), NOT_AN_INTEGER);
+    assert_eq!(send(&kv, b"SCAN 0 MATCH\r\n"), SYNTAX);
+    assert_eq!(send(&kv, b"SCAN 0 LIMIT 5\r\n"), SYNTAX);
+}
+
+#[test]
+fn keys_matches_glob_patterns() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    for key in ["a*b", "ab", "axb", "h?llo", "hallo", "hello", "hllo", "hzllo", "[x]", "user:1", "user:10", "user:2"] {
+        kv.insert(key.as_bytes(), b"v").unwrap();
+    }
+    let keys = |pattern: &str| send(&kv, command(&["KEYS", pattern]).as_bytes());
+
+    assert_eq!(keys("*").matches("\r\n$").count(), 12);
+    assert_eq!(keys("user:*"), array(&["user:1", "user:10", "user:2"]));
+    assert_eq!(keys("user:?"), array(&["user:1", "user:2"]));
+    assert_eq!(keys("user:1*"), array(&["user:1", "user:10"]));
+    assert_eq!(keys("*:1*0"), array(&["user:10"]));
+    assert_eq!(keys("h?llo"), array(&["h?llo", "hallo", "hello", "hzllo"]));
+    assert_eq!(keys("h\\?llo"), array(&["h?llo"]));
+    assert_eq!(keys("h[ae]llo"), array(&["hallo", "hello"]));
+    assert_eq!(keys("h[^ae]llo"), array(&["h?llo", "hzllo"]));
+    assert_eq!(keys("h[z-a]llo"), array(&["hallo", "hello", "hzllo"]));
+    assert_eq!(keys("h*llo"), array(&["h?llo", "hallo", "hello", "hllo", "hzllo"]));
+    assert_eq!(keys("a\\*b"), array(&["a*b"]));
+    assert_eq!(keys("a*b"), array(&["a*b", "ab", "axb"]));
+    assert_eq!(keys("\\[x\\]"), array(&["[x]"]));
+    assert_eq!(keys("[[]x]"), array(&["[x]"]));
+    // an unclosed class runs to the end of the pattern
+    assert_eq!(keys("user:[12"), array(&["user:1", "user:2"]));
+    assert_eq!(keys("**1"), array(&["user:1"]));
+    assert_eq!(keys("nothing*"), "*0\r\n");
+}
+
+#[test]
+fn expire_and_ttl() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"b", b"1").unwrap();
+
+    assert_eq!(send(&kv, b"TTL a\r\nTTL missing\r\n"), ":-1\r\n:-2\r\n");
+    assert_eq!(send(&kv, b"EXPIRE a 100\r\nTTL a\r\n"), ":1\r\n:100\r\n");
+    assert_eq!(send(&kv, b"EXPIRE missing 100\r\n"), ":0\r\n");
+    assert_eq!(send(&kv, b"EXPIRE a soon\r\n"), NOT_AN_INTEGER);
+    // a deadline that has passed deletes the key
+    assert_eq!(send(&kv, b"EXPIRE a 0\r\nEXPIRE b -5\r\nEXPIRE missing 0\r\n"), ":1\r\n:1\r\n:0\r\n");
+    assert_eq!(kv.get(b"a").unwrap(), None);
+    assert_eq!(kv.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn multi_key_commands() {
+    let dir = TempDir::new();
+    let kv = store(&dir);
+
+    assert_eq!(send(&kv, b"MSET a 1 b 2\r\nMGET a missing b\r\n"), format!("{}*3\r\n{}{}{}", OK, bulk("1"), NIL, bulk("2")));
+    assert_eq!(send(&kv, b"EXISTS a a missing b\r\n"), ":3\r\n");
+    // a key named twice is deleted once
+    assert_eq!(send(&kv, b"DEL a a missing\r\nEXISTS a b\r\n"), ":1\r\n:1\r\n");
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::{net::TcpListener, thread};
+
+use common::{open, TempDir};
+use kstore::{client::Client, server::Server, Error, SharedKv};
+
+#[test]
+fn scan_pages_are_capped() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    for i in 0..1500u32 {
+        kv.insert(&i.to_be_bytes(), b"v").unwrap();
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(kv);
+    thread::spawn(move || server.serve(listener));
+
+    let mut client = Client::connect(addr).unwrap();
+    let page = client.scan(b"", None, u32::MAX).unwrap();
+    assert_eq!(page.pairs.len(), 1000);
+    let next = page.next.unwrap();
+    assert_eq!(next, 999u32.to_be_bytes());
+
+    let page = client.scan(b"", Some(&next), u32::MAX).unwrap();
+    assert_eq!(page.pairs.len(), 500);
+    assert_eq!(page.next, None);
+}
+
+#[test]
+fn scan_limit_of_zero_is_an_error() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(b"a", b"1").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(kv);
+    thread::spawn(move || server.serve(listener));
+
+    let mut client = Client::connect(addr).unwrap();
+    assert!(matches!(client.scan(b"", None, 0), Err(Error::Server(_))));
+    assert_eq!(client.scan(b"", None, 1).unwrap().pairs.len(), 1);
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc, Barrier}, thread};
+
+use common::{open, TempDir};
+use kstore::{SharedKv, WriteBatch};
+
+const KEYS: u32 = 20;
+const READERS: usize = 4;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key-{:02}", i).into_bytes()
+}
+
+// long enough that a half-written record would show, and says which key
+// and round it was written for
+fn value(i: u32, round: u32) -> Vec<u8> {
+    format!("{:02}:{:04}:{}", i, round, "x".repeat((round % 50) as usize)).into_bytes()
+}
+
+fn round_of(i: u32, value: &[u8]) -> u32 {
+    let value = std::str::from_utf8(value).unwrap();
+    let mut parts = value.split(':');
+    assert_eq!(parts.next().unwrap().parse::<u32>().unwrap(), i, "{}", value);
+    let round = parts.next().unwrap().parse().unwrap();
+    assert_eq!(parts.next().unwrap(), "x".repeat((round % 50) as usize), "{}", value);
+    round
+}
+
+// runs `read` on READERS threads, each at least once, until `write` returns
+fn race(kv: &SharedKv, read: impl Fn(&SharedKv) + Send + Sync + 'static, write: impl FnOnce(&SharedKv)) {
+    let done = Arc::new(AtomicBool::new(false));
+    let read = Arc::new(read);
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            let (kv, done, read) = (kv.clone(), done.clone(), read.clone());
+            thread::spawn(move || loop {
+                read(&kv);
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+            })
+        })
+        .collect();
+    write(kv);
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
+
+#[test]
+fn handle_is_send_and_sync() {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<SharedKv>();
+}
+
+#[test]
+fn readers_share_the_lock() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(b"k", b"v").unwrap();
+
+    // every reader holds its guard until all of them have one, which would
+    // never happen if reads took the lock in turn
+    let barrier = Arc::new(Barrier::new(READERS));
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            let (kv, barrier) = (kv.clone(), barrier.clone());
+            thread::spawn(move || {
+                let guard = kv.read();
+                barrier.wait();
+                guard.get(b"k").unwrap()
+            })
+        })
+        .collect();
+    for reader in readers {
+        assert_eq!(reader.join().unwrap(), Some(b"v".to_vec()));
+    }
+}
+
+#[test]
+fn readers_see_whole_records_while_a_writer_appends() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    for i in 0..KEYS {
+        kv.insert(&key(i), &value(i, 0)).unwrap();
+    }
+
+    let read = |kv: &SharedKv| {
+        for i in 0..KEYS {
+            let (value, version) = kv.get_versioned(&key(i)).unwrap().unwrap();
+            round_of(i, &value);
+            assert!(kv.version(&key(i)).unwrap() >= version);
+        }
+    };
+    race(&kv, read, |kv| {
+        for round in 1..=200 {
+            for i in 0..KEYS {
+                kv.insert(&key(i), &value(i, round)).unwrap();
+            }
+        }
+    });
+
+    for i in 0..KEYS {
+        assert_eq!(kv.get(&key(i)).unwrap(), Some(value(i, 200)));
+    }
+}
+
+#[test]
+fn a_reader_never_goes_back_in_time() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(&key(0), &value(0, 0)).unwrap();
+
+    let read = |kv: &SharedKv| {
+        let mut last = 0;
+        for _ in 0..100 {
+            let round = round_of(0, &kv.get(&key(0)).unwrap().unwrap());
+            assert!(round >= last, "{} after {}", round, last);
+            last = round;
+        }
+    };
+    race(&kv, read, |kv| {
+        for round in 1..=1000 {
+            kv.insert(&key(0), &value(0, round)).unwrap();
+        }
+    });
+}
+
+#[test]
+fn readers_never_see_half_a_batch() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    let mut batch = WriteBatch::new();
+    for i in 0..KEYS {
+        batch.insert(&key(i), &value(i, 0));
+    }
+    kv.write_batch(&batch).unwrap();
+
+    // one guard for the whole pass, so every key comes from the same state
+    let read = |kv: &SharedKv| {
+        let kv = kv.read();
+        let rounds: Vec<_> = (0..KEYS).map(|i| round_of(i, &kv.get(&key(i)).unwrap().unwrap())).collect();
+        assert!(rounds.iter().all(|&round| round == rounds[0]), "{:?}", rounds);
+    };
+    race(&kv, read, |kv| {
+        for round in 1..=100 {
+            let mut batch = WriteBatch::new();
+            for i in 0..KEYS {
+                batch.insert(&key(i), &value(i, round));
+            }
+            kv.write_batch(&batch).unwrap();
+        }
+    });
+}
+
+#[test]
+fn reads_carry_on_through_compaction() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    for i in 0..KEYS {
+        kv.insert(&key(i), &value(i, 0)).unwrap();
+    }
+
+    let read = |kv: &SharedKv| {
+        for i in 0..KEYS {
+            round_of(i, &kv.get(&key(i)).unwrap().unwrap());
+        }
+    };
+    race(&kv, read, |kv| {
+        for round in 1..=20 {
+            for i in 0..KEYS {
+                kv.insert(&key(i), &value(i, round)).unwrap();
+            }
+            kv.compact().unwrap();
+        }
+    });
+
+    for i in 0..KEYS {
+        assert_eq!(kv.get(&key(i)).unwrap(), Some(value(i, 20)));
+    }
+    assert_eq!(kv.len(), KEYS as usize);
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{open, open_segmented, TempDir};
+use kstore::{ActionKvOptions, CompactionPolicy, SharedKv};
+
+#[test]
+fn snapshot_ignores_later_writes() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(b"a", b"1").unwrap();
+    kv.insert(b"b", b"2").unwrap();
+
+    let snapshot = kv.snapshot();
+    kv.insert(b"a", b"9").unwrap();
+    kv.delete(b"b").unwrap();
+    kv.insert(b"c", b"3").unwrap();
+
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(snapshot.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(snapshot.get(b"c").unwrap(), None);
+    let pairs: Vec<_> = snapshot.iter().unwrap().map(|pair| pair.unwrap()).collect();
+    assert_eq!(pairs, vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec())]);
+}
+
+#[test]
+fn snapshot_survives_compaction_and_merge() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open_segmented(&dir.join("kv"), 256));
+    for i in 0..20u32 {
+        kv.insert(&i.to_le_bytes(), b"old").unwrap();
+    }
+    let snapshot = kv.snapshot();
+    for i in 0..20u32 {
+        if i % 2 == 0 {
+            kv.delete(&i.to_le_bytes()).unwrap();
+        } else {
+            kv.insert(&i.to_le_bytes(), b"new").unwrap();
+        }
+    }
+    kv.merge().unwrap();
+    kv.compact().unwrap();
+    kv.merge().unwrap();
+
+    for i in 0..20u32 {
+        assert_eq!(snapshot.get(&i.to_le_bytes()).unwrap(), Some(b"old".to_vec()));
+        let current = if i % 2 == 0 { None } else { Some(b"new".to_vec()) };
+        assert_eq!(kv.get(&i.to_le_bytes()).unwrap(), current);
+    }
+    assert_eq!(snapshot.iter().unwrap().count(), 20);
+
+    drop(snapshot);
+    kv.compact().unwrap();
+    assert_eq!(kv.history(&0u32.to_le_bytes()).unwrap().len(), 0);
+    assert_eq!(kv.history(&1u32.to_le_bytes()).unwrap().len(), 1);
+}
+
+#[test]
+fn snapshot_reads_stay_consistent_during_merges() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open_segmented(&dir.join("kv"), 1024));
+    for i in 0..10u32 {
+        kv.insert(&i.to_le_bytes(), &0u32.to_le_bytes()).unwrap();
+    }
+    let snapshot = kv.snapshot();
+    let writer = {
+        let kv = kv.clone();
+        thread::spawn(move || {
+            for round in 1..50u32 {
+                for i in 0..10u32 {
+                    kv.insert(&i.to_le_bytes(), &round.to_le_bytes()).unwrap();
+                }
+            }
+        })
+    };
+    let merger = {
+        let kv = kv.clone();
+        thread::spawn(move || {
+            for _ in 0..10 {
+                kv.merge().unwrap();
+            }
+        })
+    };
+    for _ in 0..50 {
+        let values: Vec<_> = snapshot.iter().unwrap().map(|pair| pair.unwrap().1).collect();
+        assert_eq!(values, vec![0u32.to_le_bytes().to_vec(); 10]);
+    }
+    writer.join().unwrap();
+    merger.join().unwrap();
+}
+
+#[test]
+fn pinned_records_do_not_keep_compaction_due() {
+    let dir = TempDir::new();
+    let policy = CompactionPolicy { garbage_ratio: Some(0.1), dead_bytes: None, check_interval: Duration::from_secs(3600) };
+    let kv = ActionKvOptions::new()
+        .create_if_missing(true)
+        .segmented(true)
+        .background_compaction(Some(policy))
+        .open(&dir.join("kv"))
+        .unwrap();
+    let kv = SharedKv::new(kv);
+    for i in 0..20u32 {
+        kv.insert(&i.to_le_bytes(), b"old").unwrap();
+    }
+    let snapshot = kv.snapshot();
+    for i in 0..20u32 {
+        kv.insert(&i.to_le_bytes(), b"new").unwrap();
+    }
+    assert!(kv.read().compaction_due().unwrap());
+
+    kv.merge().unwrap();
+    assert!(!kv.read().compaction_due().unwrap());
+    assert_eq!(snapshot.get(&0u32.to_le_bytes()).unwrap(), Some(b"old".to_vec()));
+}
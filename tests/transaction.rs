@@ -0,0 +1,181 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{open, TempDir};
+use kstore::{Error, SharedKv};
+
+#[test]
+fn write_between_get_and_commit_conflicts() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(b"a", b"1").unwrap();
+
+    let mut tx = kv.begin();
+    assert_eq!(tx.get(b"a").unwrap(), Some(b"1".to_vec()));
+    tx.insert(b"b", b"2");
+    kv.insert(b"a", b"9").unwrap();
+
+    assert!(matches!(tx.commit(), Err(Error::Conflict { .. })));
+    assert_eq!(kv.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn delete_and_reinsert_between_get_and_commit_conflicts() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(b"a", b"1").unwrap();
+
+    let mut tx = kv.begin();
+    tx.get(b"a").unwrap();
+    tx.insert(b"a", b"2");
+    kv.delete(b"a").unwrap();
+    kv.insert(b"a", b"1").unwrap();
+
+    assert!(matches!(tx.commit(), Err(Error::Conflict { .. })));
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+}
+
+#[test]
+fn insert_of_a_key_read_as_absent_conflicts() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+
+    let mut tx = kv.begin();
+    assert_eq!(tx.get(b"a").unwrap(), None);
+    tx.insert(b"a", b"tx");
+    kv.insert(b"a", b"other").unwrap();
+
+    assert!(matches!(tx.commit(), Err(Error::Conflict { .. })));
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"other".to_vec()));
+}
+
+#[test]
+fn key_written_and_deleted_again_after_an_absent_read_conflicts() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+
+    let mut tx = kv.begin();
+    assert_eq!(tx.get(b"a").unwrap(), None);
+    tx.insert(b"b", b"tx");
+    // back to where the transaction saw it, but not untouched
+    kv.insert(b"a", b"other").unwrap();
+    kv.delete(b"a").unwrap();
+
+    assert!(matches!(tx.commit(), Err(Error::Conflict { .. })));
+    assert_eq!(kv.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn key_written_and_expired_after_an_absent_read_conflicts() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert_with_ttl(b"a", b"old", Duration::from_millis(1)).unwrap();
+    thread::sleep(Duration::from_millis(10));
+
+    let mut tx = kv.begin();
+    assert_eq!(tx.get(b"a").unwrap(), None);
+    tx.insert(b"b", b"tx");
+    kv.insert_with_ttl(b"a", b"other", Duration::from_millis(1)).unwrap();
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(kv.get(b"a").unwrap(), None);
+
+    assert!(matches!(tx.commit(), Err(Error::Conflict { .. })));
+}
+
+#[test]
+fn absent_read_commits_when_nothing_was_deleted() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(b"c", b"1").unwrap();
+
+    let mut tx = kv.begin();
+    assert_eq!(tx.get(b"a").unwrap(), None);
+    tx.insert(b"a", b"tx");
+    kv.insert(b"c", b"2").unwrap();
+    kv.delete(b"never there").unwrap();
+    tx.commit().unwrap();
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"tx".to_vec()));
+
+    // a delete of any other key is taken as a possible write and delete of
+    // this one, so the transaction has to retry
+    let mut tx = kv.begin();
+    assert_eq!(tx.get(b"b").unwrap(), None);
+    tx.insert(b"b", b"tx");
+    kv.delete(b"c").unwrap();
+    assert!(matches!(tx.commit(), Err(Error::Conflict { .. })));
+}
+
+#[test]
+fn commit_applies_every_write() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let kv = SharedKv::new(open(&path));
+    kv.insert(b"a", b"1").unwrap();
+
+    let mut tx = kv.begin();
+    tx.get(b"a").unwrap();
+    tx.insert(b"a", b"2").insert(b"b", b"3").delete(b"c");
+    assert_eq!(tx.get(b"b").unwrap(), Some(b"3".to_vec()));
+    tx.commit().unwrap();
+    kv.close().unwrap();
+
+    let kv = open(&path);
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(kv.get(b"b").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn abort_leaves_the_log_unchanged() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert(b"a", b"1").unwrap();
+    let size = kv.read().disk_size().unwrap();
+
+    let mut tx = kv.begin();
+    tx.get(b"a").unwrap();
+    tx.insert(b"a", b"2").insert(b"b", b"3");
+    tx.abort();
+    let mut dropped = kv.begin();
+    dropped.insert(b"c", b"4");
+    drop(dropped);
+
+    assert_eq!(kv.read().disk_size().unwrap(), size);
+    assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(kv.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn concurrent_transfers_keep_the_total() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    for account in 0..5u8 {
+        kv.insert(&[account], b"100").unwrap();
+    }
+    let threads: Vec<_> = (0..4u8)
+        .map(|thread| {
+            let kv = kv.clone();
+            std::thread::spawn(move || {
+                for i in 0..50u8 {
+                    let (from, to) = ((thread + i) % 5, (thread + i + 1) % 5);
+                    loop {
+                        let mut tx = kv.begin();
+                        let balance = |value: Option<Vec<u8>>| String::from_utf8(value.unwrap()).unwrap().parse::<i64>().unwrap();
+                        let a = balance(tx.get(&[from]).unwrap());
+                        let b = balance(tx.get(&[to]).unwrap());
+                        tx.insert(&[from], (a - 1).to_string().as_bytes()).insert(&[to], (b + 1).to_string().as_bytes());
+                        match tx.commit() {
+                            Err(Error::Conflict { .. }) => continue,
+                            result => break result.unwrap(),
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let total: i64 = (0..5u8).map(|account| String::from_utf8(kv.get(&[account]).unwrap().unwrap()).unwrap().parse::<i64>().unwrap()).sum();
+    assert_eq!(total, 500);
+}
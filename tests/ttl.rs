@@ -0,0 +1,83 @@
+mod common;
+
+use std::{fs, thread, time::Duration};
+
+use common::{open, TempDir};
+use kstore::{ActionKvOptions, SharedKv};
+
+#[test]
+fn expiry_survives_reopen() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert_with_ttl(b"short", b"1", Duration::from_millis(50)).unwrap();
+    kv.insert_with_ttl(b"long", b"2", Duration::from_secs(3600)).unwrap();
+    kv.insert(b"forever", b"3").unwrap();
+    kv.close().unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+
+    // with and without the hint file
+    for hint in [true, false] {
+        let mut kv = if hint { open(&path) } else { ActionKvOptions::new().hint_every(None).open(&path).unwrap() };
+        assert_eq!(kv.get(b"short").unwrap(), None);
+        assert!(!kv.contains_key(b"short"));
+        assert_eq!(kv.get(b"long").unwrap(), Some(b"2".to_vec()));
+        let left = kv.ttl(b"long").unwrap().unwrap();
+        assert!(left <= Duration::from_secs(3600) && left > Duration::from_secs(3500));
+        assert_eq!(kv.ttl(b"forever").unwrap(), None);
+        kv.close().unwrap();
+        fs::remove_file(dir.join("kv.hint")).ok();
+    }
+}
+
+#[test]
+fn compaction_drops_expired_records() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert_with_ttl(b"a", b"1", Duration::from_millis(20)).unwrap();
+    kv.insert(b"b", b"2").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    kv.compact().unwrap();
+    kv.close().unwrap();
+    fs::remove_file(dir.join("kv.hint")).unwrap();
+
+    let kv = open(&path);
+    assert_eq!(kv.get(b"a").unwrap(), None);
+    assert_eq!(kv.load_report().records, 1);
+}
+
+#[test]
+fn plain_insert_makes_a_key_permanent() {
+    let dir = TempDir::new();
+    let path = dir.join("kv");
+    let mut kv = open(&path);
+    kv.insert_with_ttl(b"k", b"1", Duration::from_millis(20)).unwrap();
+    kv.insert(b"k", b"2").unwrap();
+    kv.close().unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    let mut kv = open(&path);
+    assert_eq!(kv.get(b"k").unwrap(), Some(b"2".to_vec()));
+    assert!(kv.expire(b"k", Duration::from_millis(20)).unwrap());
+    assert!(!kv.expire(b"missing", Duration::from_secs(1)).unwrap());
+    kv.close().unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    assert_eq!(open(&path).get(b"k").unwrap(), None);
+}
+
+#[test]
+fn len_skips_expired_keys() {
+    let dir = TempDir::new();
+    let kv = SharedKv::new(open(&dir.join("kv")));
+    kv.insert_with_ttl(b"a", b"1", Duration::from_millis(20)).unwrap();
+    kv.insert(b"b", b"2").unwrap();
+    assert_eq!(kv.len(), 2);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(kv.len(), 1);
+
+    kv.delete(b"b").unwrap();
+    assert!(kv.is_empty());
+}